@@ -48,11 +48,13 @@ AS (SELECT DISTINCT
     intersections.transition_to_way AS transition_to_way,
     roads.tags AS way_tags,
     roads2.tags AS transition_to_way_tags,
-    restrictions.tags AS restriction_tags
+    restrictions.tags AS restriction_tags,
+    junctions.tags AS node_tags
     FROM roads
     LEFT JOIN edge_intersections AS intersections ON roads.way_id = intersections.way_id
     LEFT JOIN roads as roads2 on intersections.transition_to_way = roads2.way_id
     LEFT JOIN restrictions ON restrictions.from = intersections.way_id AND restrictions.to = intersections.transition_to_way
+    LEFT JOIN junctions ON ST_Intersects(junctions.geom, intersections.geom)
     WHERE ST_Length(intersections.geom) = 0 -- Fixes: error returned from database: Splitter line has linear intersection with input
 );
 
@@ -64,6 +66,7 @@ CREATE TABLE intersections (
     distance_along_way REAL,
     transition_to_distance_along_way REAL,
     restriction_tags JSONB,
+    node_tags JSONB,
     geom GEOMETRY(Point, 4326)
 );
 
@@ -74,6 +77,7 @@ INSERT INTO intersections (
     distance_along_way,
     transition_to_distance_along_way,
     restriction_tags,
+    node_tags,
     geom
 )
 SELECT
@@ -82,6 +86,7 @@ SELECT
     distance_along_way,
     transition_to_distance_along_way,
     restriction_tags,
+    node_tags,
     intersection_geom
 FROM edge_transitions;
 
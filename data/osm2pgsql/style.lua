@@ -19,6 +19,13 @@ tables.node_poi = osm2pgsql.define_node_table('node_poi', {
     { column = 'geom', type = 'point', projection = srid, not_null = true },
 })
 
+-- Nodes with tags that matter to travel through them, joined onto the intersections at the same
+-- place in post_run.sql.
+tables.junctions = osm2pgsql.define_node_table('junctions', {
+    { column = 'tags', type = 'jsonb' },
+    { column = 'geom', type = 'point', projection = srid, not_null = true },
+})
+
 tables.restrictions = osm2pgsql.define_table({
     name = 'pois',
     ids = { type = 'relation', id_column = 'relation_id' },
@@ -235,10 +242,32 @@ local function is_poi(tags)
     return (tags['addr:street'] and tags['addr:housenumber']) or tags.amenity or tags.shop or tags.leisure or tags.office or tags.tourism or tags.natural or tags.healthcare or tags.emergency or tags.craft
 end
 
+-- The node tags costing models look at where ways meet: kerbs and barriers in the way, and the
+-- signals, signs and crossings that hold traffic up.
+local junction_keys = { 'barrier', 'crossing', 'highway', 'kerb', 'kerb:height', 'traffic_signals' }
+
+local function junction_tags(tags)
+    local kept = nil
+    for _, key in ipairs(junction_keys) do
+        if tags[key] then
+            kept = kept or {}
+            kept[key] = tags[key]
+        end
+    end
+    return kept
+end
+
 function osm2pgsql.process_node(object)
     if clean_tags(object.tags) then
         return
     end
+    local junction = junction_tags(object.tags)
+    if junction then
+        tables.junctions:insert({
+            tags = junction,
+            geom = object:as_point()
+        })
+    end
     if is_poi(object.tags) then
         local row = {
             tags = object.tags,
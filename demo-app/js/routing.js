@@ -28,11 +28,12 @@ const process_event = debounce(async (event) => {
     for (let y = Math.min(start_tile_y, finish_tile_y); y <= Math.max(start_tile_y, finish_tile_y); y++) {
       let intersections = pm_intersections.getZxy(14, x, y)
       let roads = pm_roads.getZxy(14, x, y)
-      ingest_tile(x, y, 14, new Uint8Array((await roads).data), new Uint8Array((await intersections).data), costing_model.cost_intersection, costing_model.cost_way);
+      ingest_tile(x, y, 14, new Uint8Array((await roads).data), new Uint8Array((await intersections).data));
     }
   }
 
-  const encoded = search(start.lng, start.lat, finish.lng, finish.lat);
+  // The model's source names it, so costs are only shared between searches with the same model.
+  const encoded = search(start.lng, start.lat, finish.lng, finish.lat, event.data.costing_model, costing_model.cost_intersection, costing_model.cost_way);
   if (encoded) {
     postMessage(toGeoJSON(encoded));
   } else {
@@ -1,7 +1,7 @@
 use mvtr::{
     costing::{
-        CostingModel, TransitionCostResult, TransitionToCost, WayCoster,
-        units::{PartsPerMillion, TravelSpeed},
+        CostingModel, TransitionCostResult, TransitionToCost, Turn, WayCoster,
+        units::{Direction, PartsPerMillion, TravelSpeed},
     },
     graph::{Graph, WayId},
 };
@@ -14,6 +14,7 @@ use wasm_bindgen::prelude::*;
 use web_sys::console;
 
 struct JsCostingModel<'a> {
+    name: &'a str,
     cost_intersection: &'a js_sys::Function,
     cost_way: &'a js_sys::Function,
 }
@@ -25,6 +26,12 @@ struct IntersectionCostInput {
     to_way_tags: HashMap<String, String>,
     to_way_id: WayId,
     intersection_tags: HashMap<String, String>,
+    entry_bearing_forward: Option<f64>,
+    entry_bearing_reverse: Option<f64>,
+    exit_bearing_forward: Option<f64>,
+    exit_bearing_reverse: Option<f64>,
+    turns: Vec<Turn>,
+    other_legs: Option<u32>,
 }
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct IntersectionCostOutputLine {
@@ -46,6 +53,10 @@ struct JsWayCoster {
 }
 
 impl<'a> CostingModel for JsCostingModel<'a> {
+    fn name(&self) -> Option<&str> {
+        Some(self.name)
+    }
+
     fn cost_intersection(
         &self,
         current_way_tags: &mvtr::costing::Tags,
@@ -59,6 +70,12 @@ impl<'a> CostingModel for JsCostingModel<'a> {
                 to_way_id: transition.to_way_id(),
                 to_way_tags: transition.to_way_tags().to_hashmap(),
                 intersection_tags: transition.intersection_tags().to_hashmap(),
+                entry_bearing_forward: transition.entry_bearing(Direction::Forward),
+                entry_bearing_reverse: transition.entry_bearing(Direction::Reverse),
+                exit_bearing_forward: transition.exit_bearing(Direction::Forward),
+                exit_bearing_reverse: transition.exit_bearing(Direction::Reverse),
+                turns: transition.turns(),
+                other_legs: transition.other_legs(),
             })
             .collect();
         match (self.cost_intersection).call2(
@@ -145,18 +162,12 @@ pub fn ingest_tile(
     z: u32,
     tile_data_ways: &[u8],
     tile_data_nodes: &[u8],
-    cost_intersection: &js_sys::Function,
-    cost_way: &js_sys::Function,
 ) -> Result<(), wasm_bindgen::JsError> {
     console::log_1(&JsValue::from_str("Locking graph"));
     let graph_guard = GRAPH
         .lock()
         .map_err(|_err| JsError::new("Failed to lock mutex"))?;
     let graph = graph_guard.get_or_init(|| Graph::new());
-    let costing_model = JsCostingModel {
-        cost_intersection,
-        cost_way,
-    };
 
     console::log_1(&JsValue::from_str("Ingesting tile"));
     graph
@@ -166,7 +177,6 @@ pub fn ingest_tile(
             z,
             tile_data_ways.to_vec(),
             tile_data_nodes.to_vec(),
-            &costing_model,
         )
         .map_err(|err| JsError::new(&format!("Failed to ingest tile: {}", &err)))?;
 
@@ -192,24 +202,43 @@ pub fn search(
     from_lat: f64,
     to_lon: f64,
     to_lat: f64,
+    costing_model_name: &str,
+    cost_intersection: &js_sys::Function,
+    cost_way: &js_sys::Function,
 ) -> Result<Option<String>, wasm_bindgen::JsError> {
     let graph_guard = GRAPH
         .lock()
         .map_err(|_err| JsError::new("Failed to lock mutex"))?;
     let graph = graph_guard.get_or_init(|| Graph::new());
+    let costing_model = JsCostingModel {
+        name: costing_model_name,
+        cost_intersection,
+        cost_way,
+    };
 
     if let (Some((start_way, distance_along_start)), Some((end_way, distance_along_end))) = (
-        graph.nearest_way(&geo::Coord {
-            x: from_lon,
-            y: from_lat,
-        }),
-        graph.nearest_way(&geo::Coord {
-            x: to_lon,
-            y: to_lat,
-        }),
+        graph.nearest_way(
+            &costing_model,
+            &geo::Coord {
+                x: from_lon,
+                y: from_lat,
+            },
+        ),
+        graph.nearest_way(
+            &costing_model,
+            &geo::Coord {
+                x: to_lon,
+                y: to_lat,
+            },
+        ),
     ) {
-        let response =
-            graph.search_djikstra(start_way, distance_along_start, end_way, distance_along_end);
+        let response = graph.search_djikstra(
+            &costing_model,
+            start_way,
+            distance_along_start,
+            end_way,
+            distance_along_end,
+        );
         if response.is_none() {
             console::log_1(&JsValue::from_str("Couldn't find a way there"));
         }
@@ -0,0 +1,472 @@
+use std::collections::HashMap;
+
+use super::{
+    RoutingCost, Tags, TransitionCostResult, TransitionToCost,
+    base::{BoxedCostingModel, WayCost},
+    units::{Direction, TravelSpeed},
+};
+
+/// No way is driven faster than this, so goal-directed searches have a speed to bound by.
+const MAX_AUTO_SPEED_KMH: f64 = 130.0;
+/// How fast a ferry is taken to cross when it doesn't say.
+const FERRY_SPEED_KMH: f64 = 20.0;
+/// Extra cost, as a fraction of travel time, of ways only open to those with business there.
+const DESTINATION_ONLY_PENALTY: f64 = 2.0;
+/// Extra cost, as a fraction of travel time, of ways the driver asked to avoid.
+const AVOID_PENALTY: f64 = 4.0;
+
+/// The vehicle classes cars belong to, from most to least specific, as they appear in access,
+/// oneway and turn restriction keys.
+const CAR_CLASSES: [&str; 3] = ["motorcar", "motor_vehicle", "vehicle"];
+
+/// Tunables for `auto_costing_model`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AutoOptions {
+    avoid_tolls: bool,
+    avoid_ferries: bool,
+    avoid_highways: bool,
+}
+
+impl AutoOptions {
+    /// A driver who takes whatever way is fastest.
+    pub fn new() -> AutoOptions {
+        AutoOptions::default()
+    }
+
+    /// Avoids toll roads where there's a reasonable alternative.
+    pub fn with_avoid_tolls(mut self, avoid_tolls: bool) -> AutoOptions {
+        self.avoid_tolls = avoid_tolls;
+        self
+    }
+
+    /// Avoids ferries where there's a reasonable alternative.
+    pub fn with_avoid_ferries(mut self, avoid_ferries: bool) -> AutoOptions {
+        self.avoid_ferries = avoid_ferries;
+        self
+    }
+
+    /// Avoids motorways and trunk roads where there's a reasonable alternative.
+    pub fn with_avoid_highways(mut self, avoid_highways: bool) -> AutoOptions {
+        self.avoid_highways = avoid_highways;
+        self
+    }
+
+    fn name(&self) -> String {
+        let avoided: Vec<&str> = [
+            (self.avoid_tolls, "tolls"),
+            (self.avoid_ferries, "ferries"),
+            (self.avoid_highways, "highways"),
+        ]
+        .into_iter()
+        .filter(|(avoid, _)| *avoid)
+        .map(|(_, avoided)| avoided)
+        .collect();
+        if avoided.is_empty() {
+            String::from("auto")
+        } else {
+            format!("auto avoiding {}", avoided.join(", "))
+        }
+    }
+}
+
+pub fn auto_costing_model(options: AutoOptions) -> BoxedCostingModel {
+    BoxedCostingModel::boxed(
+        move |direction, tags| cost_way(&options, direction, tags),
+        |_tags, transitions_to_cost| cost_intersection(&CAR_CLASSES, transitions_to_cost),
+    )
+    .with_max_speed(TravelSpeed::from_kmh(MAX_AUTO_SPEED_KMH))
+    .with_name(options.name())
+}
+
+fn cost_way(options: &AutoOptions, direction: Direction, tags: &Tags) -> Option<WayCost> {
+    let is_ferry = tags.tag_is("route", "ferry");
+    let default_speed_kmh = if is_ferry {
+        FERRY_SPEED_KMH
+    } else if let Some(speed_kmh) = default_speed_kmh(tags) {
+        speed_kmh
+    } else if tags.tag_in("motorcar", &["yes", "designated"])
+        || tags.tag_in("motor_vehicle", &["yes", "designated"])
+    {
+        // Not a road, but cars are explicitly allowed on it.
+        10.0
+    } else {
+        return None;
+    };
+    if !oneway_allows(&CAR_CLASSES, direction, tags) {
+        return None;
+    }
+    let access = access(&CAR_CLASSES, tags);
+    if access == Access::Denied {
+        return None;
+    }
+
+    let mut cost = WayCost::from_speed(speed_limit(
+        direction,
+        tags,
+        default_speed_kmh,
+        MAX_AUTO_SPEED_KMH,
+    ));
+    cost.limit_speed(TravelSpeed::from_kmh(MAX_AUTO_SPEED_KMH));
+
+    if access == Access::DestinationOnly {
+        cost.add_penalty_ppm(DESTINATION_ONLY_PENALTY.into());
+    }
+    if options.avoid_tolls && (tags.tag_is("toll", "yes") || tags.tag_is("toll:motorcar", "yes")) {
+        cost.add_penalty_ppm(AVOID_PENALTY.into());
+    }
+    if options.avoid_ferries && is_ferry {
+        cost.add_penalty_ppm(AVOID_PENALTY.into());
+    }
+    if options.avoid_highways
+        && tags.tag_in(
+            "highway",
+            &["motorway", "motorway_link", "trunk", "trunk_link"],
+        )
+    {
+        cost.add_penalty_ppm(AVOID_PENALTY.into());
+    }
+    Some(cost)
+}
+
+/// Whether a vehicle may use a way, from the most specific of the way's access tags for any of
+/// `vehicle_classes`, such as `motorcar`, falling back to `access`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Access {
+    Allowed,
+    /// Only for those with business along the way, such as `private` or `delivery`.
+    DestinationOnly,
+    Denied,
+}
+
+pub(super) fn access(vehicle_classes: &[&str], tags: &Tags) -> Access {
+    let access = vehicle_classes
+        .iter()
+        .chain(["access"].iter())
+        .find_map(|key| tags.get(key));
+    match access {
+        Some("no") | Some("agricultural") | Some("forestry") | Some("emergency") | Some("bus")
+        | Some("psv") => Access::Denied,
+        Some("private") | Some("destination") | Some("customers") | Some("delivery") => {
+            Access::DestinationOnly
+        }
+        _ => Access::Allowed,
+    }
+}
+
+/// The posted speed limit along a way in `direction`, or `default_speed_kmh` if it doesn't have
+/// one. Ways without a limit are taken at `unlimited_speed_kmh`.
+pub(super) fn speed_limit(
+    direction: Direction,
+    tags: &Tags,
+    default_speed_kmh: f64,
+    unlimited_speed_kmh: f64,
+) -> TravelSpeed {
+    let maxspeed_key = match direction {
+        Direction::Forward => "maxspeed:forward",
+        Direction::Reverse => "maxspeed:backward",
+    };
+    match tags.get(maxspeed_key).or_else(|| tags.get("maxspeed")) {
+        Some("none") => TravelSpeed::from_kmh(unlimited_speed_kmh),
+        Some("walk") => TravelSpeed::from_kmh(6.0),
+        Some(maxspeed) => {
+            TravelSpeed::parse(maxspeed).unwrap_or_else(|| TravelSpeed::from_kmh(default_speed_kmh))
+        }
+        None => TravelSpeed::from_kmh(default_speed_kmh),
+    }
+}
+
+/// How fast cars typically go on a road of the way's class, when it doesn't give a `maxspeed`.
+/// `None` if cars can't use it.
+pub(super) fn default_speed_kmh(tags: &Tags) -> Option<f64> {
+    if tags.tag_is("highway", "service")
+        && tags.tag_in("service", &["parking_aisle", "driveway", "drive-through"])
+    {
+        return Some(10.0);
+    }
+    match tags.get("highway")? {
+        "motorway" => Some(105.0),
+        "trunk" => Some(85.0),
+        "primary" => Some(65.0),
+        "secondary" => Some(55.0),
+        "tertiary" => Some(45.0),
+        "motorway_link" => Some(70.0),
+        "trunk_link" => Some(55.0),
+        "primary_link" => Some(45.0),
+        "secondary_link" => Some(40.0),
+        "tertiary_link" => Some(35.0),
+        "unclassified" => Some(40.0),
+        "residential" | "road" => Some(30.0),
+        "service" | "track" => Some(15.0),
+        "living_street" => Some(10.0),
+        _ => None,
+    }
+}
+
+pub(super) fn oneway_allows(vehicle_classes: &[&str], direction: Direction, tags: &Tags) -> bool {
+    let implied_oneway =
+        tags.tag_in("junction", &["roundabout", "circular"]) || tags.tag_is("highway", "motorway");
+    let oneway = vehicle_classes
+        .iter()
+        .find_map(|class| tags.get(&format!("oneway:{class}")))
+        .or_else(|| tags.get("oneway"))
+        .unwrap_or(if implied_oneway { "yes" } else { "no" });
+    match oneway {
+        "yes" | "1" | "true" => direction == Direction::Forward,
+        "-1" | "reverse" => direction == Direction::Reverse,
+        // Which way these can be driven changes over the day, and that isn't tagged.
+        "reversible" | "alternating" => false,
+        _ => true,
+    }
+}
+
+/// The turn restriction on a transition that applies to any of `vehicle_classes`, such as
+/// `no_left_turn` or `only_straight_on`, if any.
+fn turn_restriction<'a>(vehicle_classes: &[&str], intersection_tags: &'a Tags) -> Option<&'a str> {
+    let excepted = intersection_tags.get("except").is_some_and(|except| {
+        except
+            .split(';')
+            .any(|class| vehicle_classes.contains(&class.trim()))
+    });
+    if excepted {
+        return None;
+    }
+    vehicle_classes
+        .iter()
+        .find_map(|class| intersection_tags.get(&format!("restriction:{class}")))
+        .or_else(|| intersection_tags.get("restriction"))
+}
+
+/// Every transition is free, except those a turn restriction on any of `vehicle_classes` forbids.
+/// A restriction to only one transition forbids all the others, including continuing along the
+/// same way.
+pub(super) fn cost_intersection(
+    vehicle_classes: &[&str],
+    transitions_to_cost: &[TransitionToCost],
+) -> TransitionCostResult {
+    let restricted = |transition: &TransitionToCost, kind: &str| {
+        turn_restriction(vehicle_classes, transition.intersection_tags)
+            .is_some_and(|restriction| restriction.starts_with(kind))
+    };
+    let only = transitions_to_cost
+        .iter()
+        .find(|transition| restricted(transition, "only_"));
+    let transition_costs: HashMap<_, _> = transitions_to_cost
+        .iter()
+        .filter(|transition| match only {
+            Some(only) => transition.to_way_id() == only.to_way_id(),
+            None => !restricted(transition, "no_"),
+        })
+        .map(|transition| (transition.to_way_id(), RoutingCost::zero()))
+        .collect();
+    TransitionCostResult {
+        transition_costs,
+        continue_cost: only.is_none().then(RoutingCost::zero),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::costing::{
+        CostingModel, TransitionToCost, WayCoster,
+        pedestrian::pedestrian_costing_model,
+        units::{Direction, TravelledDistance},
+    };
+    use crate::graph::{
+        WayId, WayTransition,
+        testing::{
+            CAPITOL_HILL_FROM, CAPITOL_HILL_TO_BY_ROAD, capitol_hill, route, tags, way_tags,
+        },
+    };
+
+    use super::{AutoOptions, auto_costing_model};
+
+    /// The cost equivalent, in seconds, of driving a kilometer of a way in `direction`.
+    fn cost_seconds(way_coster: &WayCoster, direction: Direction) -> Option<f64> {
+        way_coster
+            .cost_way_segment(TravelledDistance(1_000_000), direction)
+            .map(|cost| cost.elapsed_equivalent().millis() as f64 / 1000.0)
+    }
+
+    #[test]
+    fn auto_way_costs() {
+        let costing_model = auto_costing_model(AutoOptions::new());
+        let cost = |pairs: &[(&str, &str)], direction| {
+            cost_seconds(&costing_model.cost_way(&tags(pairs)), direction)
+        };
+        let close =
+            |seconds: Option<f64>, expected: f64| (seconds.unwrap() - expected).abs() < 0.01;
+
+        // 30 km/h.
+        assert!(close(
+            cost(&[("highway", "residential")], Direction::Forward),
+            120.0
+        ));
+        assert!(close(
+            cost(
+                &[("highway", "residential"), ("maxspeed", "25 mph")],
+                Direction::Forward
+            ),
+            89.478
+        ));
+        let forward_only = [
+            ("highway", "secondary"),
+            ("maxspeed", "50"),
+            ("maxspeed:forward", "30"),
+        ];
+        assert!(close(cost(&forward_only, Direction::Forward), 120.0));
+        assert!(close(cost(&forward_only, Direction::Reverse), 72.0));
+        assert!(cost(&[("highway", "footway")], Direction::Forward).is_none());
+
+        let oneway = [("highway", "residential"), ("oneway", "yes")];
+        assert!(cost(&oneway, Direction::Forward).is_some());
+        assert!(cost(&oneway, Direction::Reverse).is_none());
+        let reverse_oneway = [("highway", "residential"), ("oneway", "-1")];
+        assert!(cost(&reverse_oneway, Direction::Forward).is_none());
+        assert!(cost(&reverse_oneway, Direction::Reverse).is_some());
+        let roundabout = [("highway", "tertiary"), ("junction", "roundabout")];
+        assert!(cost(&roundabout, Direction::Reverse).is_none());
+
+        let no_cars = [("highway", "residential"), ("motor_vehicle", "no")];
+        assert!(cost(&no_cars, Direction::Forward).is_none());
+        let cars_only = [
+            ("highway", "service"),
+            ("access", "no"),
+            ("motor_vehicle", "yes"),
+        ];
+        assert!(cost(&cars_only, Direction::Forward).is_some());
+        let private = cost(
+            &[("highway", "residential"), ("access", "private")],
+            Direction::Forward,
+        )
+        .unwrap();
+        assert!(private > 120.0);
+    }
+
+    #[test]
+    fn auto_real_way_costs() {
+        let graph = capitol_hill();
+        let costing_model = auto_costing_model(AutoOptions::new());
+        let cost = |way, direction| {
+            cost_seconds(&costing_model.cost_way(&way_tags(&graph, way)), direction)
+        };
+
+        // 21st Avenue East is a one-way residential street signed at 20 mph, rather than the
+        // 30 km/h a residential street is taken to have otherwise.
+        assert_eq!(way_tags(&graph, 476409328).get("maxspeed"), Some("20 mph"));
+        assert!((cost(476409328, Direction::Forward).unwrap() - 111.847).abs() < 0.01);
+        assert_eq!(cost(476409328, Direction::Reverse), None);
+        // This stretch of East Union Street is closed to motor vehicles.
+        assert_eq!(way_tags(&graph, 337673341).get("motor_vehicle"), Some("no"));
+        assert_eq!(cost(337673341, Direction::Forward), None);
+    }
+
+    #[test]
+    fn auto_avoid_options() {
+        let toll = tags(&[("highway", "motorway"), ("toll", "yes")]);
+        let trunk = tags(&[("highway", "trunk")]);
+        let ferry = tags(&[("route", "ferry")]);
+        let costs = |options: AutoOptions| {
+            let costing_model = auto_costing_model(options);
+            [&toll, &trunk, &ferry].map(|tags| {
+                cost_seconds(&costing_model.cost_way(tags), Direction::Forward).unwrap()
+            })
+        };
+
+        let [toll_cost, trunk_cost, ferry_cost] = costs(AutoOptions::new());
+        let [avoided_toll, same_trunk, same_ferry] =
+            costs(AutoOptions::new().with_avoid_tolls(true));
+        assert!(avoided_toll > toll_cost);
+        assert_eq!((same_trunk, same_ferry), (trunk_cost, ferry_cost));
+        let [_, _, avoided_ferry] = costs(AutoOptions::new().with_avoid_ferries(true));
+        assert!(avoided_ferry > ferry_cost);
+        let [avoided_motorway, avoided_trunk, _] =
+            costs(AutoOptions::new().with_avoid_highways(true));
+        assert!(avoided_motorway > toll_cost && avoided_trunk > trunk_cost);
+
+        assert_ne!(
+            auto_costing_model(AutoOptions::new()).name(),
+            auto_costing_model(AutoOptions::new().with_avoid_tolls(true)).name()
+        );
+    }
+
+    #[test]
+    fn auto_turn_restrictions() {
+        let costing_model = auto_costing_model(AutoOptions::new());
+        let road = tags(&[("highway", "residential")]);
+        let unrestricted = tags(&[]);
+        // Transitions from way 1 onto ways 2 and 3, the first with `restriction`.
+        let allowed = |restriction: &[(&str, &str)]| {
+            let restriction = tags(restriction);
+            let transitions: Vec<TransitionToCost> = [(2, &restriction), (3, &unrestricted)]
+                .into_iter()
+                .map(|(to_way, intersection_tags)| TransitionToCost {
+                    way_transition: WayTransition::new(
+                        WayId::from_id(1),
+                        0,
+                        WayId::from_id(to_way),
+                        0,
+                    ),
+                    from_way_tags: &road,
+                    to_way_tags: &road,
+                    intersection_tags,
+                    geometry: None,
+                })
+                .collect();
+            let result = costing_model.cost_intersection(&road, &transitions);
+            let mut allowed: Vec<u64> = [2, 3]
+                .into_iter()
+                .filter(|way| result.transition_costs.contains_key(&WayId::from_id(*way)))
+                .collect();
+            if result.continue_cost.is_some() {
+                allowed.push(1);
+            }
+            allowed
+        };
+
+        assert_eq!(allowed(&[]), vec![2, 3, 1]);
+        assert_eq!(
+            allowed(&[("type", "restriction"), ("restriction", "no_left_turn")]),
+            vec![3, 1]
+        );
+        assert_eq!(
+            allowed(&[("type", "restriction"), ("restriction", "only_right_turn")]),
+            vec![2]
+        );
+        assert_eq!(
+            allowed(&[("type", "restriction"), ("restriction:hgv", "no_left_turn")]),
+            vec![2, 3, 1]
+        );
+        assert_eq!(
+            allowed(&[
+                ("type", "restriction"),
+                ("restriction", "no_left_turn"),
+                ("except", "motorcar")
+            ]),
+            vec![2, 3, 1]
+        );
+    }
+
+    #[test]
+    fn auto_route_keeps_to_roads() {
+        let graph = capitol_hill();
+        let driving = route(
+            &graph,
+            &auto_costing_model(AutoOptions::new()),
+            &CAPITOL_HILL_FROM,
+            &CAPITOL_HILL_TO_BY_ROAD,
+        );
+        let walking = route(
+            &graph,
+            &pedestrian_costing_model(1.4),
+            &CAPITOL_HILL_FROM,
+            &CAPITOL_HILL_TO_BY_ROAD,
+        );
+        // The trip starts on a sidewalk, but cars set off from the nearest road.
+        assert!(walking.uses("highway", &["footway"]));
+        assert!(!driving.uses(
+            "highway",
+            &["footway", "path", "steps", "cycleway", "pedestrian"]
+        ));
+        assert!(driving.result.route_duration_seconds() < walking.result.route_duration_seconds());
+    }
+}
@@ -25,6 +25,8 @@ impl WayCost {
         self.penalty_ppm = self.penalty_ppm + penalty;
     }
 
+    /// Adds a penalty charged once when a route turns onto the way and sets off along it in the
+    /// direction being costed, however far it then goes.
     pub fn add_flat_penalty(&mut self, flat_penalty: ElapsedTime) {
         self.flat_penalty = self.flat_penalty + flat_penalty;
     }
@@ -36,8 +38,17 @@ pub struct BaseCostingModel<
 > {
     speed_fn: CostWayFn,
     intersection_fn: IntersectionFn,
+    max_speed: Option<TravelSpeed>,
+    name: Option<String>,
 }
 
+/// A `BaseCostingModel` with its functions boxed, so the models built from options have a type
+/// they can be named by.
+pub type BoxedCostingModel = BaseCostingModel<
+    Box<dyn Fn(Direction, &Tags) -> Option<WayCost> + Send + Sync>,
+    Box<dyn Fn(&Tags, &[TransitionToCost]) -> TransitionCostResult + Send + Sync>,
+>;
+
 impl<
     CostWayFn: Fn(Direction, &Tags) -> Option<WayCost>,
     IntersectionFn: Fn(&Tags, &[TransitionToCost]) -> TransitionCostResult,
@@ -50,8 +61,36 @@ impl<
         BaseCostingModel {
             speed_fn,
             intersection_fn,
+            max_speed: None,
+            name: None,
         }
     }
+
+    pub fn with_max_speed(mut self, max_speed: TravelSpeed) -> Self {
+        self.max_speed = Some(max_speed);
+        self
+    }
+
+    /// Names the model, so searches with it keep the costs they work out and share them with any
+    /// other model of the same name that costs alike. The name has to change with anything that
+    /// changes the costs.
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+}
+
+impl BoxedCostingModel {
+    /// Like `BaseCostingModel::new`, boxing the functions.
+    pub fn boxed(
+        speed_fn: impl Fn(Direction, &Tags) -> Option<WayCost> + Send + Sync + 'static,
+        intersection_fn: impl Fn(&Tags, &[TransitionToCost]) -> TransitionCostResult
+        + Send
+        + Sync
+        + 'static,
+    ) -> BoxedCostingModel {
+        BaseCostingModel::new(Box::new(speed_fn), Box::new(intersection_fn))
+    }
 }
 
 impl<
@@ -73,8 +112,20 @@ impl<
         WayCoster {
             speed_forward: way_cost_forward.as_ref().map(|wc| wc.speed),
             speed_reverse: way_cost_reverse.as_ref().map(|wc| wc.speed),
-            penalty_ppm_forward: way_cost_forward.map(|wc| wc.penalty_ppm),
-            penalty_ppm_reverse: way_cost_reverse.map(|wc| wc.penalty_ppm),
+            penalty_ppm_forward: way_cost_forward.as_ref().map(|wc| wc.penalty_ppm),
+            penalty_ppm_reverse: way_cost_reverse.as_ref().map(|wc| wc.penalty_ppm),
+            entry_penalty_forward: way_cost_forward
+                .map_or_else(ElapsedTime::zero, |wc| wc.flat_penalty),
+            entry_penalty_reverse: way_cost_reverse
+                .map_or_else(ElapsedTime::zero, |wc| wc.flat_penalty),
         }
     }
+
+    fn max_speed(&self) -> Option<TravelSpeed> {
+        self.max_speed
+    }
+
+    fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
 }
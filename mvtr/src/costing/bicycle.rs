@@ -0,0 +1,413 @@
+use crate::graph::WayTransition;
+
+use super::{
+    Tags, TransitionCostResult,
+    base::{BoxedCostingModel, WayCost},
+    units::{Direction, TravelSpeed},
+};
+
+/// How fast a cyclist walks alongside their bike where riding isn't allowed.
+const DISMOUNTED_SPEED_M_S: f64 = 1.2;
+/// How fast a cyclist carries or wheels their bike up or down steps.
+const STEPS_SPEED_M_S: f64 = 0.5;
+
+const UNPAVED_SURFACES: [&str; 14] = [
+    "unpaved",
+    "compacted",
+    "fine_gravel",
+    "gravel",
+    "pebblestone",
+    "dirt",
+    "earth",
+    "ground",
+    "grass",
+    "grass_paver",
+    "mud",
+    "sand",
+    "woodchips",
+    "rock",
+];
+
+/// Tunables for `bicycle_costing_model`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BicycleOptions {
+    cyclist_speed_m_s: f64,
+    stress_tolerance: f64,
+    avoid_unpaved: f64,
+}
+
+impl BicycleOptions {
+    /// A cyclist who rides at `cyclist_speed_m_s` on smooth pavement, is moderately tolerant of
+    /// traffic and doesn't mind unpaved ways.
+    pub fn new(cyclist_speed_m_s: f64) -> BicycleOptions {
+        BicycleOptions {
+            cyclist_speed_m_s,
+            stress_tolerance: 0.5,
+            avoid_unpaved: 0.0,
+        }
+    }
+
+    /// How readily the cyclist rides on busy roads, from 0, avoiding them wherever there's a
+    /// quieter way, to 1, riding them as readily as any other road.
+    pub fn with_stress_tolerance(mut self, stress_tolerance: f64) -> BicycleOptions {
+        self.stress_tolerance = stress_tolerance.clamp(0.0, 1.0);
+        self
+    }
+
+    /// How strongly the cyclist avoids unpaved ways, from 0, not at all, to 1, never riding them.
+    pub fn with_avoid_unpaved(mut self, avoid_unpaved: f64) -> BicycleOptions {
+        self.avoid_unpaved = avoid_unpaved.clamp(0.0, 1.0);
+        self
+    }
+}
+
+pub fn bicycle_costing_model(options: BicycleOptions) -> BoxedCostingModel {
+    BoxedCostingModel::boxed(
+        move |direction, tags| cost_way(&options, direction, tags),
+        |_tags, transitions_to_cost| {
+            let transitions: Vec<WayTransition> = transitions_to_cost
+                .iter()
+                .map(|transition_to_cost| transition_to_cost.way_transition)
+                .collect();
+            TransitionCostResult::zero(&transitions)
+        },
+    )
+    .with_max_speed(TravelSpeed::from_meters_per_second(
+        options.cyclist_speed_m_s.max(STEPS_SPEED_M_S),
+    ))
+    .with_name(format!(
+        "bicycle {} m/s, stress tolerance {}, avoid unpaved {}",
+        options.cyclist_speed_m_s, options.stress_tolerance, options.avoid_unpaved
+    ))
+}
+
+fn cost_way(options: &BicycleOptions, direction: Direction, tags: &Tags) -> Option<WayCost> {
+    let bicycle_allowed = tags.tag_in("bicycle", &["yes", "designated", "permissive"]);
+    if tags.tag_in("bicycle", &["no", "use_sidepath"])
+        || (tags.tag_in("access", &["no", "private"]) && !bicycle_allowed)
+        || (tags.tag_in("highway", &["motorway", "motorway_link"]) && !bicycle_allowed)
+    {
+        return None;
+    }
+    if !oneway_allows(direction, tags) {
+        return None;
+    }
+
+    // Steps need the bike carried, whatever else is tagged.
+    if tags.tag_is("highway", "steps") {
+        return Some(WayCost::from_speed(TravelSpeed::from_meters_per_second(
+            STEPS_SPEED_M_S,
+        )));
+    }
+    if tags.tag_is("bicycle", "dismount")
+        || (tags.tag_in("highway", &["footway", "pedestrian", "corridor"]) && !bicycle_allowed)
+    {
+        return Some(WayCost::from_speed(TravelSpeed::from_meters_per_second(
+            DISMOUNTED_SPEED_M_S.min(options.cyclist_speed_m_s),
+        )));
+    }
+
+    let unpaved = tags.tag_in("surface", &UNPAVED_SURFACES);
+    if unpaved && options.avoid_unpaved >= 1.0 {
+        return None;
+    }
+    let speed_factor = surface_speed_factor(tags) * smoothness_speed_factor(tags)?;
+    let mut cost = WayCost::from_speed(TravelSpeed::from_meters_per_second(
+        options.cyclist_speed_m_s * speed_factor,
+    ));
+    if unpaved {
+        cost.add_penalty_ppm((options.avoid_unpaved * 2.0).into());
+    }
+
+    let stress = traffic_stress(tags) * infrastructure_stress_factor(direction, tags);
+    cost.add_penalty_ppm((stress * (1.0 - options.stress_tolerance)).into());
+    // Discourage riding in the road where a separate cycleway runs alongside it.
+    if tags.tag_in("cycleway", &["separate"])
+        || tags.tag_is("cycleway:both", "separate")
+        || tags.tag_is(side_cycleway_key(direction), "separate")
+    {
+        cost.add_penalty_ppm(0.3.into());
+    }
+    Some(cost)
+}
+
+/// Whether a cyclist may ride the way in `direction`, accounting for contraflow cycling on
+/// one-way roads.
+fn oneway_allows(direction: Direction, tags: &Tags) -> bool {
+    let contraflow = [
+        "cycleway",
+        "cycleway:left",
+        "cycleway:right",
+        "cycleway:both",
+    ]
+    .iter()
+    .filter_map(|key| tags.get(key))
+    .any(|value| value.starts_with("opposite"));
+    let oneway = match tags.get("oneway:bicycle") {
+        Some(oneway) => oneway,
+        None if contraflow => "no",
+        None if tags.tag_in("junction", &["roundabout", "circular"]) => {
+            tags.get("oneway").unwrap_or("yes")
+        }
+        None => tags.get("oneway").unwrap_or("no"),
+    };
+    match direction {
+        Direction::Forward => !matches!(oneway, "-1" | "reverse"),
+        Direction::Reverse => !matches!(oneway, "yes" | "1" | "true"),
+    }
+}
+
+/// The cycleway tag for the side of the road a cyclist rides on travelling in `direction`.
+fn side_cycleway_key(direction: Direction) -> &'static str {
+    match direction {
+        Direction::Forward => "cycleway:right",
+        Direction::Reverse => "cycleway:left",
+    }
+}
+
+/// How stressful riding in the way's traffic is, as a fraction of extra cost for a cyclist with no
+/// tolerance for it.
+fn traffic_stress(tags: &Tags) -> f64 {
+    match tags
+        .get("highway")
+        .map(|highway| highway.trim_end_matches("_link"))
+    {
+        Some("motorway") => 5.0,
+        Some("trunk") => 3.0,
+        Some("primary") => 1.5,
+        Some("secondary") => 1.0,
+        Some("tertiary") => 0.5,
+        Some("unclassified") => 0.2,
+        Some("residential") | Some("service") => 0.1,
+        _ => 0.0,
+    }
+}
+
+/// How much of the traffic stress is left once bike infrastructure on the side of the road being
+/// ridden is accounted for.
+fn infrastructure_stress_factor(direction: Direction, tags: &Tags) -> f64 {
+    ["cycleway", "cycleway:both", side_cycleway_key(direction)]
+        .iter()
+        .filter_map(|key| tags.get(key))
+        .map(|value| match value.trim_start_matches("opposite_") {
+            "track" => 0.1,
+            "lane" | "opposite" => 0.4,
+            "shared_lane" | "share_busway" => 0.8,
+            _ => 1.0,
+        })
+        .fold(1.0, f64::min)
+}
+
+fn surface_speed_factor(tags: &Tags) -> f64 {
+    match tags.get("surface") {
+        Some("paving_stones") | Some("concrete:plates") | Some("metal") | Some("wood") => 0.9,
+        Some("compacted") | Some("fine_gravel") => 0.85,
+        Some("sett") | Some("cobblestone") | Some("unhewn_cobblestone") | Some("grass_paver") => {
+            0.75
+        }
+        Some("gravel") | Some("pebblestone") | Some("unpaved") | Some("dirt") | Some("earth")
+        | Some("ground") | Some("rock") => 0.7,
+        Some("grass") | Some("mud") | Some("sand") | Some("woodchips") => 0.4,
+        _ => 1.0,
+    }
+}
+
+/// `None` if the way is too rough to ride at all.
+fn smoothness_speed_factor(tags: &Tags) -> Option<f64> {
+    match tags.get("smoothness") {
+        Some("intermediate") => Some(0.9),
+        Some("bad") => Some(0.7),
+        Some("very_bad") => Some(0.5),
+        Some("horrible") => Some(0.3),
+        Some("very_horrible") | Some("impassable") => None,
+        _ => Some(1.0),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::costing::{
+        CostingModel, WayCoster,
+        auto::{AutoOptions, auto_costing_model},
+        pedestrian::pedestrian_costing_model,
+        units::{Direction, TravelledDistance},
+    };
+    use crate::graph::testing::{
+        CAPITOL_HILL_FROM, CAPITOL_HILL_TO, capitol_hill, route, tags, way_tags,
+    };
+
+    use super::{BicycleOptions, bicycle_costing_model};
+
+    /// The cost equivalent, in seconds, of riding a kilometer of a way in `direction`.
+    fn cost_seconds(way_coster: &WayCoster, direction: Direction) -> Option<f64> {
+        way_coster
+            .cost_way_segment(TravelledDistance(1_000_000), direction)
+            .map(|cost| cost.elapsed_equivalent().millis() as f64 / 1000.0)
+    }
+
+    #[test]
+    fn bicycle_way_costs() {
+        let costing_model = bicycle_costing_model(BicycleOptions::new(5.0));
+        let cost = |pairs: &[(&str, &str)], direction| {
+            cost_seconds(&costing_model.cost_way(&tags(pairs)), direction)
+        };
+
+        let cycleway = cost(&[("highway", "cycleway")], Direction::Forward).unwrap();
+        assert_eq!(cycleway, 200.0);
+        let primary = cost(&[("highway", "primary")], Direction::Forward).unwrap();
+        let primary_with_lane = cost(
+            &[("highway", "primary"), ("cycleway:right", "lane")],
+            Direction::Forward,
+        )
+        .unwrap();
+        let residential = cost(&[("highway", "residential")], Direction::Forward).unwrap();
+        assert!(cycleway < residential && residential < primary_with_lane);
+        assert!(primary_with_lane < primary);
+        // The lane is only on the right, so riding the other way gets no benefit from it.
+        assert_eq!(
+            cost(
+                &[("highway", "primary"), ("cycleway:right", "lane")],
+                Direction::Reverse
+            ),
+            Some(primary)
+        );
+
+        assert_eq!(
+            cost(
+                &[("highway", "residential"), ("bicycle", "no")],
+                Direction::Forward
+            ),
+            None
+        );
+        assert_eq!(cost(&[("highway", "motorway")], Direction::Forward), None);
+
+        let oneway = [("highway", "residential"), ("oneway", "yes")];
+        assert!(cost(&oneway, Direction::Forward).is_some());
+        assert_eq!(cost(&oneway, Direction::Reverse), None);
+        let contraflow = [
+            ("highway", "residential"),
+            ("oneway", "yes"),
+            ("oneway:bicycle", "no"),
+        ];
+        assert!(cost(&contraflow, Direction::Reverse).is_some());
+        let opposite_lane = [
+            ("highway", "residential"),
+            ("oneway", "yes"),
+            ("cycleway", "opposite_lane"),
+        ];
+        assert!(cost(&opposite_lane, Direction::Reverse).is_some());
+        let bicycle_only_oneway = [("highway", "cycleway"), ("oneway:bicycle", "-1")];
+        assert_eq!(cost(&bicycle_only_oneway, Direction::Forward), None);
+        assert!(cost(&bicycle_only_oneway, Direction::Reverse).is_some());
+
+        let steps = cost(&[("highway", "steps")], Direction::Forward).unwrap();
+        let footway = cost(&[("highway", "footway")], Direction::Forward).unwrap();
+        let shared_footway = cost(
+            &[("highway", "footway"), ("bicycle", "designated")],
+            Direction::Forward,
+        )
+        .unwrap();
+        assert!(steps > footway && footway > shared_footway);
+        assert_eq!(shared_footway, cycleway);
+
+        let rough = cost(
+            &[("highway", "cycleway"), ("smoothness", "bad")],
+            Direction::Forward,
+        )
+        .unwrap();
+        assert!(rough > cycleway);
+        assert_eq!(
+            cost(
+                &[("highway", "cycleway"), ("smoothness", "impassable")],
+                Direction::Forward
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn bicycle_contraflow_on_real_ways() {
+        let graph = capitol_hill();
+        let riding = bicycle_costing_model(BicycleOptions::new(5.0));
+        let driving = auto_costing_model(AutoOptions::new());
+        // 21st Avenue East is one way for traffic but not for bikes.
+        let tags = way_tags(&graph, 476409328);
+        assert!(tags.tag_is("oneway", "yes") && tags.tag_is("oneway:bicycle", "no"));
+        for direction in [Direction::Forward, Direction::Reverse] {
+            assert!(cost_seconds(&riding.cost_way(&tags), direction).is_some());
+        }
+        assert!(cost_seconds(&driving.cost_way(&tags), Direction::Forward).is_some());
+        assert_eq!(
+            cost_seconds(&driving.cost_way(&tags), Direction::Reverse),
+            None
+        );
+    }
+
+    #[test]
+    fn bicycle_tunables() {
+        let gravel = tags(&[("highway", "track"), ("surface", "gravel")]);
+        let primary = tags(&[("highway", "primary")]);
+        let costs = |options: BicycleOptions| {
+            let costing_model = bicycle_costing_model(options);
+            (
+                cost_seconds(&costing_model.cost_way(&gravel), Direction::Forward),
+                cost_seconds(&costing_model.cost_way(&primary), Direction::Forward).unwrap(),
+            )
+        };
+
+        let (default_gravel, default_primary) = costs(BicycleOptions::new(5.0));
+        let (fast_gravel, fast_primary) = costs(BicycleOptions::new(10.0));
+        assert!(fast_gravel.unwrap() < default_gravel.unwrap());
+        assert!(fast_primary < default_primary);
+
+        let (_, fearless_primary) = costs(BicycleOptions::new(5.0).with_stress_tolerance(1.0));
+        let (_, timid_primary) = costs(BicycleOptions::new(5.0).with_stress_tolerance(0.0));
+        assert_eq!(fearless_primary, 200.0);
+        assert!(fearless_primary < default_primary && default_primary < timid_primary);
+
+        let (wary_gravel, _) = costs(BicycleOptions::new(5.0).with_avoid_unpaved(0.5));
+        assert!(wary_gravel.unwrap() > default_gravel.unwrap());
+        let (never_gravel, _) = costs(BicycleOptions::new(5.0).with_avoid_unpaved(1.0));
+        assert_eq!(never_gravel, None);
+
+        assert_ne!(
+            bicycle_costing_model(BicycleOptions::new(5.0)).name(),
+            bicycle_costing_model(BicycleOptions::new(5.0).with_stress_tolerance(0.0)).name()
+        );
+    }
+
+    #[test]
+    fn bicycle_route_rides_the_street() {
+        let graph = capitol_hill();
+        let riding = route(
+            &graph,
+            &bicycle_costing_model(BicycleOptions::new(5.0)),
+            &CAPITOL_HILL_FROM,
+            &CAPITOL_HILL_TO,
+        );
+        let walking = route(
+            &graph,
+            &pedestrian_costing_model(1.4),
+            &CAPITOL_HILL_FROM,
+            &CAPITOL_HILL_TO,
+        );
+        // Walkers keep to the sidewalks, where cyclists would have to get off and push, so riders
+        // take the street, along the designated bike route.
+        assert!(
+            walking
+                .way_tags
+                .iter()
+                .all(|tags| tags.tag_is("highway", "footway"))
+        );
+        assert!(riding.uses("bicycle", &["designated"]));
+        assert!(riding.result.route_duration_seconds() < walking.result.route_duration_seconds());
+        // Riders who mind traffic find the trip costlier, never cheaper.
+        let timid = route(
+            &graph,
+            &bicycle_costing_model(BicycleOptions::new(5.0).with_stress_tolerance(0.0)),
+            &CAPITOL_HILL_FROM,
+            &CAPITOL_HILL_TO,
+        );
+        assert!(timid.result.route_cost_seconds() >= riding.result.route_cost_seconds());
+    }
+}
@@ -1,8 +1,22 @@
+pub mod auto;
 pub mod base;
+pub mod bicycle;
 pub mod pedestrian;
+#[cfg(feature = "plugins")]
+pub mod plugin;
+pub mod profile;
+#[cfg(feature = "scripting")]
+pub mod script;
+pub mod truck;
 pub mod units;
+pub mod wheelchair;
 
-use std::{collections::HashMap, mem::ManuallyDrop, ops::Add};
+use std::{
+    collections::HashMap,
+    hash::{Hash, Hasher},
+    mem::ManuallyDrop,
+    ops::{Add, Sub},
+};
 
 use evmap::ShallowCopy;
 use serde::{Deserialize, Serialize};
@@ -12,9 +26,9 @@ use crate::graph::{WayId, WayTransition};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct RoutingCost {
-    cost_millis: ElapsedTime,
-    actual_millis: ElapsedTime,
-    distance_mm: TravelledDistance,
+    pub(crate) cost_millis: ElapsedTime,
+    pub(crate) actual_millis: ElapsedTime,
+    pub(crate) distance_mm: TravelledDistance,
 }
 
 impl PartialOrd for RoutingCost {
@@ -43,6 +57,18 @@ impl Add for RoutingCost {
     }
 }
 
+impl Sub for RoutingCost {
+    type Output = RoutingCost;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        RoutingCost {
+            cost_millis: self.cost_millis - rhs.cost_millis,
+            actual_millis: self.actual_millis - rhs.actual_millis,
+            distance_mm: self.distance_mm - rhs.distance_mm,
+        }
+    }
+}
+
 impl RoutingCost {
     pub fn zero() -> RoutingCost {
         RoutingCost {
@@ -85,12 +111,49 @@ impl RoutingCost {
     }
 }
 
+/// The shape of an intersection as travel passes through it from one way to another, measured
+/// from the loaded geometry of the ways that meet there. Each way's legs are the bearings of
+/// travel leaving the intersection along it forward and in reverse, or `None` where the way ends
+/// at the intersection.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct TransitionGeometry {
+    pub(crate) from_legs: (Option<f64>, Option<f64>),
+    pub(crate) to_legs: (Option<f64>, Option<f64>),
+    pub(crate) other_legs: u32,
+}
+
+/// One way of travelling through a transition: arriving along the from way in one direction and
+/// leaving along the to way in another.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Turn {
+    pub(crate) arriving: Direction,
+    pub(crate) leaving: Direction,
+    pub(crate) angle: f64,
+}
+
+impl Turn {
+    pub fn arriving(&self) -> Direction {
+        self.arriving
+    }
+
+    pub fn leaving(&self) -> Direction {
+        self.leaving
+    }
+
+    /// As for `TransitionToCost::turn_angle`.
+    pub fn angle(&self) -> f64 {
+        self.angle
+    }
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct TransitionToCost<'a> {
     pub(crate) way_transition: WayTransition,
     pub(crate) from_way_tags: &'a Tags,
     pub(crate) to_way_tags: &'a Tags,
     pub(crate) intersection_tags: &'a Tags,
+    /// `None` if either way's geometry isn't loaded.
+    pub(crate) geometry: Option<TransitionGeometry>,
 }
 
 impl<'a> TransitionToCost<'a> {
@@ -108,9 +171,64 @@ impl<'a> TransitionToCost<'a> {
     pub fn to_way_tags(&'a self) -> Tags {
         self.to_way_tags.clone()
     }
+    /// The tags of the node the ways meet at, such as `kerb`, `barrier`, `crossing` and `highway`,
+    /// along with those of any turn restriction from the from way onto the to way.
     pub fn intersection_tags(&'a self) -> Tags {
         self.intersection_tags.clone()
     }
+
+    /// The bearing, in degrees clockwise from north, of travel along the from way in the
+    /// `arriving` direction as it arrives at the intersection, or `None` if the way starts there
+    /// in that direction.
+    pub fn entry_bearing(&self, arriving: Direction) -> Option<f64> {
+        let (forward_leg, reverse_leg) = self.geometry?.from_legs;
+        // Arriving is heading the opposite way to the leg travel arrives along.
+        let leg = match arriving {
+            Direction::Forward => reverse_leg,
+            Direction::Reverse => forward_leg,
+        }?;
+        Some((leg + 180.0).rem_euclid(360.0))
+    }
+
+    /// The bearing, in degrees clockwise from north, of travel along the to way in the `leaving`
+    /// direction as it leaves the intersection, or `None` if the way ends there in that direction.
+    pub fn exit_bearing(&self, leaving: Direction) -> Option<f64> {
+        let (forward_leg, reverse_leg) = self.geometry?.to_legs;
+        match leaving {
+            Direction::Forward => forward_leg,
+            Direction::Reverse => reverse_leg,
+        }
+    }
+
+    /// How far travel arriving along the from way in the `arriving` direction and leaving along
+    /// the to way in the `leaving` direction turns, in degrees from -180 to 180. Positive turns
+    /// are to the right, 0 is straight on and ±180 is a U-turn.
+    pub fn turn_angle(&self, arriving: Direction, leaving: Direction) -> Option<f64> {
+        let change = self.exit_bearing(leaving)? - self.entry_bearing(arriving)?;
+        Some((change + 180.0).rem_euclid(360.0) - 180.0)
+    }
+
+    /// Every way of travelling through the transition the ways' geometry allows, with its turn
+    /// angle.
+    pub fn turns(&self) -> Vec<Turn> {
+        let directions = [Direction::Forward, Direction::Reverse];
+        directions
+            .into_iter()
+            .flat_map(|arriving| directions.map(|leaving| (arriving, leaving)))
+            .filter_map(|(arriving, leaving)| {
+                Some(Turn {
+                    arriving,
+                    leaving,
+                    angle: self.turn_angle(arriving, leaving)?,
+                })
+            })
+            .collect()
+    }
+
+    /// How many legs the intersection has besides the ones the transition enters and leaves by.
+    pub fn other_legs(&self) -> Option<u32> {
+        Some(self.geometry?.other_legs)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
@@ -161,10 +279,15 @@ impl TransitionCostResult {
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct WayCoster {
-    speed_forward: Option<TravelSpeed>,
-    speed_reverse: Option<TravelSpeed>,
-    penalty_ppm_forward: Option<PartsPerMillion>,
-    penalty_ppm_reverse: Option<PartsPerMillion>,
+    pub(crate) speed_forward: Option<TravelSpeed>,
+    pub(crate) speed_reverse: Option<TravelSpeed>,
+    pub(crate) penalty_ppm_forward: Option<PartsPerMillion>,
+    pub(crate) penalty_ppm_reverse: Option<PartsPerMillion>,
+    /// Charged once, on top of travel along it, whenever a route turns onto the way and sets off
+    /// forward.
+    pub(crate) entry_penalty_forward: ElapsedTime,
+    /// As `entry_penalty_forward`, for routes that set off in reverse.
+    pub(crate) entry_penalty_reverse: ElapsedTime,
 }
 
 impl WayCoster {
@@ -174,6 +297,8 @@ impl WayCoster {
             speed_reverse: None,
             penalty_ppm_forward: None,
             penalty_ppm_reverse: None,
+            entry_penalty_forward: ElapsedTime::zero(),
+            entry_penalty_reverse: ElapsedTime::zero(),
         }
     }
     pub fn from_speeds(
@@ -187,9 +312,38 @@ impl WayCoster {
             speed_reverse,
             penalty_ppm_forward,
             penalty_ppm_reverse,
+            entry_penalty_forward: ElapsedTime::zero(),
+            entry_penalty_reverse: ElapsedTime::zero(),
         }
     }
 
+    /// Charges an entry penalty each time a route turns onto the way, however far it then goes,
+    /// depending on which direction it sets off in.
+    pub fn with_entry_penalties(
+        mut self,
+        entry_penalty_forward: ElapsedTime,
+        entry_penalty_reverse: ElapsedTime,
+    ) -> Self {
+        self.entry_penalty_forward = entry_penalty_forward;
+        self.entry_penalty_reverse = entry_penalty_reverse;
+        self
+    }
+
+    /// Whether turning onto the way costs anything in either direction.
+    pub fn has_entry_penalty(&self) -> bool {
+        self.entry_penalty_forward > ElapsedTime::zero()
+            || self.entry_penalty_reverse > ElapsedTime::zero()
+    }
+
+    /// The cost of turning onto the way and setting off along it in `direction`, on top of the
+    /// travel itself.
+    pub fn cost_entry(&self, direction: Direction) -> RoutingCost {
+        RoutingCost::zero().with_penalty(match direction {
+            Direction::Forward => self.entry_penalty_forward,
+            Direction::Reverse => self.entry_penalty_reverse,
+        })
+    }
+
     fn estimate_time_ms(
         &self,
         distance: TravelledDistance,
@@ -205,6 +359,13 @@ impl WayCoster {
         }
     }
 
+    pub(crate) fn max_speed(&self) -> Option<TravelSpeed> {
+        match (self.speed_forward, self.speed_reverse) {
+            (Some(forward), Some(reverse)) => Some(forward.max(reverse)),
+            (forward, reverse) => forward.or(reverse),
+        }
+    }
+
     pub fn cost_way_segment(
         &self,
         distance: TravelledDistance,
@@ -238,13 +399,36 @@ pub trait CostingModel {
     ) -> TransitionCostResult;
 
     fn cost_way(&self, tags: &Tags) -> WayCoster;
+
+    /// An upper bound on the speed of any way this model costs. Goal-directed searches divide the
+    /// remaining great-circle distance by this to estimate the remaining cost, so it must never be
+    /// lower than a speed returned from `cost_way`. `None` disables goal direction.
+    fn max_speed(&self) -> Option<TravelSpeed> {
+        None
+    }
+
+    /// Opts the model into keeping the costs searches work out, and sharing them with other models
+    /// of the same name, so the name has to change with any configuration that changes its costs.
+    /// Contraction hierarchies are also prepared by name. `None`, the default, gives every search
+    /// its own costs.
+    fn name(&self) -> Option<&str> {
+        None
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Tags {
     map: HashMap<String, String>,
 }
 
+impl Hash for Tags {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let mut entries: Vec<(&String, &String)> = self.map.iter().collect();
+        entries.sort();
+        entries.hash(state);
+    }
+}
+
 impl Tags {
     pub(super) fn from_hashmap(map: HashMap<String, String>) -> Tags {
         Tags { map }
@@ -268,7 +452,74 @@ impl Tags {
         false
     }
 
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.map.get(key).map(|val| val.as_str())
+    }
+
     pub fn to_hashmap(&self) -> HashMap<String, String> {
         self.map.clone()
     }
 }
+
+/// The ways a model's fingerprint is taken from, covering the tags the models here cost by.
+const FINGERPRINT_WAYS: [&[(&str, &str)]; 12] = [
+    &[("highway", "footway")],
+    &[
+        ("highway", "footway"),
+        ("footway", "crossing"),
+        ("kerb", "raised"),
+    ],
+    &[("highway", "steps")],
+    &[
+        ("highway", "path"),
+        ("surface", "gravel"),
+        ("incline", "10%"),
+    ],
+    &[("highway", "cycleway")],
+    &[("highway", "residential")],
+    &[
+        ("highway", "residential"),
+        ("oneway", "yes"),
+        ("oneway:bicycle", "no"),
+    ],
+    &[("highway", "primary"), ("maxspeed", "25 mph")],
+    &[("highway", "motorway"), ("toll", "yes")],
+    &[("highway", "service"), ("access", "private")],
+    &[("highway", "track"), ("hgv", "no")],
+    &[("route", "ferry")],
+];
+
+/// A stable hash of the max speed a model declares and how it costs a fixed set of ways. Models
+/// of the same name with different fingerprints don't cost alike, so they don't share costs.
+pub(crate) fn fingerprint(model: &dyn CostingModel) -> u64 {
+    let speed = |speed: Option<TravelSpeed>| speed.map_or(u64::MAX, |speed| speed.um_per_ms.into());
+    let penalty = |ppm: Option<PartsPerMillion>| ppm.map_or(u64::MAX, |ppm| ppm.0.into());
+    let mut bytes = speed(model.max_speed()).to_le_bytes().to_vec();
+    for way in FINGERPRINT_WAYS {
+        let tags = Tags::from_hashmap(
+            way.iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        );
+        let way_coster = model.cost_way(&tags);
+        for value in [
+            speed(way_coster.speed_forward),
+            speed(way_coster.speed_reverse),
+            penalty(way_coster.penalty_ppm_forward),
+            penalty(way_coster.penalty_ppm_reverse),
+            way_coster.entry_penalty_forward.millis(),
+            way_coster.entry_penalty_reverse.millis(),
+        ] {
+            bytes.extend(value.to_le_bytes());
+        }
+    }
+    stable_hash(&bytes)
+}
+
+/// The 64-bit FNV-1a hash of `bytes`. Unlike `DefaultHasher`, it's the same on every platform and
+/// Rust release, so model names built from it still match the names in saved snapshots.
+pub(crate) fn stable_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
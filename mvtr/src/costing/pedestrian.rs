@@ -1,18 +1,13 @@
 use crate::graph::WayTransition;
 
 use super::{
-    Tags, TransitionCostResult, TransitionToCost,
-    base::{BaseCostingModel, WayCost},
-    units::{Direction, ElapsedTime, TravelSpeed},
+    TransitionCostResult,
+    base::{BoxedCostingModel, WayCost},
+    units::{ElapsedTime, TravelSpeed},
 };
 
-pub fn pedestrian_costing_model(
-    pedestrian_speed_m_s: f64,
-) -> BaseCostingModel<
-    impl Fn(Direction, &Tags) -> Option<WayCost>,
-    impl Fn(&Tags, &[TransitionToCost]) -> TransitionCostResult,
-> {
-    BaseCostingModel::new(
+pub fn pedestrian_costing_model(pedestrian_speed_m_s: f64) -> BoxedCostingModel {
+    BoxedCostingModel::boxed(
         move |_direction, tags| {
             let mut cost =
                 WayCost::from_speed(TravelSpeed::from_meters_per_second(pedestrian_speed_m_s));
@@ -63,4 +58,6 @@ pub fn pedestrian_costing_model(
             TransitionCostResult::zero(&transitions)
         },
     )
+    .with_max_speed(TravelSpeed::from_meters_per_second(pedestrian_speed_m_s))
+    .with_name(format!("pedestrian {pedestrian_speed_m_s} m/s"))
 }
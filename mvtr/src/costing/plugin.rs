@@ -0,0 +1,526 @@
+use std::collections::HashMap;
+
+use wasmi::{
+    Config, Engine, Instance, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder,
+};
+
+use super::{
+    CostingModel, Tags, TransitionCostResult, TransitionToCost, WayCoster, stable_hash,
+    units::{PartsPerMillion, TravelSpeed},
+};
+use crate::graph::WayId;
+
+/// The version of the calling convention below that plugins are written against.
+const ABI_VERSION: i32 = 1;
+/// How many instructions, roughly, a plugin gets to cost a way or an intersection, unless told
+/// otherwise.
+const DEFAULT_FUEL: u64 = 1_000_000;
+/// How much linear memory a plugin can have, unless told otherwise.
+const DEFAULT_MEMORY_LIMIT_BYTES: usize = 16 * 1024 * 1024;
+
+/// A costing model that runs a WebAssembly module in an embedded interpreter, for costing written
+/// by third parties, in whatever language they like, that can't be trusted with the process.
+///
+/// The module can't import anything, and exports:
+///
+/// - `memory`, its linear memory.
+/// - `alloc(size: i32) -> i32`, which returns where in memory `size` bytes of input can be put.
+/// - `cost_way(ptr: i32, len: i32) -> i32`, which is given the way's tags, and returns where its
+///   output is: the speeds forward and in reverse in meters per second, then the time penalties
+///   forward and in reverse as fractions of travel time, all `f64`. A speed that isn't positive
+///   makes the way impassable in that direction.
+/// - `cost_intersection(ptr: i32, len: i32) -> i32`, which is given the current way's tags, a
+///   `u32` count of transitions, then for each the `u64` ids of the ways it's from and to and the
+///   tags of the from way, the to way and the intersection. It returns where its output is: an
+///   `f64` penalty in seconds for continuing along the current way, which is not allowed if
+///   negative, a `u32` count, then for each a `u64` way id that can be turned onto and its `f64`
+///   penalty in seconds.
+/// - Optionally, `abi_version() -> i32`, which must return 1.
+///
+/// Tags are a `u32` count, then for each a `u32` length and the UTF-8 bytes of the key, then the
+/// same for the value. All numbers are little-endian. Every call runs in a fresh instance, limited
+/// in the fuel it can burn and the memory it can grow to. A call that traps or runs out of either
+/// costs its way or intersection as impassable.
+pub struct PluginCostingModel {
+    engine: Engine,
+    module: Module,
+    name: String,
+    max_speed: Option<TravelSpeed>,
+    fuel: u64,
+    memory_limit_bytes: usize,
+}
+
+struct PluginState {
+    limits: StoreLimits,
+}
+
+/// Reads a plugin's output out of its memory.
+struct OutputReader<'a> {
+    memory: &'a [u8],
+    offset: usize,
+}
+
+impl OutputReader<'_> {
+    fn bytes<const N: usize>(&mut self) -> anyhow::Result<[u8; N]> {
+        let bytes = self
+            .offset
+            .checked_add(N)
+            .and_then(|end| self.memory.get(self.offset..end))
+            .ok_or_else(|| anyhow::anyhow!("Plugin output runs past the end of its memory"))?;
+        self.offset += N;
+        Ok(bytes.try_into()?)
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes()?))
+    }
+
+    fn u64(&mut self) -> anyhow::Result<u64> {
+        Ok(u64::from_le_bytes(self.bytes()?))
+    }
+
+    fn f64(&mut self) -> anyhow::Result<f64> {
+        Ok(f64::from_le_bytes(self.bytes()?))
+    }
+}
+
+impl PluginCostingModel {
+    /// Loads a plugin from `wasm`, either a binary module or, for plugins written by hand, its
+    /// text format, and checks it has the exports costing needs.
+    pub fn new(name: &str, wasm: &[u8]) -> anyhow::Result<PluginCostingModel> {
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, wasm)
+            .map_err(|err| anyhow::anyhow!("Failed to load costing plugin: {}", err))?;
+        if let Some(import) = module.imports().next() {
+            return Err(anyhow::anyhow!(
+                "Costing plugin imports {}::{}, but plugins can't import anything",
+                import.module(),
+                import.name()
+            ));
+        }
+        let plugin = PluginCostingModel {
+            engine,
+            module,
+            name: format!("plugin {} {:016x}", name, stable_hash(wasm)),
+            max_speed: None,
+            fuel: DEFAULT_FUEL,
+            memory_limit_bytes: DEFAULT_MEMORY_LIMIT_BYTES,
+        };
+
+        let (mut store, instance) = plugin.instantiate()?;
+        if let Ok(abi_version) = instance.get_typed_func::<(), i32>(&store, "abi_version") {
+            let abi_version = abi_version.call(&mut store, ()).map_err(|err| {
+                anyhow::anyhow!("Failed to get costing plugin ABI version: {}", err)
+            })?;
+            if abi_version != ABI_VERSION {
+                return Err(anyhow::anyhow!(
+                    "Costing plugin is for ABI version {}, not {}",
+                    abi_version,
+                    ABI_VERSION
+                ));
+            }
+        }
+        instance
+            .get_typed_func::<i32, i32>(&store, "alloc")
+            .map_err(|err| anyhow::anyhow!("Costing plugin doesn't export alloc: {}", err))?;
+        for function in ["cost_way", "cost_intersection"] {
+            instance
+                .get_typed_func::<(i32, i32), i32>(&store, function)
+                .map_err(|err| {
+                    anyhow::anyhow!("Costing plugin doesn't export {}: {}", function, err)
+                })?;
+        }
+        Ok(plugin)
+    }
+
+    /// The fastest the plugin ever has anything travel, which lets searches be goal-directed.
+    pub fn with_max_speed(mut self, max_speed: TravelSpeed) -> PluginCostingModel {
+        self.max_speed = Some(max_speed);
+        self
+    }
+
+    /// How much fuel each call into the plugin gets, which most instructions burn one of.
+    pub fn with_fuel(mut self, fuel: u64) -> PluginCostingModel {
+        self.fuel = fuel;
+        self
+    }
+
+    /// How large the plugin's memory can grow.
+    pub fn with_memory_limit(mut self, memory_limit_bytes: usize) -> PluginCostingModel {
+        self.memory_limit_bytes = memory_limit_bytes;
+        self
+    }
+
+    /// A fresh instance of the plugin, with a full tank of fuel.
+    fn instantiate(&self) -> anyhow::Result<(Store<PluginState>, Instance)> {
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(self.memory_limit_bytes)
+            .instances(1)
+            .memories(1)
+            .tables(1)
+            .trap_on_grow_failure(true)
+            .build();
+        let mut store = Store::new(&self.engine, PluginState { limits });
+        store.limiter(|state| &mut state.limits);
+        store
+            .set_fuel(self.fuel)
+            .map_err(|err| anyhow::anyhow!("Failed to fuel costing plugin: {}", err))?;
+        let instance = Linker::<PluginState>::new(&self.engine)
+            .instantiate(&mut store, &self.module)
+            .and_then(|instance| instance.start(&mut store))
+            .map_err(|err| anyhow::anyhow!("Failed to instantiate costing plugin: {}", err))?;
+        Ok((store, instance))
+    }
+
+    /// Calls one of the plugin's functions with `input`, and reads its output with `read`.
+    fn try_call<Output>(
+        &self,
+        function: &str,
+        input: &[u8],
+        read: impl FnOnce(&mut OutputReader) -> anyhow::Result<Output>,
+    ) -> anyhow::Result<Output> {
+        let (mut store, instance) = self.instantiate()?;
+        let memory: Memory = instance
+            .get_memory(&store, "memory")
+            .ok_or_else(|| anyhow::anyhow!("Costing plugin doesn't export memory"))?;
+        let len = i32::try_from(input.len())?;
+        let ptr = instance
+            .get_typed_func::<i32, i32>(&store, "alloc")
+            .and_then(|alloc| alloc.call(&mut store, len))
+            .map_err(|err| anyhow::anyhow!("Failed to allocate plugin input: {}", err))?;
+        memory
+            .write(&mut store, ptr as u32 as usize, input)
+            .map_err(|err| anyhow::anyhow!("Failed to write plugin input: {}", err))?;
+        let output = instance
+            .get_typed_func::<(i32, i32), i32>(&store, function)
+            .and_then(|function| function.call(&mut store, (ptr, len)))
+            .map_err(|err| anyhow::anyhow!("Failed to run: {}", err))?;
+        read(&mut OutputReader {
+            memory: memory.data(&store),
+            offset: output as u32 as usize,
+        })
+    }
+
+    /// Like `try_call`, but `None` if anything goes wrong, which is logged.
+    fn call<Output>(
+        &self,
+        function: &str,
+        input: &[u8],
+        read: impl FnOnce(&mut OutputReader) -> anyhow::Result<Output>,
+    ) -> Option<Output> {
+        match self.try_call(function, input, read) {
+            Ok(output) => Some(output),
+            Err(err) => {
+                tracing::warn!("Costing plugin's {} failed: {}", function, err);
+                None
+            }
+        }
+    }
+}
+
+fn write_bytes(input: &mut Vec<u8>, bytes: &[u8]) {
+    input.extend((bytes.len() as u32).to_le_bytes());
+    input.extend(bytes);
+}
+
+fn write_tags(input: &mut Vec<u8>, tags: &Tags) {
+    // Sorted, so a plugin always sees the same tags in the same order.
+    let mut tags: Vec<(String, String)> = tags.to_hashmap().into_iter().collect();
+    tags.sort();
+    input.extend((tags.len() as u32).to_le_bytes());
+    for (key, value) in tags {
+        write_bytes(input, key.as_bytes());
+        write_bytes(input, value.as_bytes());
+    }
+}
+
+impl CostingModel for PluginCostingModel {
+    fn cost_intersection(
+        &self,
+        current_way_tags: &Tags,
+        transitions_to_cost: &[TransitionToCost],
+    ) -> TransitionCostResult {
+        let mut input = Vec::new();
+        write_tags(&mut input, current_way_tags);
+        input.extend((transitions_to_cost.len() as u32).to_le_bytes());
+        for transition in transitions_to_cost {
+            input.extend(transition.from_way_id().id().to_le_bytes());
+            input.extend(transition.to_way_id().id().to_le_bytes());
+            write_tags(&mut input, transition.from_way_tags);
+            write_tags(&mut input, transition.to_way_tags);
+            write_tags(&mut input, transition.intersection_tags);
+        }
+        let Some((transition_costs, continue_penalty)) =
+            self.call("cost_intersection", &input, |output| {
+                let continue_penalty = output.f64()?;
+                let count = output.u32()?;
+                let mut transition_costs = HashMap::new();
+                for _ in 0..count {
+                    let to_way_id = WayId::from_id(output.u64()?);
+                    let penalty_seconds = output.f64()?;
+                    if penalty_seconds >= 0.0 {
+                        transition_costs.insert(to_way_id, penalty_seconds);
+                    }
+                }
+                Ok((
+                    transition_costs,
+                    Some(continue_penalty).filter(|penalty| *penalty >= 0.0),
+                ))
+            })
+        else {
+            return TransitionCostResult::impassable();
+        };
+        TransitionCostResult::from_transitions_and_costs_seconds(
+            &transition_costs,
+            continue_penalty,
+        )
+    }
+
+    fn cost_way(&self, tags: &Tags) -> WayCoster {
+        let mut input = Vec::new();
+        write_tags(&mut input, tags);
+        let Some(output) = self.call("cost_way", &input, |output| {
+            Ok([output.f64()?, output.f64()?, output.f64()?, output.f64()?])
+        }) else {
+            return WayCoster::impassable();
+        };
+        let [
+            speed_forward,
+            speed_reverse,
+            penalty_forward,
+            penalty_reverse,
+        ] = output;
+        let speed = |meters_per_second: f64| {
+            Some(meters_per_second)
+                .filter(|meters_per_second| *meters_per_second > 0.0)
+                .map(TravelSpeed::from_meters_per_second)
+        };
+        WayCoster::from_speeds(
+            speed(speed_forward),
+            speed(speed_reverse),
+            Some(PartsPerMillion::from_fraction(penalty_forward)),
+            Some(PartsPerMillion::from_fraction(penalty_reverse)),
+        )
+    }
+
+    fn max_speed(&self) -> Option<TravelSpeed> {
+        self.max_speed
+    }
+
+    fn name(&self) -> Option<&str> {
+        Some(&self.name)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use crate::costing::{
+        CostingModel, TransitionToCost,
+        pedestrian::pedestrian_costing_model,
+        units::{Direction, TravelSpeed, TravelledDistance},
+    };
+    use crate::graph::{
+        WayId, WayTransition,
+        testing::{
+            CAPITOL_HILL_EAST_OF_STEPS, CAPITOL_HILL_WEST_OF_STEPS, capitol_hill, route, tags,
+            way_tags,
+        },
+    };
+
+    use super::PluginCostingModel;
+
+    fn walking_plugin() -> PluginCostingModel {
+        PluginCostingModel::new(
+            "walking",
+            &wat::parse_str(include_str!("../../testdata/plugin.wat")).unwrap(),
+        )
+        .unwrap()
+    }
+
+    /// A plugin that exports the costing functions, with `cost_way_body` as the body of
+    /// `cost_way`, and `memory_pages` of memory. Output at 1024 has ways passable at 1 m/s.
+    fn plugin(cost_way_body: &str, memory_pages: u32) -> anyhow::Result<PluginCostingModel> {
+        PluginCostingModel::new(
+            "test",
+            &wat::parse_str(format!(
+                r#"(module
+                    (memory (export "memory") {memory_pages})
+                    (data (i32.const 1024) "\00\00\00\00\00\00\f0\3f")
+                    (data (i32.const 1032) "\00\00\00\00\00\00\f0\3f")
+                    (func (export "alloc") (param i32) (result i32) (i32.const 0))
+                    (func (export "cost_way") (param i32 i32) (result i32) {cost_way_body})
+                    (func (export "cost_intersection") (param i32 i32) (result i32)
+                        (i32.const 0)))"#
+            ))
+            .unwrap(),
+        )
+    }
+
+    fn passable(costing_model: &PluginCostingModel) -> bool {
+        costing_model
+            .cost_way(&tags(&[]))
+            .cost_way_segment(TravelledDistance(1), Direction::Forward)
+            .is_some()
+    }
+
+    #[test]
+    fn plugin_way_and_intersection_costs() {
+        let costing_model = walking_plugin();
+        let cost = |pairs: &[(&str, &str)]| {
+            costing_model
+                .cost_way(&tags(pairs))
+                .cost_way_segment(TravelledDistance(14_000), Direction::Forward)
+                .map(|cost| cost.elapsed_equivalent().millis())
+        };
+        assert_eq!(cost(&[("highway", "footway")]), Some(10_000));
+        assert_eq!(cost(&[("highway", "residential")]), Some(10_500));
+        assert_eq!(cost(&[("highway", "motorway")]), None);
+
+        let sidewalk = tags(&[("highway", "footway")]);
+        let road = tags(&[("highway", "residential"), ("name", "East Pine Street")]);
+        let crossing = tags(&[("highway", "footway"), ("footway", "crossing")]);
+        let steps = tags(&[("highway", "steps")]);
+        let intersection = tags(&[("highway", "traffic_signals")]);
+        let transitions: Vec<TransitionToCost> = [(2, &crossing), (3, &steps), (4, &road)]
+            .into_iter()
+            .map(|(to_way, to_way_tags)| TransitionToCost {
+                way_transition: WayTransition::new(WayId::from_id(1), 0, WayId::from_id(to_way), 0),
+                from_way_tags: &sidewalk,
+                to_way_tags,
+                intersection_tags: &intersection,
+                geometry: None,
+            })
+            .collect();
+        let result = costing_model.cost_intersection(&sidewalk, &transitions);
+        assert_eq!(result.transition_costs.len(), 2);
+        assert_eq!(
+            result.transition_costs[&WayId::from_id(2)]
+                .elapsed_equivalent()
+                .millis(),
+            10_000
+        );
+        assert_eq!(
+            result.transition_costs[&WayId::from_id(4)]
+                .elapsed_equivalent()
+                .millis(),
+            0
+        );
+        assert_eq!(
+            result.continue_cost.unwrap().elapsed_equivalent().millis(),
+            0
+        );
+    }
+
+    #[test]
+    fn plugin_fuel_limit() {
+        let endless = plugin("(loop (br 0)) (i32.const 1024)", 1).unwrap();
+        let started = Instant::now();
+        assert!(!passable(&endless));
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        // Costing the same way needs more fuel than a near-empty tank.
+        let walking = walking_plugin();
+        assert!(
+            walking
+                .cost_way(&tags(&[("highway", "footway")]))
+                .cost_way_segment(TravelledDistance(1), Direction::Forward)
+                .is_some()
+        );
+        assert!(!passable(&walking_plugin().with_fuel(10)));
+    }
+
+    #[test]
+    fn plugin_fuel_limit_on_real_ways() {
+        let graph = capitol_hill();
+        // Steps with no other tags, and a street with ten, which take the plugin more fuel to cost.
+        let (steps, street) = (way_tags(&graph, 1320436546), way_tags(&graph, 476409328));
+        assert_eq!(steps.to_hashmap().len(), 1);
+        assert_eq!(street.to_hashmap().len(), 10);
+        let passable = |costing_model: &PluginCostingModel, tags| {
+            costing_model
+                .cost_way(tags)
+                .cost_way_segment(TravelledDistance(1), Direction::Forward)
+                .is_some()
+        };
+        assert!(passable(&walking_plugin(), &steps));
+        assert!(passable(&walking_plugin(), &street));
+        // Each call gets the same tank, which is enough for the steps but not the street.
+        let short_of_fuel = walking_plugin().with_fuel(280);
+        assert!(passable(&short_of_fuel, &steps));
+        assert!(!passable(&short_of_fuel, &street));
+        assert!(passable(&short_of_fuel, &steps));
+    }
+
+    #[test]
+    fn plugin_memory_limit() {
+        // 64 KiB pages, so this grows to 4 MiB.
+        let growing = "(drop (memory.grow (i32.const 63))) (i32.const 1024)";
+        assert!(passable(&plugin(growing, 1).unwrap()));
+        assert!(!passable(
+            &plugin(growing, 1).unwrap().with_memory_limit(1024 * 1024)
+        ));
+        // Plugins that start out too large fail every call.
+        assert!(!passable(
+            &plugin("(i32.const 1024)", 32)
+                .unwrap()
+                .with_memory_limit(1024 * 1024)
+        ));
+    }
+
+    #[test]
+    fn plugin_errors() {
+        assert!(PluginCostingModel::new("garbage", b"not wasm").is_err());
+        assert!(
+            PluginCostingModel::new(
+                "missing",
+                &wat::parse_str(r#"(module (memory (export "memory") 1))"#).unwrap()
+            )
+            .is_err()
+        );
+        assert!(
+            PluginCostingModel::new(
+                "imports",
+                &wat::parse_str(r#"(module (import "env" "log" (func)))"#).unwrap()
+            )
+            .is_err()
+        );
+        let wrong_version = include_str!("../../testdata/plugin.wat").replace(
+            "(func (export \"abi_version\") (result i32)\n    (i32.const 1))",
+            "(func (export \"abi_version\") (result i32)\n    (i32.const 2))",
+        );
+        assert!(
+            PluginCostingModel::new("wrong version", &wat::parse_str(wrong_version).unwrap())
+                .is_err()
+        );
+        // Output that isn't in memory is an error, not a panic.
+        assert!(!passable(&plugin("(i32.const -1)", 1).unwrap()));
+        assert_ne!(
+            walking_plugin().name(),
+            plugin("(i32.const 1024)", 1).unwrap().name()
+        );
+    }
+
+    #[test]
+    fn plugin_route_avoids_steps() {
+        let graph = capitol_hill();
+        let route_between_steps = |costing_model: &dyn CostingModel| {
+            route(
+                &graph,
+                costing_model,
+                &CAPITOL_HILL_EAST_OF_STEPS,
+                &CAPITOL_HILL_WEST_OF_STEPS,
+            )
+        };
+        // The reference plugin won't turn onto steps, which the pedestrian model happily climbs.
+        let plugged_in = route_between_steps(
+            &walking_plugin().with_max_speed(TravelSpeed::from_meters_per_second(1.4)),
+        );
+        let walking = route_between_steps(&pedestrian_costing_model(1.4));
+        assert!(walking.uses("highway", &["steps"]));
+        assert!(!plugged_in.uses("highway", &["steps"]));
+    }
+}
@@ -0,0 +1,561 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use super::{
+    Tags, TransitionCostResult, TransitionToCost,
+    base::{BoxedCostingModel, WayCost},
+    stable_hash,
+    units::{Direction, ElapsedTime, PartsPerMillion, TravelSpeed},
+};
+
+/// A costing model written as data rather than code, so it can be changed without a rebuild.
+///
+/// Ways are costed by starting at the profile's `speed` and applying each way rule whose tags
+/// match, in order: a rule can set the speed, add penalties, or make the way impassable.
+/// Transitions are free unless an intersection rule matches them. In TOML:
+///
+/// ```toml
+/// name = "careful walker"
+/// speed = "5 km/h"
+///
+/// [[way_rules]]
+/// match = { highway = ["motorway", "trunk"] }
+/// impassable = true
+///
+/// [[way_rules]]
+/// match = { highway = "steps" }
+/// speed = "2 km/h"
+/// flat_penalty_seconds = 10
+///
+/// [[way_rules]]
+/// match = { incline = "up" }
+/// direction = "Forward"
+/// penalty_ppm = 200000
+///
+/// [[intersection_rules]]
+/// to = { footway = "crossing" }
+/// penalty_seconds = 15
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CostingProfile {
+    name: String,
+    speed: ProfileSpeed,
+    #[serde(default)]
+    way_rules: Vec<WayRule>,
+    #[serde(default)]
+    intersection_rules: Vec<IntersectionRule>,
+    /// Tells profiles with the same name but different rules apart, so costs one has worked out
+    /// aren't reused for the other.
+    #[serde(skip)]
+    fingerprint: u64,
+}
+
+/// What a way rule does to the ways it matches.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WayRule {
+    #[serde(rename = "match", default)]
+    matches: TagMatch,
+    /// The rule doesn't apply to ways that match this, if given.
+    unless: Option<TagMatch>,
+    /// The rule only applies to travel in this direction along the way, if given.
+    direction: Option<Direction>,
+    speed: Option<ProfileSpeed>,
+    penalty_ppm: Option<u32>,
+    flat_penalty_seconds: Option<f64>,
+    #[serde(default)]
+    impassable: bool,
+}
+
+/// What an intersection rule does to the transitions it matches. Continuing along a way through
+/// an intersection counts as a transition from the way onto itself.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct IntersectionRule {
+    #[serde(default)]
+    from: TagMatch,
+    #[serde(default)]
+    to: TagMatch,
+    /// Matched against the tags of the node the ways meet at, such as `highway =
+    /// "traffic_signals"`, along with those of any turn restriction from the from way onto the to
+    /// way. Continuing along a way only sees the node's tags.
+    #[serde(default)]
+    at: TagMatch,
+    penalty_seconds: Option<f64>,
+    #[serde(default)]
+    impassable: bool,
+}
+
+/// Tags to match: every key listed must have one of its listed values, or any value if `"*"` is
+/// listed. Matches everything if empty.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(transparent)]
+struct TagMatch(HashMap<String, TagValues>);
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum TagValues {
+    One(String),
+    Any(Vec<String>),
+}
+
+impl TagMatch {
+    fn matches(&self, tags: &Tags) -> bool {
+        self.0.iter().all(|(key, values)| {
+            tags.get(key).is_some_and(|value| {
+                let values = match values {
+                    TagValues::One(one) => std::slice::from_ref(one),
+                    TagValues::Any(any) => any.as_slice(),
+                };
+                values
+                    .iter()
+                    .any(|allowed| allowed == "*" || allowed == value)
+            })
+        })
+    }
+}
+
+/// A speed as OSM tags it, such as `"25 mph"`, or a bare number of km/h.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(try_from = "RawSpeed")]
+struct ProfileSpeed(TravelSpeed);
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawSpeed {
+    Number(f64),
+    Text(String),
+}
+
+impl TryFrom<RawSpeed> for ProfileSpeed {
+    type Error = String;
+
+    fn try_from(raw: RawSpeed) -> Result<Self, Self::Error> {
+        match raw {
+            RawSpeed::Number(kmh) => Ok(ProfileSpeed(TravelSpeed::from_kmh(kmh))),
+            RawSpeed::Text(text) => TravelSpeed::parse(&text)
+                .map(ProfileSpeed)
+                .ok_or_else(|| format!("Couldn't understand speed {text:?}")),
+        }
+    }
+}
+
+impl CostingProfile {
+    pub fn from_json(json: &str) -> anyhow::Result<CostingProfile> {
+        let profile: CostingProfile = serde_json::from_str(json)
+            .map_err(|err| anyhow::anyhow!("Failed to parse costing profile: {}", err))?;
+        Ok(profile.with_fingerprint(json))
+    }
+
+    pub fn from_toml(toml: &str) -> anyhow::Result<CostingProfile> {
+        let profile: CostingProfile = toml::from_str(toml)
+            .map_err(|err| anyhow::anyhow!("Failed to parse costing profile: {}", err))?;
+        Ok(profile.with_fingerprint(toml))
+    }
+
+    fn with_fingerprint(mut self, source: &str) -> CostingProfile {
+        self.fingerprint = stable_hash(source.as_bytes());
+        self
+    }
+
+    /// The fastest speed any way rule, or the profile itself, sets.
+    fn max_speed(&self) -> TravelSpeed {
+        self.way_rules
+            .iter()
+            .filter_map(|rule| rule.speed)
+            .map(|speed| speed.0)
+            .fold(self.speed.0, |max, speed| max.max(speed))
+    }
+
+    fn cost_way(&self, direction: Direction, tags: &Tags) -> Option<WayCost> {
+        let mut speed = self.speed.0;
+        let mut penalty_ppm = PartsPerMillion::of(0);
+        let mut flat_penalty = ElapsedTime::zero();
+        for rule in &self.way_rules {
+            if rule.direction.is_some_and(|only| only != direction)
+                || !rule.matches.matches(tags)
+                || rule
+                    .unless
+                    .as_ref()
+                    .is_some_and(|unless| unless.matches(tags))
+            {
+                continue;
+            }
+            if rule.impassable {
+                return None;
+            }
+            if let Some(rule_speed) = rule.speed {
+                speed = rule_speed.0;
+            }
+            penalty_ppm = penalty_ppm + PartsPerMillion::of(rule.penalty_ppm.unwrap_or(0));
+            flat_penalty =
+                flat_penalty + ElapsedTime::from_seconds(rule.flat_penalty_seconds.unwrap_or(0.0));
+        }
+        let mut cost = WayCost::from_speed(speed);
+        cost.add_penalty_ppm(penalty_ppm);
+        cost.add_flat_penalty(flat_penalty);
+        Some(cost)
+    }
+
+    /// The penalty, in seconds, of a transition between ways with `from_tags` and `to_tags` at an
+    /// intersection with `at_tags`. `None` if the transition isn't allowed.
+    fn transition_penalty_seconds(
+        &self,
+        from_tags: &Tags,
+        to_tags: &Tags,
+        at_tags: &Tags,
+    ) -> Option<f64> {
+        let mut penalty_seconds = 0.0;
+        for rule in &self.intersection_rules {
+            if !(rule.from.matches(from_tags)
+                && rule.to.matches(to_tags)
+                && rule.at.matches(at_tags))
+            {
+                continue;
+            }
+            if rule.impassable {
+                return None;
+            }
+            penalty_seconds += rule.penalty_seconds.unwrap_or(0.0);
+        }
+        Some(penalty_seconds)
+    }
+
+    fn cost_intersection(
+        &self,
+        current_way_tags: &Tags,
+        transitions_to_cost: &[TransitionToCost],
+    ) -> TransitionCostResult {
+        let transitions: HashMap<_, _> = transitions_to_cost
+            .iter()
+            .filter_map(|transition| {
+                let penalty_seconds = self.transition_penalty_seconds(
+                    transition.from_way_tags,
+                    transition.to_way_tags,
+                    transition.intersection_tags,
+                )?;
+                Some((transition.to_way_id(), penalty_seconds))
+            })
+            .collect();
+        // A turn restriction's tags only apply to its own pair of ways, so continuing through the
+        // intersection sees the node's tags alone, unless the way loops back onto itself here.
+        let intersection_tags = match transitions_to_cost
+            .iter()
+            .find(|transition| transition.to_way_id() == transition.from_way_id())
+        {
+            Some(transition) => transition.intersection_tags.clone(),
+            None => Tags::from_hashmap(
+                transitions_to_cost
+                    .first()
+                    .map(|transition| node_tags(transition.intersection_tags))
+                    .unwrap_or_default(),
+            ),
+        };
+        let continue_penalty =
+            self.transition_penalty_seconds(current_way_tags, current_way_tags, &intersection_tags);
+        TransitionCostResult::from_transitions_and_costs_seconds(&transitions, continue_penalty)
+    }
+}
+
+/// Keys a turn restriction relation's tags are carried under.
+const RESTRICTION_KEYS: &[&str] = &[
+    "type",
+    "restriction",
+    "except",
+    "day_on",
+    "day_off",
+    "hour_on",
+    "hour_off",
+];
+
+/// An intersection's tags without those of any turn restriction, leaving the node's.
+fn node_tags(intersection_tags: &Tags) -> HashMap<String, String> {
+    let mut tags = intersection_tags.to_hashmap();
+    tags.retain(|key, _| {
+        !RESTRICTION_KEYS.contains(&key.as_str()) && !key.starts_with("restriction:")
+    });
+    tags
+}
+
+pub fn profile_costing_model(profile: CostingProfile) -> BoxedCostingModel {
+    let name = format!("profile {} {:016x}", profile.name, profile.fingerprint);
+    let max_speed = profile.max_speed();
+    let intersection_profile = profile.clone();
+    BoxedCostingModel::boxed(
+        move |direction, tags| profile.cost_way(direction, tags),
+        move |tags, transitions_to_cost| {
+            intersection_profile.cost_intersection(tags, transitions_to_cost)
+        },
+    )
+    .with_max_speed(max_speed)
+    .with_name(name)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::costing::{
+        CostingModel, TransitionToCost,
+        units::{Direction, TravelledDistance},
+    };
+    use crate::graph::{
+        WayId, WayTransition,
+        testing::{
+            CAPITOL_HILL_EAST_OF_STEPS, CAPITOL_HILL_WEST_OF_STEPS, capitol_hill, route, tags,
+            way_tags,
+        },
+    };
+
+    use super::{CostingProfile, profile_costing_model};
+
+    const WALKING_PROFILE: &str = include_str!("../../testdata/walking-profile.toml");
+
+    #[test]
+    fn profile_way_rules() {
+        let costing_model =
+            profile_costing_model(CostingProfile::from_toml(WALKING_PROFILE).unwrap());
+        // The cost equivalent, in seconds, of a hundred meters of a way in `direction`.
+        let cost = |pairs: &[(&str, &str)], direction| {
+            costing_model
+                .cost_way(&tags(pairs))
+                .cost_way_segment(TravelledDistance(100_000), direction)
+                .map(|cost| cost.elapsed_equivalent().millis() as f64 / 1000.0)
+        };
+
+        // 5 km/h.
+        let footway = cost(&[("highway", "footway")], Direction::Forward).unwrap();
+        assert!((footway - 72.0).abs() < 0.1);
+        assert!(cost(&[("highway", "motorway")], Direction::Forward).is_none());
+        assert!(
+            cost(
+                &[("highway", "footway"), ("foot", "no")],
+                Direction::Reverse
+            )
+            .is_none()
+        );
+        assert!(
+            cost(
+                &[("highway", "footway"), ("foot", "no"), ("access", "yes")],
+                Direction::Forward
+            )
+            .is_some()
+        );
+        assert!(cost(&[("highway", "steps")], Direction::Forward).unwrap() > footway);
+
+        // Only uphill is penalized.
+        let sloped = [("highway", "footway"), ("incline", "up")];
+        assert!(cost(&sloped, Direction::Forward).unwrap() > footway);
+        assert_eq!(cost(&sloped, Direction::Reverse), Some(footway));
+        let road = cost(&[("highway", "residential")], Direction::Forward).unwrap();
+        assert!((road - footway * 1.1).abs() < 0.1);
+    }
+
+    #[test]
+    fn profile_later_rules_take_precedence_on_real_steps() {
+        let graph = capitol_hill();
+        // Steps with a handrail on both sides, and steps nothing more is known about.
+        let (handrail, bare) = (way_tags(&graph, 1188542971), way_tags(&graph, 1320436546));
+        assert!(handrail.tag_is("highway", "steps") && handrail.tag_is("handrail", "both"));
+        assert!(bare.tag_is("highway", "steps") && bare.get("handrail").is_none());
+
+        // The seconds it takes to climb a hundred meters of each, with the steps rule and the
+        // handrail rule in the order given.
+        let costs = |rules: [&str; 2]| {
+            let profile = CostingProfile::from_json(&format!(
+                r#"{{ "name": "steps", "speed": 5, "way_rules": [{}, {}] }}"#,
+                rules[0], rules[1]
+            ))
+            .unwrap();
+            let costing_model = profile_costing_model(profile);
+            [&handrail, &bare].map(|tags| {
+                costing_model
+                    .cost_way(tags)
+                    .cost_way_segment(TravelledDistance(100_000), Direction::Forward)
+                    .map(|cost| cost.elapsed_equivalent().millis() / 1000)
+            })
+        };
+        let steps = r#"{ "match": { "highway": "steps" }, "speed": 2 }"#;
+        let handrails = r#"{ "match": { "handrail": ["yes", "both"] }, "speed": 3 }"#;
+        assert_eq!(costs([steps, handrails]), [Some(120), Some(180)]);
+        assert_eq!(costs([handrails, steps]), [Some(180), Some(180)]);
+    }
+
+    #[test]
+    fn profile_intersection_rules() {
+        let profile = CostingProfile::from_json(
+            r#"{
+                "name": "no crossing arterials",
+                "speed": 5,
+                "intersection_rules": [
+                    { "to": { "footway": "crossing" }, "penalty_seconds": 15 },
+                    {
+                        "from": { "highway": "footway" },
+                        "to": { "highway": ["primary", "secondary"] },
+                        "impassable": true
+                    },
+                    { "at": { "highway": "traffic_signals" }, "penalty_seconds": 30 }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let costing_model = profile_costing_model(profile);
+        let sidewalk = tags(&[("highway", "footway"), ("footway", "sidewalk")]);
+        let crossing = tags(&[("highway", "footway"), ("footway", "crossing")]);
+        let arterial = tags(&[("highway", "secondary")]);
+        let signals = tags(&[("highway", "traffic_signals")]);
+        let transitions: Vec<TransitionToCost> = [(2, &crossing), (3, &arterial)]
+            .into_iter()
+            .map(|(to_way, to_way_tags)| TransitionToCost {
+                way_transition: WayTransition::new(WayId::from_id(1), 0, WayId::from_id(to_way), 0),
+                from_way_tags: &sidewalk,
+                to_way_tags,
+                intersection_tags: &signals,
+                geometry: None,
+            })
+            .collect();
+
+        let result = costing_model.cost_intersection(&sidewalk, &transitions);
+        assert_eq!(result.transition_costs.len(), 1);
+        assert_eq!(
+            result.transition_costs[&WayId::from_id(2)]
+                .elapsed_equivalent()
+                .millis(),
+            45_000
+        );
+        assert_eq!(
+            result.continue_cost.unwrap().elapsed_equivalent().millis(),
+            30_000
+        );
+    }
+
+    #[test]
+    fn profile_restrictions_only_apply_to_their_transition() {
+        let profile = CostingProfile::from_json(
+            r#"{
+                "name": "signals and restrictions",
+                "speed": 5,
+                "intersection_rules": [
+                    { "at": { "highway": "traffic_signals" }, "penalty_seconds": 30 },
+                    { "at": { "restriction": "*" }, "impassable": true }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let costing_model = profile_costing_model(profile);
+        let street = tags(&[("highway", "residential")]);
+        let signals = tags(&[("highway", "traffic_signals")]);
+        let restricted = tags(&[
+            ("highway", "traffic_signals"),
+            ("type", "restriction"),
+            ("restriction", "no_left_turn"),
+        ]);
+        for restricted_way in [2, 3] {
+            let transitions: Vec<TransitionToCost> = [2, 3]
+                .into_iter()
+                .map(|to_way| TransitionToCost {
+                    way_transition: WayTransition::new(
+                        WayId::from_id(1),
+                        0,
+                        WayId::from_id(to_way),
+                        0,
+                    ),
+                    from_way_tags: &street,
+                    to_way_tags: &street,
+                    intersection_tags: if to_way == restricted_way {
+                        &restricted
+                    } else {
+                        &signals
+                    },
+                    geometry: None,
+                })
+                .collect();
+
+            let result = costing_model.cost_intersection(&street, &transitions);
+            assert_eq!(result.transition_costs.len(), 1);
+            assert!(
+                !result
+                    .transition_costs
+                    .contains_key(&WayId::from_id(restricted_way))
+            );
+            assert_eq!(
+                result.continue_cost.unwrap().elapsed_equivalent().millis(),
+                30_000
+            );
+        }
+    }
+
+    #[test]
+    fn profile_errors() {
+        assert!(CostingProfile::from_toml("name = \"fast\"\nspeed = \"warp 9\"").is_err());
+        assert!(CostingProfile::from_toml("name = \"typo\"\nspeeed = 5").is_err());
+        assert!(
+            CostingProfile::from_json(
+                r#"{"name": "x", "speed": 5, "way_rules": [{"impasable": true}]}"#
+            )
+            .is_err()
+        );
+        // Profiles with the same name but different rules don't share costs.
+        let slow = CostingProfile::from_toml("name = \"walk\"\nspeed = 4").unwrap();
+        let fast = CostingProfile::from_toml("name = \"walk\"\nspeed = 6").unwrap();
+        // Names don't depend on the platform or Rust release, so snapshots saved with them load.
+        assert_eq!(
+            profile_costing_model(slow.clone()).name(),
+            Some("profile walk 7e671d0f4338ce66")
+        );
+        assert_ne!(
+            profile_costing_model(slow).name(),
+            profile_costing_model(fast).name()
+        );
+    }
+
+    #[test]
+    fn profile_route_follows_its_rules() {
+        let graph = capitol_hill();
+        let route_between_steps = |toml: &str| {
+            route(
+                &graph,
+                &profile_costing_model(CostingProfile::from_toml(toml).unwrap()),
+                &CAPITOL_HILL_EAST_OF_STEPS,
+                &CAPITOL_HILL_WEST_OF_STEPS,
+            )
+        };
+        // Steps are slow, but still quicker than going round.
+        let walking = route_between_steps(WALKING_PROFILE);
+        assert!(walking.uses("highway", &["steps"]));
+        // A rule added at the end of the profile takes them off the map.
+        let no_steps = route_between_steps(&format!(
+            "{WALKING_PROFILE}\n[[way_rules]]\nmatch = {{ highway = \"steps\" }}\nimpassable = true\n"
+        ));
+        assert!(!no_steps.uses("highway", &["steps"]));
+        assert!(no_steps.result.route_cost_seconds() > walking.result.route_cost_seconds());
+    }
+
+    #[test]
+    fn profile_route_flat_penalty_depends_on_direction() {
+        let graph = capitol_hill();
+        // Turning onto the steps is worse than going round, but only one way along them.
+        let costing_model = profile_costing_model(
+            CostingProfile::from_toml(&format!(
+                "{WALKING_PROFILE}\n[[way_rules]]\nmatch = {{ highway = \"steps\" }}\ndirection = \"Forward\"\nflat_penalty_seconds = 600\n"
+            ))
+            .unwrap(),
+        );
+        let westward = route(
+            &graph,
+            &costing_model,
+            &CAPITOL_HILL_EAST_OF_STEPS,
+            &CAPITOL_HILL_WEST_OF_STEPS,
+        );
+        let eastward = route(
+            &graph,
+            &costing_model,
+            &CAPITOL_HILL_WEST_OF_STEPS,
+            &CAPITOL_HILL_EAST_OF_STEPS,
+        );
+        assert_ne!(
+            westward.uses("highway", &["steps"]),
+            eastward.uses("highway", &["steps"])
+        );
+    }
+}
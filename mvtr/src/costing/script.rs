@@ -0,0 +1,505 @@
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use rhai::{AST, Dynamic, Engine, Scope, module_resolvers::DummyModuleResolver};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    CostingModel, Tags, TransitionCostResult, TransitionToCost, Turn, WayCoster, stable_hash,
+    units::{Direction, PartsPerMillion, TravelSpeed},
+};
+use crate::graph::WayId;
+
+/// How long a script gets to cost a way or an intersection, unless told otherwise.
+const DEFAULT_TIME_LIMIT: Duration = Duration::from_millis(50);
+
+thread_local! {
+    /// When the script call running on this thread has to be stopped.
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/// A costing model that runs a Rhai script, for apps that want costing to be configurable without
+/// a rebuild. The script defines the same two functions, taking and returning the same shapes, as
+/// the demo app's JavaScript costing models:
+///
+/// ```rhai
+/// fn cost_way(tags) {
+///     let speed = if tags.highway == "motorway" { () } else { 1.4 };
+///     #{
+///         speed_forward_meters_per_second: speed,
+///         speed_reverse_meters_per_second: speed,
+///         time_penalty_fraction_forward: 0.0,
+///         time_penalty_fraction_reverse: 0.0,
+///     }
+/// }
+///
+/// fn cost_intersection(current_way_tags, transitions) {
+///     #{
+///         transition_costs: transitions.map(|t| #{ to_way_id: t.to_way_id, penalty_seconds: 5.0 }),
+///         continue_penalty: 0.0,
+///     }
+/// }
+/// ```
+///
+/// Tags are maps of strings, and a missing speed makes the way impassable in that direction. Each
+/// transition also has the `entry_bearing_forward`, `entry_bearing_reverse`,
+/// `exit_bearing_forward`, `exit_bearing_reverse` and `other_legs` of its `TransitionToCost`, or
+/// `()` where the geometry doesn't allow them, and its `turns`, each an `arriving` and `leaving`
+/// direction of `"Forward"` or `"Reverse"` and the turn's `angle`.
+/// Scripts can't reach the filesystem or other modules, and each call is stopped once it runs
+/// past the time limit. A call that fails or is stopped costs its way or intersection as
+/// impassable.
+pub struct ScriptCostingModel {
+    engine: Engine,
+    ast: AST,
+    name: String,
+    max_speed: Option<TravelSpeed>,
+    time_limit: Duration,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct ScriptTransition {
+    from_way_id: WayId,
+    from_way_tags: HashMap<String, String>,
+    to_way_id: WayId,
+    to_way_tags: HashMap<String, String>,
+    intersection_tags: HashMap<String, String>,
+    entry_bearing_forward: Option<f64>,
+    entry_bearing_reverse: Option<f64>,
+    exit_bearing_forward: Option<f64>,
+    exit_bearing_reverse: Option<f64>,
+    turns: Vec<Turn>,
+    other_legs: Option<u32>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ScriptTransitionCost {
+    to_way_id: u64,
+    penalty_seconds: f64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ScriptIntersectionCost {
+    transition_costs: Vec<ScriptTransitionCost>,
+    continue_penalty: Option<f64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ScriptWayCoster {
+    speed_forward_meters_per_second: Option<f64>,
+    speed_reverse_meters_per_second: Option<f64>,
+    time_penalty_fraction_forward: Option<f64>,
+    time_penalty_fraction_reverse: Option<f64>,
+}
+
+impl ScriptCostingModel {
+    /// Compiles `script`, which must define `cost_way(tags)` and
+    /// `cost_intersection(current_way_tags, transitions)`.
+    pub fn new(name: &str, script: &str) -> anyhow::Result<ScriptCostingModel> {
+        let engine = sandboxed_engine();
+        let ast = engine
+            .compile(script)
+            .map_err(|err| anyhow::anyhow!("Failed to compile costing script: {}", err))?;
+        for (function, params) in [("cost_way", 1), ("cost_intersection", 2)] {
+            if !ast
+                .iter_functions()
+                .any(|defined| defined.name == function && defined.params.len() == params)
+            {
+                return Err(anyhow::anyhow!(
+                    "Costing script doesn't define {} with {} parameters",
+                    function,
+                    params
+                ));
+            }
+        }
+        Ok(ScriptCostingModel {
+            engine,
+            ast,
+            name: format!("script {} {:016x}", name, stable_hash(script.as_bytes())),
+            max_speed: None,
+            time_limit: DEFAULT_TIME_LIMIT,
+        })
+    }
+
+    /// The fastest the script ever has anything travel, which lets searches be goal-directed.
+    pub fn with_max_speed(mut self, max_speed: TravelSpeed) -> ScriptCostingModel {
+        self.max_speed = Some(max_speed);
+        self
+    }
+
+    /// How long each call into the script may run before it's stopped.
+    pub fn with_time_limit(mut self, time_limit: Duration) -> ScriptCostingModel {
+        self.time_limit = time_limit;
+        self
+    }
+
+    /// Calls one of the script's functions, within the time limit. `None` if the call fails, runs
+    /// out of time or returns something of the wrong shape, which is logged.
+    fn call<Output: for<'de> Deserialize<'de>>(
+        &self,
+        function: &str,
+        args: impl rhai::FuncArgs,
+    ) -> Option<Output> {
+        DEADLINE.set(Some(Instant::now() + self.time_limit));
+        let result = self
+            .engine
+            .call_fn::<Dynamic>(&mut Scope::new(), &self.ast, function, args);
+        DEADLINE.set(None);
+        let output = match result {
+            Ok(output) => output,
+            Err(err) => {
+                tracing::warn!("Costing script's {} failed: {}", function, err);
+                return None;
+            }
+        };
+        // Going through JSON lets scripts write whole numbers where fractional ones are expected.
+        let output = rhai::serde::from_dynamic::<serde_json::Value>(&output)
+            .map_err(|err| err.to_string())
+            .and_then(|output| serde_json::from_value(output).map_err(|err| err.to_string()));
+        match output {
+            Ok(output) => Some(output),
+            Err(err) => {
+                tracing::warn!(
+                    "Costing script's {} output didn't match expected schema: {}",
+                    function,
+                    err
+                );
+                None
+            }
+        }
+    }
+}
+
+/// An engine that can't reach anything outside the script, and stops scripts that run past the
+/// deadline of the call they're in, or that grow without bound.
+fn sandboxed_engine() -> Engine {
+    let mut engine = Engine::new();
+    engine.set_module_resolver(DummyModuleResolver::new());
+    engine.disable_symbol("eval");
+    engine.set_max_call_levels(32);
+    engine.set_max_expr_depths(64, 32);
+    engine.set_max_string_size(10_000);
+    engine.set_max_array_size(10_000);
+    engine.set_max_map_size(10_000);
+    engine.on_print(|text| tracing::info!("Costing script: {}", text));
+    engine.on_debug(|text, _source, _position| tracing::debug!("Costing script: {}", text));
+    engine.on_progress(|_operations| {
+        DEADLINE
+            .get()
+            .filter(|deadline| Instant::now() > *deadline)
+            .map(|_| Dynamic::from("Costing script ran out of time"))
+    });
+    engine
+}
+
+fn tags_to_dynamic(tags: &Tags) -> Dynamic {
+    rhai::serde::to_dynamic(tags.to_hashmap()).unwrap_or(Dynamic::UNIT)
+}
+
+impl CostingModel for ScriptCostingModel {
+    fn cost_intersection(
+        &self,
+        current_way_tags: &Tags,
+        transitions_to_cost: &[TransitionToCost],
+    ) -> TransitionCostResult {
+        let transitions: Vec<ScriptTransition> = transitions_to_cost
+            .iter()
+            .map(|transition| ScriptTransition {
+                from_way_id: transition.from_way_id(),
+                from_way_tags: transition.from_way_tags.to_hashmap(),
+                to_way_id: transition.to_way_id(),
+                to_way_tags: transition.to_way_tags.to_hashmap(),
+                intersection_tags: transition.intersection_tags.to_hashmap(),
+                entry_bearing_forward: transition.entry_bearing(Direction::Forward),
+                entry_bearing_reverse: transition.entry_bearing(Direction::Reverse),
+                exit_bearing_forward: transition.exit_bearing(Direction::Forward),
+                exit_bearing_reverse: transition.exit_bearing(Direction::Reverse),
+                turns: transition.turns(),
+                other_legs: transition.other_legs(),
+            })
+            .collect();
+        let Ok(transitions) = rhai::serde::to_dynamic(transitions) else {
+            return TransitionCostResult::impassable();
+        };
+        let Some(output) = self.call::<ScriptIntersectionCost>(
+            "cost_intersection",
+            (tags_to_dynamic(current_way_tags), transitions),
+        ) else {
+            return TransitionCostResult::impassable();
+        };
+        let transition_costs = output
+            .transition_costs
+            .into_iter()
+            .map(|transition| {
+                (
+                    WayId::from_id(transition.to_way_id),
+                    transition.penalty_seconds,
+                )
+            })
+            .collect();
+        TransitionCostResult::from_transitions_and_costs_seconds(
+            &transition_costs,
+            output.continue_penalty,
+        )
+    }
+
+    fn cost_way(&self, tags: &Tags) -> WayCoster {
+        let Some(output) = self.call::<ScriptWayCoster>("cost_way", (tags_to_dynamic(tags),))
+        else {
+            return WayCoster::impassable();
+        };
+        WayCoster::from_speeds(
+            output
+                .speed_forward_meters_per_second
+                .map(TravelSpeed::from_meters_per_second),
+            output
+                .speed_reverse_meters_per_second
+                .map(TravelSpeed::from_meters_per_second),
+            output
+                .time_penalty_fraction_forward
+                .map(PartsPerMillion::from_fraction),
+            output
+                .time_penalty_fraction_reverse
+                .map(PartsPerMillion::from_fraction),
+        )
+    }
+
+    fn max_speed(&self) -> Option<TravelSpeed> {
+        self.max_speed
+    }
+
+    fn name(&self) -> Option<&str> {
+        Some(&self.name)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use crate::costing::{
+        CostingModel, TransitionToCost,
+        pedestrian::pedestrian_costing_model,
+        units::{Direction, TravelSpeed, TravelledDistance},
+    };
+    use crate::graph::{
+        WayId, WayTransition,
+        testing::{
+            CAPITOL_HILL_EAST_OF_STEPS, CAPITOL_HILL_WEST_OF_STEPS, capitol_hill, route, tags,
+            way_tags,
+        },
+    };
+
+    use super::ScriptCostingModel;
+
+    const WALKING_SCRIPT: &str = r#"
+        fn cost_way(tags) {
+            let speed = 1.4;
+            let penalty = if tags.highway == "footway" { 0.0 } else { 0.05 };
+            if ["trunk", "trunk_link", "motorway", "motorway_link"].contains(tags.highway) {
+                speed = ();
+            }
+            let uphill = if tags.incline == "up" { 0.5 } else { 0.0 };
+            #{
+                speed_forward_meters_per_second: speed,
+                speed_reverse_meters_per_second: speed,
+                time_penalty_fraction_forward: penalty + uphill,
+                time_penalty_fraction_reverse: penalty,
+            }
+        }
+
+        fn cost_intersection(current_way_tags, transitions) {
+            let costs = [];
+            for transition in transitions {
+                if transition.to_way_tags.highway == "steps" {
+                    continue;
+                }
+                let penalty = if transition.to_way_tags.footway == "crossing" { 10.0 } else { 0.0 };
+                costs.push(#{ to_way_id: transition.to_way_id, penalty_seconds: penalty });
+            }
+            #{ transition_costs: costs, continue_penalty: 1 }
+        }
+    "#;
+
+    /// A script that defines the costing functions around `cost_way_body`.
+    fn script(cost_way_body: &str) -> String {
+        format!(
+            "fn cost_way(tags) {{ {cost_way_body} }}\n\
+             fn cost_intersection(current_way_tags, transitions) {{ #{{ transition_costs: [] }} }}"
+        )
+    }
+
+    #[test]
+    fn script_way_and_intersection_costs() {
+        let costing_model = ScriptCostingModel::new("walking", WALKING_SCRIPT).unwrap();
+        let cost = |pairs: &[(&str, &str)], direction| {
+            costing_model
+                .cost_way(&tags(pairs))
+                .cost_way_segment(TravelledDistance(14_000), direction)
+                .map(|cost| cost.elapsed_equivalent().millis())
+        };
+        assert_eq!(
+            cost(&[("highway", "footway")], Direction::Forward),
+            Some(10_000)
+        );
+        assert_eq!(cost(&[("highway", "motorway")], Direction::Forward), None);
+        assert_eq!(
+            cost(
+                &[("highway", "footway"), ("incline", "up")],
+                Direction::Forward
+            ),
+            Some(15_000)
+        );
+        assert_eq!(
+            cost(
+                &[("highway", "footway"), ("incline", "up")],
+                Direction::Reverse
+            ),
+            Some(10_000)
+        );
+
+        let sidewalk = tags(&[("highway", "footway")]);
+        let crossing = tags(&[("highway", "footway"), ("footway", "crossing")]);
+        let steps = tags(&[("highway", "steps")]);
+        let intersection = tags(&[]);
+        let transitions: Vec<TransitionToCost> = [(2, &crossing), (3, &steps)]
+            .into_iter()
+            .map(|(to_way, to_way_tags)| TransitionToCost {
+                way_transition: WayTransition::new(WayId::from_id(1), 0, WayId::from_id(to_way), 0),
+                from_way_tags: &sidewalk,
+                to_way_tags,
+                intersection_tags: &intersection,
+                geometry: None,
+            })
+            .collect();
+        let result = costing_model.cost_intersection(&sidewalk, &transitions);
+        assert_eq!(result.transition_costs.len(), 1);
+        assert_eq!(
+            result.transition_costs[&WayId::from_id(2)]
+                .elapsed_equivalent()
+                .millis(),
+            10_000
+        );
+        assert_eq!(
+            result.continue_cost.unwrap().elapsed_equivalent().millis(),
+            1_000
+        );
+    }
+
+    #[test]
+    fn script_sandbox_and_time_limit() {
+        // Scripts can't load other code.
+        assert!(ScriptCostingModel::new("eval", &script("eval(\"1\")")).is_err());
+        let importing = ScriptCostingModel::new(
+            "import",
+            &format!("import \"costs\" as costs;\n{}", script("costs::way(tags)")),
+        )
+        .unwrap();
+        assert!(
+            importing
+                .cost_way(&tags(&[]))
+                .cost_way_segment(TravelledDistance(1), Direction::Forward)
+                .is_none()
+        );
+
+        // A script that never returns is stopped, and its way is impassable.
+        let endless = ScriptCostingModel::new("endless", &script("loop {}"))
+            .unwrap()
+            .with_time_limit(Duration::from_millis(20));
+        let started = Instant::now();
+        let way_coster = endless.cost_way(&tags(&[]));
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(
+            way_coster
+                .cost_way_segment(TravelledDistance(1), Direction::Forward)
+                .is_none()
+        );
+        // The deadline doesn't carry over to the next call.
+        let walking = ScriptCostingModel::new("walking", WALKING_SCRIPT).unwrap();
+        assert!(
+            walking
+                .cost_way(&tags(&[("highway", "footway")]))
+                .cost_way_segment(TravelledDistance(1), Direction::Forward)
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn script_time_limit_on_real_ways() {
+        let graph = capitol_hill();
+        // Gets stuck on steps with handrails on both sides, and costs other ways as usual.
+        let stalling = ScriptCostingModel::new(
+            "stalling",
+            &script(
+                "if tags.handrail == \"both\" { loop {} }\n\
+                 #{ speed_forward_meters_per_second: 1.4, speed_reverse_meters_per_second: 1.4 }",
+            ),
+        )
+        .unwrap()
+        .with_time_limit(Duration::from_millis(20));
+        let passable = |way| {
+            stalling
+                .cost_way(&way_tags(&graph, way))
+                .cost_way_segment(TravelledDistance(1), Direction::Forward)
+                .is_some()
+        };
+
+        assert!(way_tags(&graph, 1188542971).tag_is("handrail", "both"));
+        let started = Instant::now();
+        assert!(!passable(1188542971));
+        assert!(started.elapsed() < Duration::from_secs(1));
+        // Neither the steps without handrails nor the street were held up by it.
+        assert!(passable(1320436546));
+        assert!(passable(476409328));
+    }
+
+    #[test]
+    fn script_errors() {
+        assert!(ScriptCostingModel::new("syntax", "fn cost_way(tags) {").is_err());
+        assert!(ScriptCostingModel::new("missing", "fn cost_way(tags) { #{} }").is_err());
+        let wrong_shape = ScriptCostingModel::new(
+            "wrong shape",
+            &script("#{ speed_forward_meters_per_second: \"fast\" }"),
+        )
+        .unwrap();
+        assert!(
+            wrong_shape
+                .cost_way(&tags(&[]))
+                .cost_way_segment(TravelledDistance(1), Direction::Forward)
+                .is_none()
+        );
+        assert_ne!(
+            ScriptCostingModel::new("walking", WALKING_SCRIPT)
+                .unwrap()
+                .name(),
+            ScriptCostingModel::new("walking", &script("#{}"))
+                .unwrap()
+                .name()
+        );
+    }
+
+    #[test]
+    fn script_route_avoids_steps() {
+        let graph = capitol_hill();
+        let route_between_steps = |costing_model: &dyn CostingModel| {
+            route(
+                &graph,
+                costing_model,
+                &CAPITOL_HILL_EAST_OF_STEPS,
+                &CAPITOL_HILL_WEST_OF_STEPS,
+            )
+        };
+        // The script won't turn onto steps, which the pedestrian model happily climbs.
+        let scripted = route_between_steps(
+            &ScriptCostingModel::new("walking", WALKING_SCRIPT)
+                .unwrap()
+                .with_max_speed(TravelSpeed::from_meters_per_second(1.4)),
+        );
+        let walking = route_between_steps(&pedestrian_costing_model(1.4));
+        assert!(walking.uses("highway", &["steps"]));
+        assert!(!scripted.uses("highway", &["steps"]));
+    }
+}
@@ -0,0 +1,258 @@
+use super::{
+    Tags,
+    auto::{Access, access, cost_intersection, default_speed_kmh, oneway_allows, speed_limit},
+    base::{BoxedCostingModel, WayCost},
+    units::{Direction, Length, TravelSpeed, Weight},
+};
+
+/// Trucks are governed to this speed, whatever the limit for other traffic.
+const MAX_TRUCK_SPEED_KMH: f64 = 90.0;
+/// Extra cost, as a fraction of travel time, of ways only open to trucks with business there.
+const DESTINATION_ONLY_PENALTY: f64 = 2.0;
+/// Extra cost, as a fraction of travel time, of residential streets, which trucks keep off where
+/// they can.
+const RESIDENTIAL_PENALTY: f64 = 0.5;
+
+/// The vehicle classes trucks belong to, from most to least specific, as they appear in access,
+/// oneway and turn restriction keys.
+const TRUCK_CLASSES: [&str; 3] = ["hgv", "motor_vehicle", "vehicle"];
+
+/// The truck a route is for. Ways whose posted limits it exceeds can't be used.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TruckOptions {
+    height: Length,
+    width: Length,
+    length: Length,
+    weight: Weight,
+    axle_load: Weight,
+    hazmat: bool,
+}
+
+impl TruckOptions {
+    /// A typical tractor-trailer: 4.11 m high, 2.6 m wide, 21.64 m long, weighing 21.77 t with
+    /// 9.07 t on each axle, and not carrying hazardous materials.
+    pub fn new() -> TruckOptions {
+        TruckOptions {
+            height: Length::from_meters(4.11),
+            width: Length::from_meters(2.6),
+            length: Length::from_meters(21.64),
+            weight: Weight::from_tonnes(21.77),
+            axle_load: Weight::from_tonnes(9.07),
+            hazmat: false,
+        }
+    }
+
+    pub fn with_height(mut self, height: Length) -> TruckOptions {
+        self.height = height;
+        self
+    }
+
+    pub fn with_width(mut self, width: Length) -> TruckOptions {
+        self.width = width;
+        self
+    }
+
+    pub fn with_length(mut self, length: Length) -> TruckOptions {
+        self.length = length;
+        self
+    }
+
+    /// The truck's gross weight, including its load.
+    pub fn with_weight(mut self, weight: Weight) -> TruckOptions {
+        self.weight = weight;
+        self
+    }
+
+    /// The most weight on any one of the truck's axles.
+    pub fn with_axle_load(mut self, axle_load: Weight) -> TruckOptions {
+        self.axle_load = axle_load;
+        self
+    }
+
+    /// Whether the truck is carrying hazardous materials.
+    pub fn with_hazmat(mut self, hazmat: bool) -> TruckOptions {
+        self.hazmat = hazmat;
+        self
+    }
+
+    /// Whether the truck exceeds any of the physical limits posted on a way.
+    fn exceeds_limits(&self, tags: &Tags) -> bool {
+        let exceeds_length = |key: &str, dimension: Length| {
+            tags.get(key)
+                .and_then(Length::parse)
+                .is_some_and(|limit| dimension > limit)
+        };
+        let exceeds_weight = |key: &str, weight: Weight| {
+            tags.get(key)
+                .and_then(Weight::parse)
+                .is_some_and(|limit| weight > limit)
+        };
+        exceeds_length("maxheight", self.height)
+            || exceeds_length("maxwidth", self.width)
+            || exceeds_length("maxlength", self.length)
+            || exceeds_weight("maxweight", self.weight)
+            || exceeds_weight("maxaxleload", self.axle_load)
+    }
+}
+
+impl Default for TruckOptions {
+    fn default() -> Self {
+        TruckOptions::new()
+    }
+}
+
+pub fn truck_costing_model(options: TruckOptions) -> BoxedCostingModel {
+    BoxedCostingModel::boxed(
+        move |direction, tags| cost_way(&options, direction, tags),
+        |_tags, transitions_to_cost| cost_intersection(&TRUCK_CLASSES, transitions_to_cost),
+    )
+    .with_max_speed(TravelSpeed::from_kmh(MAX_TRUCK_SPEED_KMH))
+    .with_name(format!(
+        "truck {} m high, {} m wide, {} m long, {} t, {} t per axle{}",
+        options.height.meters(),
+        options.width.meters(),
+        options.length.meters(),
+        options.weight.tonnes(),
+        options.axle_load.tonnes(),
+        if options.hazmat { ", hazmat" } else { "" }
+    ))
+}
+
+fn cost_way(options: &TruckOptions, direction: Direction, tags: &Tags) -> Option<WayCost> {
+    let default_speed_kmh = if let Some(speed_kmh) = default_speed_kmh(tags) {
+        speed_kmh
+    } else if tags.tag_in("hgv", &["yes", "designated"]) {
+        // Not a road, but trucks are explicitly allowed on it.
+        10.0
+    } else {
+        return None;
+    };
+    if !oneway_allows(&TRUCK_CLASSES, direction, tags) || options.exceeds_limits(tags) {
+        return None;
+    }
+    if options.hazmat && tags.tag_in("hazmat", &["no", "destination"]) {
+        return None;
+    }
+    let access = access(&TRUCK_CLASSES, tags);
+    if access == Access::Denied {
+        return None;
+    }
+
+    let speed = tags
+        .get("maxspeed:hgv")
+        .and_then(TravelSpeed::parse)
+        .unwrap_or_else(|| speed_limit(direction, tags, default_speed_kmh, MAX_TRUCK_SPEED_KMH));
+    let mut cost = WayCost::from_speed(speed);
+    cost.limit_speed(TravelSpeed::from_kmh(MAX_TRUCK_SPEED_KMH));
+
+    if access == Access::DestinationOnly {
+        cost.add_penalty_ppm(DESTINATION_ONLY_PENALTY.into());
+    }
+    if tags.tag_in("highway", &["residential", "living_street"])
+        && !tags.tag_in("hgv", &["yes", "designated"])
+    {
+        cost.add_penalty_ppm(RESIDENTIAL_PENALTY.into());
+    }
+    Some(cost)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::costing::{
+        CostingModel, Tags,
+        units::{Direction, Length, TravelledDistance, Weight},
+    };
+
+    use super::{TruckOptions, truck_costing_model};
+
+    /// A primary road with `pairs` tagged.
+    fn primary_road(pairs: &[(&str, &str)]) -> Tags {
+        Tags::from_hashmap(
+            [("highway", "primary")]
+                .iter()
+                .chain(pairs)
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        )
+    }
+
+    /// Whether a truck with `options` can drive forward along a primary road with `pairs` tagged.
+    fn passable(options: TruckOptions, pairs: &[(&str, &str)]) -> bool {
+        truck_costing_model(options)
+            .cost_way(&primary_road(pairs))
+            .cost_way_segment(TravelledDistance(1_000), Direction::Forward)
+            .is_some()
+    }
+
+    #[test]
+    fn truck_maxheight() {
+        let truck = TruckOptions::new().with_height(Length::from_meters(4.0));
+        assert!(passable(truck, &[("maxheight", "4.5")]));
+        assert!(!passable(truck, &[("maxheight", "3.81")]));
+        assert!(!passable(truck, &[("maxheight", "12'6\"")]));
+        assert!(passable(truck, &[("maxheight", "13'6\"")]));
+        assert!(passable(truck, &[("maxheight", "default")]));
+    }
+
+    #[test]
+    fn truck_maxwidth_and_maxlength() {
+        let truck = TruckOptions::new()
+            .with_width(Length::from_meters(2.5))
+            .with_length(Length::from_meters(18.0));
+        assert!(!passable(truck, &[("maxwidth", "2.2 m")]));
+        assert!(passable(truck, &[("maxwidth", "3")]));
+        assert!(!passable(truck, &[("maxlength", "12")]));
+        assert!(passable(truck, &[("maxlength", "65 ft")]));
+    }
+
+    #[test]
+    fn truck_maxweight() {
+        let truck = TruckOptions::new().with_weight(Weight::from_tonnes(7.5));
+        assert!(!passable(truck, &[("maxweight", "3.5 t")]));
+        assert!(!passable(truck, &[("maxweight", "3500 kg")]));
+        assert!(passable(truck, &[("maxweight", "7.5")]));
+        assert!(passable(truck, &[("maxweight", "20000 lbs")]));
+        assert!(passable(
+            truck.with_weight(Weight::from_tonnes(3.0)),
+            &[("maxweight", "3.5 t")]
+        ));
+    }
+
+    #[test]
+    fn truck_maxaxleload() {
+        let truck = TruckOptions::new().with_axle_load(Weight::from_tonnes(10.0));
+        assert!(!passable(truck, &[("maxaxleload", "8 t")]));
+        assert!(passable(truck, &[("maxaxleload", "11.5")]));
+    }
+
+    #[test]
+    fn truck_hgv_access() {
+        let truck = TruckOptions::new();
+        assert!(!passable(truck, &[("hgv", "no")]));
+        assert!(passable(truck, &[("access", "no"), ("hgv", "yes")]));
+        assert!(!passable(truck, &[("motor_vehicle", "no")]));
+        assert!(passable(truck, &[("motorcar", "no")]));
+        let cost = |pairs: &[(&str, &str)]| {
+            truck_costing_model(truck)
+                .cost_way(&primary_road(pairs))
+                .cost_way_segment(TravelledDistance(1_000_000), Direction::Forward)
+                .unwrap()
+                .elapsed_equivalent()
+        };
+        assert!(cost(&[("hgv", "destination")]) > cost(&[]));
+        // Trucks are slower than the posted limit where they have their own.
+        assert!(cost(&[("maxspeed", "60"), ("maxspeed:hgv", "40")]) > cost(&[("maxspeed", "60")]));
+    }
+
+    #[test]
+    fn truck_hazmat() {
+        let truck = TruckOptions::new();
+        assert!(passable(truck, &[("hazmat", "no")]));
+        assert!(!passable(truck.with_hazmat(true), &[("hazmat", "no")]));
+        assert!(passable(truck.with_hazmat(true), &[("hazmat", "yes")]));
+        assert_ne!(
+            truck_costing_model(truck).name(),
+            truck_costing_model(truck.with_hazmat(true)).name()
+        );
+    }
+}
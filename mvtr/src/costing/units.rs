@@ -1,4 +1,4 @@
-use std::ops::{Add, Div, Mul};
+use std::ops::{Add, Div, Mul, Sub};
 
 use serde::{Deserialize, Serialize};
 
@@ -29,8 +29,16 @@ impl Add for TravelledDistance {
     }
 }
 
+impl Sub for TravelledDistance {
+    type Output = TravelledDistance;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        Self(self.0.saturating_sub(rhs.0))
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
-pub struct ElapsedTime(pub(super) u64);
+pub struct ElapsedTime(pub(crate) u64);
 
 impl ElapsedTime {
     pub fn millis(&self) -> u64 {
@@ -58,9 +66,17 @@ impl Add for ElapsedTime {
     }
 }
 
+impl Sub for ElapsedTime {
+    type Output = ElapsedTime;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        Self(self.0.saturating_sub(rhs.0))
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct TravelSpeed {
-    um_per_ms: u32,
+    pub(crate) um_per_ms: u32,
 }
 
 impl TravelSpeed {
@@ -77,6 +93,22 @@ impl TravelSpeed {
         }
     }
 
+    pub fn from_kmh(kmh: f64) -> TravelSpeed {
+        TravelSpeed::from_meters_per_second(kmh / 3.6)
+    }
+
+    /// Parses a speed as OSM tags it, such as `50`, `50 km/h`, `25 mph` or `10 knots`. Bare numbers
+    /// are in km/h. `None` for anything else, such as `none` or `signals`.
+    pub fn parse(value: &str) -> Option<TravelSpeed> {
+        let (number, unit) = split_number(value.trim())?;
+        match unit {
+            "" | "km/h" | "kmh" | "kph" => Some(TravelSpeed::from_kmh(number)),
+            "mph" => Some(TravelSpeed::from_mph(number)),
+            "knots" => Some(TravelSpeed::from_kmh(number * 1.852)),
+            _ => None,
+        }
+    }
+
     pub fn min(&self, other: &TravelSpeed) -> TravelSpeed {
         TravelSpeed {
             um_per_ms: self.um_per_ms.min(other.um_per_ms),
@@ -84,6 +116,89 @@ impl TravelSpeed {
     }
 }
 
+/// A physical dimension, such as a way's width or a kerb's height.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Length {
+    pub(crate) mm: u32,
+}
+
+impl Length {
+    pub fn from_meters(meters: f64) -> Length {
+        Length {
+            mm: (meters * 1000.0).round() as u32,
+        }
+    }
+
+    pub fn meters(&self) -> f64 {
+        self.mm as f64 / 1000.0
+    }
+
+    /// Parses a length as OSM tags it, such as `1.5`, `1.5 m`, `150 cm`, `5 ft` or `12'6"`. Bare
+    /// numbers are in meters. `None` for anything else, such as `default` or `below_default`.
+    pub fn parse(value: &str) -> Option<Length> {
+        let value = value.trim();
+        if let Some((feet, inches)) = value.split_once('\'') {
+            let feet: f64 = feet.trim().parse().ok()?;
+            let inches = inches.trim().trim_end_matches('"').trim();
+            let inches: f64 = if inches.is_empty() {
+                0.0
+            } else {
+                inches.parse().ok()?
+            };
+            return Some(Length::from_meters((feet * 12.0 + inches) * 0.0254));
+        }
+        let (number, unit) = split_number(value)?;
+        match unit {
+            "" | "m" => Some(Length::from_meters(number)),
+            "cm" => Some(Length::from_meters(number / 100.0)),
+            "mm" => Some(Length::from_meters(number / 1000.0)),
+            "ft" => Some(Length::from_meters(number * 0.3048)),
+            "in" | "\"" => Some(Length::from_meters(number * 0.0254)),
+            _ => None,
+        }
+    }
+}
+
+/// A vehicle's mass, or a limit on it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Weight {
+    pub(crate) kg: u32,
+}
+
+impl Weight {
+    pub fn from_tonnes(tonnes: f64) -> Weight {
+        Weight {
+            kg: (tonnes * 1000.0).round() as u32,
+        }
+    }
+
+    pub fn tonnes(&self) -> f64 {
+        self.kg as f64 / 1000.0
+    }
+
+    /// Parses a weight as OSM tags it, such as `3.5`, `3.5 t`, `3500 kg`, `8000 lbs` or `7 st` in
+    /// short tons. Bare numbers are in metric tonnes. `None` for anything else, such as `default`.
+    pub fn parse(value: &str) -> Option<Weight> {
+        let (number, unit) = split_number(value.trim())?;
+        match unit {
+            "" | "t" => Some(Weight::from_tonnes(number)),
+            "kg" => Some(Weight::from_tonnes(number / 1000.0)),
+            "lbs" | "lb" => Some(Weight::from_tonnes(number * 0.000_453_592_37)),
+            "st" => Some(Weight::from_tonnes(number * 0.907_184_74)),
+            _ => None,
+        }
+    }
+}
+
+/// Splits a tag value such as `3.5 t` into its number and unit.
+fn split_number(value: &str) -> Option<(f64, &str)> {
+    let unit_start = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(unit_start);
+    Some((number.parse().ok()?, unit.trim()))
+}
+
 impl Div<TravelSpeed> for TravelledDistance {
     type Output = Option<ElapsedTime>;
 
@@ -104,7 +219,7 @@ impl Div<TravelSpeed> for TravelledDistance {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
-pub struct PartsPerMillion(u32);
+pub struct PartsPerMillion(pub(crate) u32);
 
 impl PartsPerMillion {
     pub fn of(ppm: u32) -> PartsPerMillion {
@@ -147,3 +262,53 @@ impl From<f64> for PartsPerMillion {
         Self((value * 1_000_000.0).round() as u32)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{Length, TravelSpeed, Weight};
+
+    #[test]
+    fn parse_speeds() {
+        assert_eq!(TravelSpeed::parse("50"), Some(TravelSpeed::from_kmh(50.0)));
+        assert_eq!(
+            TravelSpeed::parse("50 km/h"),
+            Some(TravelSpeed::from_kmh(50.0))
+        );
+        assert_eq!(
+            TravelSpeed::parse("25 mph"),
+            Some(TravelSpeed::from_mph(25.0))
+        );
+        assert_eq!(
+            TravelSpeed::parse("25mph"),
+            Some(TravelSpeed::from_mph(25.0))
+        );
+        assert_eq!(TravelSpeed::parse("10 knots").unwrap().um_per_ms, 5144);
+        assert_eq!(TravelSpeed::parse("none"), None);
+        assert_eq!(TravelSpeed::parse("signals"), None);
+        assert_eq!(TravelSpeed::parse("25 furlongs"), None);
+    }
+
+    #[test]
+    fn parse_lengths() {
+        assert_eq!(Length::parse("1.5"), Some(Length::from_meters(1.5)));
+        assert_eq!(Length::parse("1.5 m"), Some(Length::from_meters(1.5)));
+        assert_eq!(Length::parse("150 cm"), Some(Length::from_meters(1.5)));
+        assert_eq!(Length::parse("10 ft"), Some(Length::from_meters(3.048)));
+        assert_eq!(Length::parse("10'0\""), Some(Length::from_meters(3.048)));
+        assert_eq!(Length::parse("12'6\""), Some(Length::from_meters(3.81)));
+        assert_eq!(Length::parse("12'"), Some(Length::from_meters(3.6576)));
+        assert_eq!(Length::parse("default"), None);
+        assert_eq!(Length::parse("3 cubits"), None);
+    }
+
+    #[test]
+    fn parse_weights() {
+        assert_eq!(Weight::parse("3.5"), Some(Weight::from_tonnes(3.5)));
+        assert_eq!(Weight::parse("3.5 t"), Some(Weight::from_tonnes(3.5)));
+        assert_eq!(Weight::parse("3500 kg"), Some(Weight::from_tonnes(3.5)));
+        assert_eq!(Weight::parse("8000 lbs"), Some(Weight { kg: 3629 }));
+        assert_eq!(Weight::parse("7 st"), Some(Weight { kg: 6350 }));
+        assert_eq!(Weight::parse("default"), None);
+        assert_eq!(Weight::parse("3 stone"), None);
+    }
+}
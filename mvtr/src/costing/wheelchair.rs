@@ -0,0 +1,408 @@
+use std::collections::HashMap;
+
+use super::{
+    RoutingCost, Tags, TransitionCostResult, TransitionToCost,
+    base::{BoxedCostingModel, WayCost},
+    units::{Direction, Length, TravelSpeed},
+};
+
+/// Ways narrower than this are too narrow to wheel along.
+const MIN_WIDTH_METERS: f64 = 0.9;
+/// The grade assumed of ways tagged only as sloping `up` or `down`.
+const UNKNOWN_INCLINE: f64 = 0.05;
+/// Extra cost, as a fraction of travel time, of ways only partly accessible by wheelchair.
+const LIMITED_ACCESS_PENALTY: f64 = 1.0;
+
+/// Surfaces a wheelchair can't cross at all.
+const IMPASSABLE_SURFACES: [&str; 7] = [
+    "sand",
+    "mud",
+    "grass",
+    "pebblestone",
+    "woodchips",
+    "rock",
+    "stepping_stones",
+];
+
+/// Barriers that can't be passed in a wheelchair. Kerbs are judged by their height instead.
+const HARD_BARRIERS: [&str; 14] = [
+    "wall",
+    "fence",
+    "hedge",
+    "ditch",
+    "retaining_wall",
+    "city_wall",
+    "stile",
+    "turnstile",
+    "full-height_turnstile",
+    "kissing_gate",
+    "cattle_grid",
+    "motorcycle_barrier",
+    "log",
+    "step",
+];
+
+/// How high a kerb is, from lowest to highest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Kerb {
+    Flush,
+    Lowered,
+    Rolled,
+    Raised,
+}
+
+impl Kerb {
+    /// The kerb a way or node has, from its `kerb` and `kerb:height` tags. A kerb of unknown
+    /// kind is taken to be raised.
+    fn from_tags(tags: &Tags) -> Option<Kerb> {
+        if let Some(height) = tags.get("kerb:height").and_then(Length::parse) {
+            return Some(match height.meters() {
+                height if height <= 0.01 => Kerb::Flush,
+                height if height <= 0.03 => Kerb::Lowered,
+                height if height <= 0.06 => Kerb::Rolled,
+                _ => Kerb::Raised,
+            });
+        }
+        match tags
+            .get("kerb")
+            .or_else(|| tags.tag_is("barrier", "kerb").then_some("yes"))?
+        {
+            "flush" | "no" => Some(Kerb::Flush),
+            "lowered" => Some(Kerb::Lowered),
+            "rolled" => Some(Kerb::Rolled),
+            _ => Some(Kerb::Raised),
+        }
+    }
+}
+
+/// Tunables for `wheelchair_costing_model`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WheelchairOptions {
+    speed_m_s: f64,
+    max_incline: f64,
+    kerb_tolerance: Kerb,
+}
+
+impl WheelchairOptions {
+    /// A wheelchair user travelling at `speed_m_s` on the level, who can manage grades up to 8%
+    /// and lowered kerbs.
+    pub fn new(speed_m_s: f64) -> WheelchairOptions {
+        WheelchairOptions {
+            speed_m_s,
+            max_incline: 0.08,
+            kerb_tolerance: Kerb::Lowered,
+        }
+    }
+
+    /// The steepest grade, as a fraction such as 0.05 for 5%, the user can go up or down.
+    pub fn with_max_incline(mut self, max_incline: f64) -> WheelchairOptions {
+        self.max_incline = max_incline.abs();
+        self
+    }
+
+    /// The highest kerb the user can get over.
+    pub fn with_kerb_tolerance(mut self, kerb_tolerance: Kerb) -> WheelchairOptions {
+        self.kerb_tolerance = kerb_tolerance;
+        self
+    }
+
+    /// Whether a way or node's tags put something in the way the user can't get past.
+    fn blocked_by(&self, tags: &Tags) -> bool {
+        tags.tag_in("barrier", &HARD_BARRIERS)
+            || Kerb::from_tags(tags).is_some_and(|kerb| kerb > self.kerb_tolerance)
+    }
+}
+
+pub fn wheelchair_costing_model(options: WheelchairOptions) -> BoxedCostingModel {
+    BoxedCostingModel::boxed(
+        move |direction, tags| cost_way(&options, direction, tags),
+        move |_tags, transitions_to_cost| cost_intersection(&options, transitions_to_cost),
+    )
+    .with_max_speed(TravelSpeed::from_meters_per_second(options.speed_m_s))
+    .with_name(format!(
+        "wheelchair {} m/s, max incline {}, kerb tolerance {:?}",
+        options.speed_m_s, options.max_incline, options.kerb_tolerance
+    ))
+}
+
+fn cost_way(options: &WheelchairOptions, direction: Direction, tags: &Tags) -> Option<WayCost> {
+    if tags.tag_is("wheelchair", "no")
+        || tags.tag_in("foot", &["no", "use_sidepath"])
+        || tags.tag_in("access", &["no", "private"])
+        || tags.tag_in(
+            "highway",
+            &["motorway", "motorway_link", "trunk", "trunk_link"],
+        )
+    {
+        return None;
+    }
+    // Steps can't be wheeled up or down, however few there are, unless there's a ramp beside them.
+    if tags.tag_is("highway", "steps") && !tags.tag_is("ramp:wheelchair", "yes") {
+        return None;
+    }
+    if options.blocked_by(tags) || tags.tag_in("surface", &IMPASSABLE_SURFACES) {
+        return None;
+    }
+    if tags
+        .get("width")
+        .and_then(Length::parse)
+        .is_some_and(|width| width.meters() < MIN_WIDTH_METERS)
+    {
+        return None;
+    }
+
+    let mut speed_factor = surface_speed_factor(tags);
+    if let Some(incline) = tags.get("incline").and_then(parse_incline) {
+        let climb = match direction {
+            Direction::Forward => incline,
+            Direction::Reverse => -incline,
+        };
+        if climb.abs() > options.max_incline {
+            return None;
+        }
+        // Climbing is hard work, and descending needs care, the more so the closer the grade is to
+        // the user's limit.
+        let effort = climb.abs() / options.max_incline.max(f64::EPSILON);
+        speed_factor *= if climb > 0.0 {
+            1.0 - 0.5 * effort
+        } else {
+            1.0 - 0.2 * effort
+        };
+    }
+
+    let mut cost = WayCost::from_speed(TravelSpeed::from_meters_per_second(
+        options.speed_m_s * speed_factor,
+    ));
+    if tags.tag_is("wheelchair", "limited") {
+        cost.add_penalty_ppm(LIMITED_ACCESS_PENALTY.into());
+    }
+    Some(cost)
+}
+
+/// The grade of a way, positive uphill in its forward direction, from an `incline` such as `8%`,
+/// `-3%`, `5°` or `up`.
+fn parse_incline(incline: &str) -> Option<f64> {
+    let incline = incline.trim();
+    match incline {
+        "up" => return Some(UNKNOWN_INCLINE),
+        "down" => return Some(-UNKNOWN_INCLINE),
+        _ => {}
+    }
+    if let Some(percent) = incline.strip_suffix('%') {
+        return Some(percent.trim().parse::<f64>().ok()? / 100.0);
+    }
+    if let Some(degrees) = incline.strip_suffix('°') {
+        return Some(degrees.trim().parse::<f64>().ok()?.to_radians().tan());
+    }
+    None
+}
+
+/// How much a surface slows a wheelchair, from 1 on smooth pavement.
+fn surface_speed_factor(tags: &Tags) -> f64 {
+    match tags.get("surface") {
+        Some("paving_stones") | Some("concrete:plates") | Some("wood") => 0.9,
+        Some("compacted") | Some("fine_gravel") => 0.7,
+        Some("sett")
+        | Some("cobblestone")
+        | Some("unhewn_cobblestone")
+        | Some("gravel")
+        | Some("dirt")
+        | Some("earth")
+        | Some("ground")
+        | Some("unpaved") => 0.5,
+        _ => 1.0,
+    }
+}
+
+/// Every transition is free, except through a node with a barrier or a kerb the user can't get
+/// past, which can't be passed at all, including to continue along the same way.
+fn cost_intersection(
+    options: &WheelchairOptions,
+    transitions_to_cost: &[TransitionToCost],
+) -> TransitionCostResult {
+    if transitions_to_cost
+        .iter()
+        .any(|transition| options.blocked_by(transition.intersection_tags))
+    {
+        return TransitionCostResult::impassable();
+    }
+    let transition_costs: HashMap<_, _> = transitions_to_cost
+        .iter()
+        .map(|transition| (transition.to_way_id(), RoutingCost::zero()))
+        .collect();
+    TransitionCostResult {
+        transition_costs,
+        continue_cost: Some(RoutingCost::zero()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::costing::{
+        CostingModel, Tags, TransitionToCost, WayCoster,
+        pedestrian::pedestrian_costing_model,
+        units::{Direction, TravelledDistance},
+    };
+    use crate::graph::{
+        WayId, WayTransition,
+        testing::{
+            CAPITOL_HILL_EAST_OF_STEPS, CAPITOL_HILL_WEST_OF_STEPS, capitol_hill, route, tags,
+            way_tags,
+        },
+    };
+
+    use super::{Kerb, WheelchairOptions, wheelchair_costing_model};
+
+    /// The cost equivalent, in seconds, of wheeling a hundred meters of a way in `direction`.
+    fn cost_seconds(way_coster: &WayCoster, direction: Direction) -> Option<f64> {
+        way_coster
+            .cost_way_segment(TravelledDistance(100_000), direction)
+            .map(|cost| cost.elapsed_equivalent().millis() as f64 / 1000.0)
+    }
+
+    #[test]
+    fn wheelchair_way_costs() {
+        let costing_model = wheelchair_costing_model(WheelchairOptions::new(1.0));
+        let cost = |pairs: &[(&str, &str)], direction| {
+            cost_seconds(&costing_model.cost_way(&tags(pairs)), direction)
+        };
+
+        assert_eq!(
+            cost(&[("highway", "footway")], Direction::Forward),
+            Some(100.0)
+        );
+        assert!(cost(&[("highway", "steps")], Direction::Forward).is_none());
+        assert!(
+            cost(
+                &[("highway", "steps"), ("ramp:wheelchair", "yes")],
+                Direction::Forward
+            )
+            .is_some()
+        );
+        let footway_with = |pair| cost(&[("highway", "footway"), pair], Direction::Forward);
+        assert!(footway_with(("wheelchair", "no")).is_none());
+        assert!(footway_with(("wheelchair", "limited")).unwrap() > 100.0);
+        assert!(footway_with(("surface", "sand")).is_none());
+        assert!(footway_with(("surface", "sett")).unwrap() > 100.0);
+        assert!(footway_with(("width", "0.6")).is_none());
+        assert!(footway_with(("width", "10'0\"")).is_some());
+        assert!(footway_with(("barrier", "stile")).is_none());
+
+        // Uphill is slower than downhill, and too steep is impassable either way.
+        let sloped = [("highway", "footway"), ("incline", "5%")];
+        let uphill = cost(&sloped, Direction::Forward).unwrap();
+        let downhill = cost(&sloped, Direction::Reverse).unwrap();
+        assert!(uphill > downhill && downhill > 100.0);
+        let steep = [("highway", "footway"), ("incline", "-12%")];
+        assert!(cost(&steep, Direction::Forward).is_none());
+        assert!(cost(&steep, Direction::Reverse).is_none());
+        let mountaineer =
+            wheelchair_costing_model(WheelchairOptions::new(1.0).with_max_incline(0.15));
+        assert!(cost_seconds(&mountaineer.cost_way(&tags(&steep)), Direction::Forward).is_some());
+    }
+
+    #[test]
+    fn wheelchair_kerbs() {
+        let crossing = tags(&[("highway", "footway"), ("footway", "crossing")]);
+        let road = tags(&[("highway", "residential")]);
+        let kerbs = [
+            tags(&[("kerb", "lowered")]),
+            tags(&[("kerb", "raised")]),
+            tags(&[("kerb:height", "4 cm")]),
+        ];
+        // Whether the crossing can be turned onto at a node with `kerb`, and whether a crossing
+        // way with that kerb can be travelled.
+        let passable = |kerb_tolerance, kerb: &Tags| {
+            let costing_model = wheelchair_costing_model(
+                WheelchairOptions::new(1.0).with_kerb_tolerance(kerb_tolerance),
+            );
+            let transitions = [TransitionToCost {
+                way_transition: WayTransition::new(WayId::from_id(1), 0, WayId::from_id(2), 0),
+                from_way_tags: &road,
+                to_way_tags: &crossing,
+                intersection_tags: kerb,
+                geometry: None,
+            }];
+            let result = costing_model.cost_intersection(&road, &transitions);
+            let at_node = result.transition_costs.contains_key(&WayId::from_id(2));
+            assert_eq!(at_node, result.continue_cost.is_some());
+            let mut crossing_with_kerb = crossing.to_hashmap();
+            crossing_with_kerb.extend(kerb.to_hashmap());
+            let on_way = costing_model
+                .cost_way(&Tags::from_hashmap(crossing_with_kerb))
+                .cost_way_segment(TravelledDistance(1), Direction::Forward)
+                .is_some();
+            assert_eq!(at_node, on_way);
+            at_node
+        };
+
+        assert_eq!(
+            kerbs
+                .iter()
+                .map(|kerb| passable(Kerb::Lowered, kerb))
+                .collect::<Vec<_>>(),
+            vec![true, false, false]
+        );
+        assert_eq!(
+            kerbs
+                .iter()
+                .map(|kerb| passable(Kerb::Rolled, kerb))
+                .collect::<Vec<_>>(),
+            vec![true, false, true]
+        );
+        assert!(passable(Kerb::Raised, &kerbs[1]));
+        assert!(!passable(Kerb::Raised, &tags(&[("barrier", "turnstile")])));
+    }
+
+    #[test]
+    fn wheelchair_kerbs_on_real_crossings() {
+        let graph = capitol_hill();
+        // Footways leading onto crossings, one with a kerb of unknown height, which is taken to
+        // be raised, one with a lowered kerb and one flush with the road.
+        let crossings = [432755336, 668196632, 668196631];
+        for (way, kerb) in crossings.iter().zip(["yes", "lowered", "flush"]) {
+            assert!(way_tags(&graph, *way).tag_is("kerb", kerb));
+        }
+        let passable = |kerb_tolerance| {
+            let costing_model = wheelchair_costing_model(
+                WheelchairOptions::new(1.0).with_kerb_tolerance(kerb_tolerance),
+            );
+            crossings.map(|way| {
+                cost_seconds(
+                    &costing_model.cost_way(&way_tags(&graph, way)),
+                    Direction::Forward,
+                )
+                .is_some()
+            })
+        };
+        assert_eq!(passable(Kerb::Flush), [false, false, true]);
+        assert_eq!(passable(Kerb::Lowered), [false, true, true]);
+        assert_eq!(passable(Kerb::Raised), [true, true, true]);
+    }
+
+    #[test]
+    fn wheelchair_route_avoids_steps() {
+        let graph = capitol_hill();
+        let route_between_steps = |costing_model: &dyn CostingModel| {
+            route(
+                &graph,
+                costing_model,
+                &CAPITOL_HILL_EAST_OF_STEPS,
+                &CAPITOL_HILL_WEST_OF_STEPS,
+            )
+        };
+        let walking = route_between_steps(&pedestrian_costing_model(1.0));
+        let wheeling = route_between_steps(&wheelchair_costing_model(WheelchairOptions::new(1.0)));
+        assert!(walking.uses("highway", &["steps"]));
+        assert!(!wheeling.uses("highway", &["steps"]));
+        assert!(wheeling.result.route_distance_meters() > walking.result.route_distance_meters());
+        // Wheelchair users can get over more, but never less, than those with a lower tolerance.
+        let nimble = route_between_steps(&wheelchair_costing_model(
+            WheelchairOptions::new(1.0)
+                .with_max_incline(0.2)
+                .with_kerb_tolerance(Kerb::Raised),
+        ));
+        assert!(!nimble.uses("highway", &["steps"]));
+        assert!(nimble.result.route_cost_seconds() <= wheeling.result.route_cost_seconds());
+    }
+}
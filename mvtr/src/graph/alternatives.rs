@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+
+use crate::costing::{
+    CostingModel, RoutingCost,
+    units::{ElapsedTime, PartsPerMillion},
+};
+
+use super::{Costing, Frontier, Graph, SearchResult, SearchState, WayId};
+
+/// Alternatives may cost at most this much more than the optimal route, as a fraction of its cost.
+const ALTERNATIVE_MAX_STRETCH: f64 = 0.25;
+/// Alternatives may share at most this fraction of their distance with any route already chosen.
+const ALTERNATIVE_MAX_OVERLAP: f64 = 0.6;
+/// Added to the penalty on every way of each route found, as a fraction of the way's travel cost.
+const ALTERNATIVE_WAY_PENALTY: f64 = 0.5;
+/// How many penalized searches to run for each route asked for before giving up on finding more.
+const ALTERNATIVE_SEARCHES_PER_ROUTE: usize = 4;
+
+/// The stretches of each way a route travels along, as ordered pairs of distances along the way.
+type TravelledIntervals = HashMap<WayId, Vec<(i32, i32)>>;
+
+impl Graph {
+    /// Searches for up to `max_routes` routes between two points: the optimal route first, then
+    /// alternatives found by searching again with the ways of every route so far penalized.
+    /// Alternatives that cost too much more than the optimal route, or overlap too much with a
+    /// route already chosen, are discarded.
+    pub fn search_alternatives(
+        &self,
+        costing_model: &dyn CostingModel,
+        start: WayId,
+        distance_along_start_mm: i32,
+        end: WayId,
+        distance_along_end_mm: i32,
+        max_routes: usize,
+    ) -> Vec<SearchResult> {
+        let costing = if let Ok(costing) = self.costing(costing_model) {
+            costing
+        } else {
+            return Vec::new();
+        };
+        let mut routes: Vec<(Vec<SearchState>, TravelledIntervals)> = Vec::new();
+        let mut way_penalties: HashMap<WayId, PartsPerMillion> = HashMap::new();
+        let mut optimal_cost: Option<RoutingCost> = None;
+        for _ in 0..max_routes * ALTERNATIVE_SEARCHES_PER_ROUTE {
+            if routes.len() >= max_routes {
+                break;
+            }
+            let states = if let Some(states) = self
+                .search_djikstra_inner(
+                    &costing,
+                    start,
+                    distance_along_start_mm,
+                    end,
+                    distance_along_end_mm,
+                    Frontier::new(|_| ElapsedTime::zero())
+                        .with_way_penalties(way_penalties.clone()),
+                )
+                .and_then(|states| self.recost_route(&costing, states))
+            {
+                states
+            } else {
+                break;
+            };
+            let intervals = travelled_intervals(&states);
+            for way in intervals.keys() {
+                let penalty = way_penalties.entry(*way).or_default();
+                *penalty = *penalty + PartsPerMillion::from_fraction(ALTERNATIVE_WAY_PENALTY);
+            }
+
+            let cost = if let Some(state) = states.last() {
+                state.cost
+            } else {
+                break;
+            };
+            let optimal_cost = if let Some(optimal_cost) = optimal_cost {
+                optimal_cost
+            } else {
+                optimal_cost = Some(cost);
+                routes.push((states, intervals));
+                continue;
+            };
+            let stretch = cost.elapsed_equivalent().millis() as f64
+                / optimal_cost.elapsed_equivalent().millis().max(1) as f64
+                - 1.0;
+            let distance_mm = cost.distance().mm().max(1) as f64;
+            let distinct = routes.iter().all(|(_, other_intervals)| {
+                shared_distance_mm(&intervals, other_intervals) as f64 / distance_mm
+                    <= ALTERNATIVE_MAX_OVERLAP
+            });
+            if stretch <= ALTERNATIVE_MAX_STRETCH && distinct {
+                routes.push((states, intervals));
+            }
+        }
+        routes
+            .iter()
+            .filter_map(|(states, _)| self.build_search_result(states))
+            .collect()
+    }
+
+    /// Recomputes the cost of each state of a route from the graph's own costs, dropping any
+    /// penalties the search that found it applied.
+    fn recost_route(
+        &self,
+        costing: &Costing,
+        mut states: Vec<SearchState>,
+    ) -> Option<Vec<SearchState>> {
+        for idx in 1..states.len() {
+            let previous = states[idx - 1];
+            let state = states[idx];
+            let travel_cost = self.cost_along_way(
+                costing,
+                &previous.node.way,
+                previous.node.distance_along_way_mm,
+                state.via.distance_along_way_mm,
+                previous.entering,
+            )?;
+            // The step that finishes a route doesn't take a transition.
+            let transition_cost = self
+                .costed_transitions(costing, &state.via)
+                .into_iter()
+                .find(|(_, transition)| {
+                    transition.to_way_id == state.node.way
+                        && transition.transition_to_distance_along_way_mm
+                            == state.node.distance_along_way_mm
+                })
+                .map(|(costed, _)| costed.cost)
+                .unwrap_or(RoutingCost::zero());
+            states[idx].cost = previous.cost + travel_cost + transition_cost;
+        }
+        Some(states)
+    }
+}
+
+fn travelled_intervals(states: &[SearchState]) -> TravelledIntervals {
+    let mut intervals = TravelledIntervals::new();
+    for window in states.windows(2) {
+        let from = window[0].node.distance_along_way_mm;
+        let to = window[1].via.distance_along_way_mm;
+        if from != to {
+            intervals
+                .entry(window[0].node.way)
+                .or_default()
+                .push((from.min(to), from.max(to)));
+        }
+    }
+    intervals
+}
+
+/// The distance travelled by both routes along the same ways.
+fn shared_distance_mm(intervals: &TravelledIntervals, other: &TravelledIntervals) -> i64 {
+    let mut shared = 0;
+    for (way, way_intervals) in intervals {
+        for other_interval in other.get(way).iter().copied().flatten() {
+            for interval in way_intervals {
+                let overlap = interval.1.min(other_interval.1) - interval.0.max(other_interval.0);
+                shared += overlap.max(0) as i64;
+            }
+        }
+    }
+    shared
+}
+
+#[cfg(test)]
+mod test {
+    use geo::Coord;
+
+    use crate::costing::pedestrian::pedestrian_costing_model;
+
+    use super::super::Graph;
+    use super::ALTERNATIVE_MAX_STRETCH;
+
+    #[test]
+    fn search_alternatives_basic() {
+        let costing_model = pedestrian_costing_model(1.4);
+        let graph = Graph::new();
+        graph
+            .ingest_tile(
+                2625,
+                5721,
+                14,
+                include_bytes!("../../testdata/tile.pbf").to_vec(),
+                include_bytes!("../../testdata/tile.pbf").to_vec(),
+            )
+            .expect("Failed to ingest tile");
+        let (from_way_id, from_way_distance) = graph
+            .nearest_way(
+                &costing_model,
+                &Coord {
+                    x: -122.3126740,
+                    y: 47.6153470,
+                },
+            )
+            .unwrap();
+        let (to_way_id, to_way_distance) = graph
+            .nearest_way(
+                &costing_model,
+                &Coord {
+                    x: -122.315503,
+                    y: 47.6163794,
+                },
+            )
+            .unwrap();
+
+        let optimal = graph
+            .search_djikstra(
+                &costing_model,
+                from_way_id,
+                from_way_distance,
+                to_way_id,
+                to_way_distance,
+            )
+            .expect("Couldn't find a route.");
+        let routes = graph.search_alternatives(
+            &costing_model,
+            from_way_id,
+            from_way_distance,
+            to_way_id,
+            to_way_distance,
+            3,
+        );
+        assert!(routes.len() > 1);
+        assert_eq!(routes[0], optimal);
+        for route in &routes[1..] {
+            assert_ne!(route.encoded_polyline(), optimal.encoded_polyline());
+            assert!(
+                route.route_cost_seconds()
+                    <= optimal.route_cost_seconds() * (1.0 + ALTERNATIVE_MAX_STRETCH)
+            );
+        }
+    }
+}
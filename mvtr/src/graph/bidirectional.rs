@@ -0,0 +1,555 @@
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use crate::costing::{CostingModel, RoutingCost};
+
+use super::{Costing, Graph, SearchNode, SearchResult, SearchState, WayId};
+
+/// A single step of the search: travel along a way to the node `via`, then take a transition to
+/// `to`. Steps that finish the route have `via` and `to` both set to the destination.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct SearchEdge {
+    pub(super) via: SearchNode,
+    pub(super) to: SearchNode,
+    /// Whether the step leaves the route entering `to`'s way, as `Graph::enters`.
+    pub(super) enters: bool,
+    pub(super) cost: RoutingCost,
+}
+
+impl Graph {
+    /// Finds the same route as `search_djikstra`, but searches forward from the start and backward
+    /// from the end at the same time, stopping once the two searches can no longer improve on the
+    /// best route through a node they've both reached.
+    pub fn search_bidirectional(
+        &self,
+        costing_model: &dyn CostingModel,
+        start: WayId,
+        distance_along_start_mm: i32,
+        end: WayId,
+        distance_along_end_mm: i32,
+    ) -> Option<SearchResult> {
+        let start_node = SearchNode {
+            way: start,
+            distance_along_way_mm: distance_along_start_mm,
+        };
+        let end_node = SearchNode {
+            way: end,
+            distance_along_way_mm: distance_along_end_mm,
+        };
+        let costing = self.costing(costing_model).ok()?;
+        let states = self.search_bidirectional_inner(&costing, start_node, end_node)?;
+        self.build_search_result(&states)
+    }
+
+    fn search_bidirectional_inner(
+        &self,
+        costing: &Costing,
+        start_node: SearchNode,
+        end_node: SearchNode,
+    ) -> Option<Vec<SearchState>> {
+        let initial_state = |idx: usize, node: SearchNode, entering: bool| SearchState {
+            previous: idx,
+            idx,
+            node,
+            via: node,
+            entering,
+            cost: RoutingCost::zero(),
+            priority: RoutingCost::zero(),
+        };
+        // Backward states point at their successor with `previous`, and their `via` is the node on
+        // their own way that leads to it. Their cost is the cost to reach the end, and whether
+        // they're `entering` is whether that cost includes their way's entry penalty. Either can
+        // reach the end itself.
+        let mut forward_log = vec![initial_state(0, start_node, false)];
+        let mut backward_log = vec![
+            initial_state(0, end_node, false),
+            initial_state(1, end_node, true),
+        ];
+        let mut forward_frontier = BinaryHeap::from(forward_log.clone());
+        let mut backward_frontier = BinaryHeap::from(backward_log.clone());
+        let mut forward_costs: HashMap<(SearchNode, bool), (RoutingCost, usize)> =
+            HashMap::from([((start_node, false), (RoutingCost::zero(), 0))]);
+        let mut backward_costs: HashMap<(SearchNode, bool), (RoutingCost, usize)> =
+            HashMap::from([
+                ((end_node, false), (RoutingCost::zero(), 0)),
+                ((end_node, true), (RoutingCost::zero(), 1)),
+            ]);
+        let mut forward_settled = HashSet::new();
+        let mut backward_settled = HashSet::new();
+
+        // The cheapest complete route found so far, as indexes into the forward and backward logs.
+        let mut best: Option<(RoutingCost, usize, usize)> = if start_node == end_node {
+            Some((RoutingCost::zero(), 0, 0))
+        } else {
+            None
+        };
+
+        loop {
+            Self::discard_settled(&mut forward_frontier, &forward_settled);
+            Self::discard_settled(&mut backward_frontier, &backward_settled);
+            let forward_min = forward_frontier.peek().map(|state| state.cost);
+            let backward_min = backward_frontier.peek().map(|state| state.cost);
+            if forward_min.is_none() && backward_min.is_none() {
+                break;
+            }
+            if let Some((best_cost, _, _)) = best {
+                // Any route that hasn't been found yet is at least this expensive.
+                let lower_bound = forward_min.unwrap_or(RoutingCost::zero())
+                    + backward_min.unwrap_or(RoutingCost::zero());
+                if lower_bound >= best_cost {
+                    break;
+                }
+            } else if forward_min.is_none() || backward_min.is_none() {
+                // One side ran out of nodes without meeting the other, so there's no route.
+                break;
+            }
+
+            let expand_forward = match (forward_min, backward_min) {
+                (Some(forward_min), Some(backward_min)) => forward_min <= backward_min,
+                (forward_min, _) => forward_min.is_some(),
+            };
+            if expand_forward {
+                let state = forward_frontier.pop()?;
+                forward_settled.insert((state.node, state.entering));
+                if state.node == end_node {
+                    continue;
+                }
+                for edge in
+                    self.forward_edges(costing, &state.node, state.entering, Some(&end_node))
+                {
+                    let new_state = SearchState {
+                        previous: state.idx,
+                        idx: forward_log.len(),
+                        node: edge.to,
+                        via: edge.via,
+                        entering: edge.enters,
+                        cost: state.cost + edge.cost,
+                        priority: state.cost + edge.cost,
+                    };
+                    let label = (new_state.node, new_state.entering);
+                    if forward_costs
+                        .get(&label)
+                        .is_some_and(|(cost, _)| *cost <= new_state.cost)
+                    {
+                        continue;
+                    }
+                    forward_costs.insert(label, (new_state.cost, new_state.idx));
+                    forward_log.push(new_state);
+                    forward_frontier.push(new_state);
+                    if let Some((backward_cost, backward_idx)) = backward_costs.get(&label) {
+                        let route_cost = new_state.cost + *backward_cost;
+                        if best.is_none_or(|(best_cost, _, _)| route_cost < best_cost) {
+                            best = Some((route_cost, new_state.idx, *backward_idx));
+                        }
+                    }
+                }
+            } else {
+                let state = backward_frontier.pop()?;
+                backward_settled.insert((state.node, state.entering));
+                for (from, entering, edge) in self.backward_edges(
+                    costing,
+                    &state.node,
+                    state.entering,
+                    &start_node,
+                    &end_node,
+                ) {
+                    let new_state = SearchState {
+                        previous: state.idx,
+                        idx: backward_log.len(),
+                        node: from,
+                        via: edge.via,
+                        entering,
+                        cost: state.cost + edge.cost,
+                        priority: state.cost + edge.cost,
+                    };
+                    let label = (new_state.node, new_state.entering);
+                    if backward_costs
+                        .get(&label)
+                        .is_some_and(|(cost, _)| *cost <= new_state.cost)
+                    {
+                        continue;
+                    }
+                    backward_costs.insert(label, (new_state.cost, new_state.idx));
+                    backward_log.push(new_state);
+                    backward_frontier.push(new_state);
+                    if let Some((forward_cost, forward_idx)) = forward_costs.get(&label) {
+                        let route_cost = *forward_cost + new_state.cost;
+                        if best.is_none_or(|(best_cost, _, _)| route_cost < best_cost) {
+                            best = Some((route_cost, *forward_idx, new_state.idx));
+                        }
+                    }
+                }
+            }
+        }
+
+        let (_, forward_idx, backward_idx) = best?;
+        let mut states = self.unwind_route(&forward_log, forward_idx);
+        let mut backward_state = backward_log[backward_idx];
+        while backward_state.previous != backward_state.idx {
+            let successor = backward_log[backward_state.previous];
+            let previous_cost = states.last()?.cost;
+            let cost = previous_cost + (backward_state.cost - successor.cost);
+            states.push(SearchState {
+                previous: states.len() - 1,
+                idx: states.len(),
+                node: successor.node,
+                via: backward_state.via,
+                entering: successor.entering,
+                cost,
+                priority: cost,
+            });
+            backward_state = successor;
+        }
+        Some(states)
+    }
+
+    fn discard_settled(
+        frontier: &mut BinaryHeap<SearchState>,
+        settled: &HashSet<(SearchNode, bool)>,
+    ) {
+        while frontier
+            .peek()
+            .is_some_and(|state| settled.contains(&(state.node, state.entering)))
+        {
+            frontier.pop();
+        }
+    }
+
+    /// Sorted and deduplicated positions of the intersections along a way.
+    pub(super) fn node_distances(&self, way: &WayId) -> Vec<i32> {
+        let mut distances: Vec<i32> = self
+            .nodes_read
+            .get(way)
+            .iter()
+            .flatten()
+            .map(|node| node.distance_along_way_mm)
+            .collect();
+        distances.sort();
+        distances.dedup();
+        distances
+    }
+
+    /// Every position on a way that a search can be at: the start, and wherever a transition
+    /// lands.
+    fn landing_distances(&self, way: &WayId, start_node: &SearchNode) -> Vec<i32> {
+        let mut distances: Vec<i32> = self
+            .landings_read
+            .get(way)
+            .iter()
+            .flatten()
+            .map(|node| node.distance_along_way_mm)
+            .collect();
+        if start_node.way == *way {
+            distances.push(start_node.distance_along_way_mm);
+        }
+        distances.sort();
+        distances.dedup();
+        distances
+    }
+
+    /// The intersections `search_djikstra_inner` can travel to from `from` before transitioning:
+    /// one exactly at `from`, or the nearest in either direction along the way. Each comes with
+    /// the cost of travelling there, including the way's entry penalty if `entering`.
+    pub(super) fn reachable_vias(
+        &self,
+        costing: &Costing,
+        from: &SearchNode,
+        entering: bool,
+    ) -> Vec<(SearchNode, RoutingCost)> {
+        let distance = from.distance_along_way_mm;
+        let nodes = self.node_distances(&from.way);
+        let before = nodes.iter().rev().find(|node| **node < distance).copied();
+        let after = nodes.iter().find(|node| **node > distance).copied();
+        let identity = nodes.contains(&distance).then_some(distance);
+        [identity, after, before]
+            .into_iter()
+            .flatten()
+            .filter_map(|via_distance| {
+                // Impassable way segments are skipped.
+                let travel_cost =
+                    self.cost_along_way(costing, &from.way, distance, via_distance, entering)?;
+                let via = SearchNode {
+                    way: from.way,
+                    distance_along_way_mm: via_distance,
+                };
+                Some((via, travel_cost))
+            })
+            .collect()
+    }
+
+    /// The cost of finishing a route at `end_node` from `from`, if it lies on the same way strictly
+    /// between `from` and the nearest intersection in either direction.
+    pub(super) fn finish_cost(
+        &self,
+        costing: &Costing,
+        from: &SearchNode,
+        entering: bool,
+        end_node: &SearchNode,
+    ) -> Option<RoutingCost> {
+        if from.way != end_node.way {
+            return None;
+        }
+        let distance = from.distance_along_way_mm;
+        let end_distance = end_node.distance_along_way_mm;
+        let nodes = self.node_distances(&from.way);
+        let before = nodes.iter().rev().find(|node| **node < distance).copied();
+        let after = nodes.iter().find(|node| **node > distance).copied();
+        for next in [after, before].into_iter().flatten() {
+            let end_is_before_next = (distance < end_distance && end_distance < next)
+                || (distance > end_distance && end_distance > next);
+            if end_is_before_next {
+                return self.cost_along_way(costing, &from.way, distance, end_distance, entering);
+            }
+        }
+        None
+    }
+
+    /// The steps `search_djikstra_inner` can take from `from`, `entering` its way or not:
+    /// travelling to one of its `reachable_vias` and taking a transition there. If an end node is
+    /// given, this includes finishing the route on the way.
+    pub(super) fn forward_edges(
+        &self,
+        costing: &Costing,
+        from: &SearchNode,
+        entering: bool,
+        end_node: Option<&SearchNode>,
+    ) -> Vec<SearchEdge> {
+        self.load_tiles_along(&from.way);
+        let mut edges = Vec::new();
+        if let Some(end_node) = end_node
+            && let Some(cost) = self.finish_cost(costing, from, entering, end_node)
+        {
+            edges.push(SearchEdge {
+                via: *end_node,
+                to: *end_node,
+                enters: false,
+                cost,
+            });
+        }
+        for (via, travel_cost) in self.reachable_vias(costing, from, entering) {
+            for (costed, transition) in self.costed_transitions(costing, &via) {
+                let to = SearchNode {
+                    way: transition.to_way_id,
+                    distance_along_way_mm: transition.transition_to_distance_along_way_mm,
+                };
+                // As in `search_djikstra_inner`, steps don't land back where they started.
+                if to == *from {
+                    continue;
+                }
+                edges.push(SearchEdge {
+                    via,
+                    to,
+                    enters: self.enters(costing, &via.way, &to.way),
+                    cost: travel_cost + costed.cost,
+                });
+            }
+        }
+        edges
+    }
+
+    /// The inverse of `forward_edges`: every node that has a step to `to` that leaves it
+    /// `entering` or not, along with that step. Each node comes both entering its way and not if
+    /// the way has an entry penalty, with the step's cost including it in the first case.
+    pub(super) fn backward_edges(
+        &self,
+        costing: &Costing,
+        to: &SearchNode,
+        entering: bool,
+        start_node: &SearchNode,
+        end_node: &SearchNode,
+    ) -> Vec<(SearchNode, bool, SearchEdge)> {
+        self.load_tiles_along(&to.way);
+        let mut edges = Vec::new();
+        if to == end_node && !entering {
+            // Finishing steps are taken from a node on the same way, as long as there's no
+            // intersection between it and the end, and there is one past the end.
+            let end_distance = end_node.distance_along_way_mm;
+            let nodes = self.node_distances(&end_node.way);
+            let before = nodes
+                .iter()
+                .rev()
+                .find(|node| **node < end_distance)
+                .copied();
+            let after = nodes.iter().find(|node| **node > end_distance).copied();
+            if !nodes.contains(&end_distance) {
+                for from_distance in self.landing_distances(&end_node.way, start_node) {
+                    let can_finish = if from_distance < end_distance {
+                        after.is_some() && before.is_none_or(|before| from_distance >= before)
+                    } else if from_distance > end_distance {
+                        before.is_some() && after.is_none_or(|after| from_distance <= after)
+                    } else {
+                        false
+                    };
+                    if !can_finish {
+                        continue;
+                    }
+                    for &from_entering in self.entering_labels(costing, &end_node.way) {
+                        if let Some(cost) = self.cost_along_way(
+                            costing,
+                            &end_node.way,
+                            from_distance,
+                            end_distance,
+                            from_entering,
+                        ) {
+                            edges.push((
+                                SearchNode {
+                                    way: end_node.way,
+                                    distance_along_way_mm: from_distance,
+                                },
+                                from_entering,
+                                SearchEdge {
+                                    via: *end_node,
+                                    to: *end_node,
+                                    enters: false,
+                                    cost,
+                                },
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+
+        for (costed, transition) in self.costed_reverse_transitions(costing, to) {
+            if self.enters(costing, &transition.from_way_id, &to.way) != entering {
+                continue;
+            }
+            let via = SearchNode {
+                way: transition.from_way_id,
+                distance_along_way_mm: transition.distance_along_way_mm,
+            };
+            // A node only steps to `via` if there's no other intersection between them.
+            let nodes = self.node_distances(&via.way);
+            let before = nodes
+                .iter()
+                .rev()
+                .find(|node| **node < via.distance_along_way_mm)
+                .copied();
+            let after = nodes
+                .iter()
+                .find(|node| **node > via.distance_along_way_mm)
+                .copied();
+            for from_distance in self.landing_distances(&via.way, start_node) {
+                let from = SearchNode {
+                    way: via.way,
+                    distance_along_way_mm: from_distance,
+                };
+                if before.is_some_and(|before| from_distance < before)
+                    || after.is_some_and(|after| from_distance > after)
+                    || from == *to
+                {
+                    continue;
+                }
+                for &from_entering in self.entering_labels(costing, &via.way) {
+                    let travel_cost = if let Some(cost) = self.cost_along_way(
+                        costing,
+                        &via.way,
+                        from_distance,
+                        via.distance_along_way_mm,
+                        from_entering,
+                    ) {
+                        cost
+                    } else {
+                        // Impassable way segment.
+                        continue;
+                    };
+                    edges.push((
+                        from,
+                        from_entering,
+                        SearchEdge {
+                            via,
+                            to: *to,
+                            enters: entering,
+                            cost: travel_cost + costed.cost,
+                        },
+                    ));
+                }
+            }
+        }
+        edges
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use geo::Coord;
+
+    use crate::costing::pedestrian::pedestrian_costing_model;
+
+    use super::super::{Graph, WayId};
+
+    #[test]
+    fn search_bidirectional_matches_djikstra_basic() {
+        let costing_model = pedestrian_costing_model(1.4);
+        let graph = Graph::new();
+        graph
+            .ingest_tile(
+                2625,
+                5721,
+                14,
+                include_bytes!("../../testdata/tile.pbf").to_vec(),
+                include_bytes!("../../testdata/tile.pbf").to_vec(),
+            )
+            .expect("Failed to ingest tile");
+        let (from_way_id, from_way_distance) = graph
+            .nearest_way(
+                &costing_model,
+                &Coord {
+                    x: -122.3126740,
+                    y: 47.6153470,
+                },
+            )
+            .unwrap();
+        let (to_way_id, to_way_distance) = graph
+            .nearest_way(
+                &costing_model,
+                &Coord {
+                    x: -122.315503,
+                    y: 47.6163794,
+                },
+            )
+            .unwrap();
+
+        let djikstra = graph
+            .search_djikstra(
+                &costing_model,
+                from_way_id,
+                from_way_distance,
+                to_way_id,
+                to_way_distance,
+            )
+            .expect("Couldn't find a route.");
+        let bidirectional = graph
+            .search_bidirectional(
+                &costing_model,
+                from_way_id,
+                from_way_distance,
+                to_way_id,
+                to_way_distance,
+            )
+            .expect("Couldn't find a route.");
+        assert_eq!(bidirectional.cost, djikstra.cost);
+        assert_eq!(bidirectional.encoded_polyline, djikstra.encoded_polyline);
+    }
+
+    #[test]
+    fn search_bidirectional_matches_djikstra_fremont() {
+        let costing_model = pedestrian_costing_model(1.4);
+        let graph = Graph::new();
+        graph
+            .ingest_tile(
+                2623,
+                5718,
+                14,
+                include_bytes!("../../testdata/tile2.pbf").to_vec(),
+                include_bytes!("../../testdata/tile2.pbf").to_vec(),
+            )
+            .expect("Failed to ingest tile");
+        let djikstra = graph
+            .search_djikstra(&costing_model, WayId(671949014), 0, WayId(980366562), 0)
+            .expect("Couldn't find a route.");
+        let bidirectional = graph
+            .search_bidirectional(&costing_model, WayId(671949014), 0, WayId(980366562), 0)
+            .expect("Couldn't find a route.");
+        assert_eq!(bidirectional.cost, djikstra.cost);
+    }
+}
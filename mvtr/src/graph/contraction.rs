@@ -0,0 +1,805 @@
+use std::{
+    collections::{BinaryHeap, HashMap},
+    sync::{Arc, atomic},
+};
+
+use crate::costing::{CostingModel, RoutingCost};
+
+use super::{
+    Costing, Graph, SearchNode, SearchResult, SearchState, WayId, bidirectional::SearchEdge,
+};
+
+/// How many nodes a witness search may settle before giving up and adding the shortcut anyway.
+/// Extra shortcuts cost memory and query time but never correctness.
+const WITNESS_SEARCH_SETTLE_LIMIT: usize = 64;
+
+/// Neighbouring nodes and the cost of the edge to or from each.
+type Adjacency = Vec<(usize, RoutingCost)>;
+
+/// Each step of the search is split in two, so that the hierarchy's nodes are a node-based graph
+/// rather than a clique of every way at every intersection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum HierarchyNode {
+    /// A node on a way that a transition leads to. Travelling along the way connects it to vias.
+    Landing(SearchNode),
+    /// A landing reached by turning onto its way, so travelling on from it pays the way's entry
+    /// penalty.
+    Entry(SearchNode),
+    /// An intersection on a way. Transitions connect it to landings.
+    Via(SearchNode),
+    /// The intersection an entry is at, reached without travelling. Its transitions don't lead
+    /// back to the entry's own node, which would get out of the entry penalty.
+    EntryVia(SearchNode),
+}
+
+impl HierarchyNode {
+    fn search_node(&self) -> SearchNode {
+        match self {
+            HierarchyNode::Landing(node)
+            | HierarchyNode::Entry(node)
+            | HierarchyNode::Via(node)
+            | HierarchyNode::EntryVia(node) => *node,
+        }
+    }
+
+    /// The landing a step that ends at `node` arrives at.
+    fn landing(node: SearchNode, entering: bool) -> HierarchyNode {
+        if entering {
+            HierarchyNode::Entry(node)
+        } else {
+            HierarchyNode::Landing(node)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ContractedEdgeKind {
+    /// Travel along a way, or a transition at an intersection.
+    Original,
+    /// Two edges joined across a node that was contracted.
+    Shortcut { middle: usize },
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ContractedEdge {
+    cost: RoutingCost,
+    kind: ContractedEdgeKind,
+}
+
+/// A min-heap entry, ordered by priority and then by node for determinism.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct QueueEntry<Priority: PartialOrd + Eq> {
+    priority: Priority,
+    node: usize,
+}
+
+impl<Priority: PartialOrd + Eq> PartialOrd for QueueEntry<Priority> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<Priority: PartialOrd + Eq> Ord for QueueEntry<Priority> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        other
+            .priority
+            .partial_cmp(&self.priority)
+            .unwrap()
+            .then_with(|| other.node.cmp(&self.node))
+    }
+}
+
+/// A contraction hierarchy over the steps of the forward search between every node a transition
+/// lands on, plus shortcuts added while contracting them.
+pub(crate) struct ContractionHierarchy {
+    nodes: Vec<HierarchyNode>,
+    index: HashMap<HierarchyNode, usize>,
+    /// Edges to higher-ranked nodes, by their source.
+    upward: Vec<Adjacency>,
+    /// Edges from higher-ranked nodes, by their target.
+    downward: Vec<Adjacency>,
+    edges: HashMap<(usize, usize), ContractedEdge>,
+    /// The graph's generation when the hierarchy was built.
+    generation: u64,
+    /// The fingerprint of the costing model it was built with.
+    fingerprint: Option<u64>,
+}
+
+/// The graph that remains while nodes are contracted, along with scratch space for witness
+/// searches so they don't allocate.
+struct Contractor {
+    outgoing: Vec<Adjacency>,
+    incoming: Vec<Adjacency>,
+    witness_costs: Vec<Option<RoutingCost>>,
+    witness_touched: Vec<usize>,
+    witness_frontier: BinaryHeap<QueueEntry<RoutingCost>>,
+}
+
+impl Contractor {
+    fn new(node_count: usize) -> Contractor {
+        Contractor {
+            outgoing: vec![Vec::new(); node_count],
+            incoming: vec![Vec::new(); node_count],
+            witness_costs: vec![None; node_count],
+            witness_touched: Vec::new(),
+            witness_frontier: BinaryHeap::new(),
+        }
+    }
+
+    /// Adds an edge, or lowers the cost of an existing one. Returns whether anything changed.
+    fn set_edge(&mut self, from: usize, to: usize, cost: RoutingCost) -> bool {
+        if let Some((_, existing)) = self.outgoing[from].iter_mut().find(|(node, _)| *node == to) {
+            if *existing <= cost {
+                return false;
+            }
+            *existing = cost;
+            if let Some((_, existing)) =
+                self.incoming[to].iter_mut().find(|(node, _)| *node == from)
+            {
+                *existing = cost;
+            }
+        } else {
+            self.outgoing[from].push((to, cost));
+            self.incoming[to].push((from, cost));
+        }
+        true
+    }
+
+    /// Removes a node from the remaining graph, returning its incoming and outgoing edges.
+    fn remove(&mut self, node: usize) -> (Adjacency, Adjacency) {
+        let incoming = std::mem::take(&mut self.incoming[node]);
+        let outgoing = std::mem::take(&mut self.outgoing[node]);
+        for (from, _) in &incoming {
+            self.outgoing[*from].retain(|(to, _)| *to != node);
+        }
+        for (to, _) in &outgoing {
+            self.incoming[*to].retain(|(from, _)| *from != node);
+        }
+        (incoming, outgoing)
+    }
+
+    /// The shortcuts needed to preserve every cheapest path through `node` once it's removed.
+    fn shortcuts(&mut self, node: usize) -> Vec<(usize, usize, RoutingCost)> {
+        let mut shortcuts = Vec::new();
+        for incoming_idx in 0..self.incoming[node].len() {
+            let (from, incoming_cost) = self.incoming[node][incoming_idx];
+            let max_cost = if let Some(max_cost) = self.outgoing[node]
+                .iter()
+                .filter(|(to, _)| *to != from)
+                .map(|(_, outgoing_cost)| incoming_cost + *outgoing_cost)
+                .max_by(|a, b| a.partial_cmp(b).unwrap())
+            {
+                max_cost
+            } else {
+                continue;
+            };
+            let targets = self.outgoing[node].len();
+            self.witness_search(from, node, max_cost, targets);
+            for (to, outgoing_cost) in &self.outgoing[node] {
+                if *to == from {
+                    continue;
+                }
+                let shortcut_cost = incoming_cost + *outgoing_cost;
+                if self.witness_costs[*to].is_some_and(|witness_cost| witness_cost <= shortcut_cost)
+                {
+                    continue;
+                }
+                shortcuts.push((from, *to, shortcut_cost));
+            }
+        }
+        shortcuts
+    }
+
+    /// Finds the costs of the cheapest paths from `from` that avoid `avoid`, up to `max_cost` or
+    /// until the neighbours of `avoid` are all settled, leaving them in `witness_costs`.
+    fn witness_search(&mut self, from: usize, avoid: usize, max_cost: RoutingCost, targets: usize) {
+        for node in self.witness_touched.drain(..) {
+            self.witness_costs[node] = None;
+        }
+        self.witness_frontier.clear();
+        self.witness_costs[from] = Some(RoutingCost::zero());
+        self.witness_touched.push(from);
+        self.witness_frontier.push(QueueEntry {
+            priority: RoutingCost::zero(),
+            node: from,
+        });
+        let mut settled = 0;
+        let mut settled_targets = 0;
+        while let Some(entry) = self.witness_frontier.pop() {
+            if self.witness_costs[entry.node].is_some_and(|cost| cost < entry.priority) {
+                continue;
+            }
+            settled += 1;
+            if entry.priority > max_cost || settled > WITNESS_SEARCH_SETTLE_LIMIT {
+                break;
+            }
+            if self.outgoing[avoid].iter().any(|(to, _)| *to == entry.node) {
+                settled_targets += 1;
+                if settled_targets >= targets {
+                    break;
+                }
+            }
+            for (to, cost) in &self.outgoing[entry.node] {
+                if *to == avoid {
+                    continue;
+                }
+                let new_cost = entry.priority + *cost;
+                if self.witness_costs[*to].is_none_or(|cost| new_cost < cost) {
+                    if self.witness_costs[*to].is_none() {
+                        self.witness_touched.push(*to);
+                    }
+                    self.witness_costs[*to] = Some(new_cost);
+                    self.witness_frontier.push(QueueEntry {
+                        priority: new_cost,
+                        node: *to,
+                    });
+                }
+            }
+        }
+    }
+}
+
+impl ContractionHierarchy {
+    fn build(graph: &Graph, costing: &Costing, generation: u64) -> ContractionHierarchy {
+        let mut landings: Vec<SearchNode> = graph
+            .landings_read
+            .read()
+            .map(|landings| {
+                landings
+                    .iter()
+                    .flat_map(|(_, nodes)| nodes.iter().copied().collect::<Vec<_>>())
+                    .collect()
+            })
+            .unwrap_or_default();
+        landings.sort();
+        landings.dedup();
+        let mut nodes: Vec<HierarchyNode> = landings
+            .iter()
+            .flat_map(|landing| {
+                graph
+                    .entering_labels(costing, &landing.way)
+                    .iter()
+                    .map(|&entering| HierarchyNode::landing(*landing, entering))
+            })
+            .collect();
+        let landing_count = nodes.len();
+        let mut index: HashMap<HierarchyNode, usize> = nodes
+            .iter()
+            .enumerate()
+            .map(|(idx, node)| (*node, idx))
+            .collect();
+
+        let mut travel_edges = Vec::new();
+        for from in 0..landing_count {
+            let landing = nodes[from].search_node();
+            let entering = matches!(nodes[from], HierarchyNode::Entry(_));
+            for (via, travel_cost) in graph.reachable_vias(costing, &landing, entering) {
+                let via = if entering && via == landing {
+                    HierarchyNode::EntryVia(via)
+                } else {
+                    HierarchyNode::Via(via)
+                };
+                let to = *index.entry(via).or_insert_with(|| {
+                    nodes.push(via);
+                    nodes.len() - 1
+                });
+                travel_edges.push((from, to, travel_cost));
+            }
+        }
+        let mut transition_edges = Vec::new();
+        for (from, node) in nodes.iter().enumerate().skip(landing_count) {
+            let via = node.search_node();
+            for (costed, transition) in graph.costed_transitions(costing, &via) {
+                let to = SearchNode {
+                    way: transition.to_way_id,
+                    distance_along_way_mm: transition.transition_to_distance_along_way_mm,
+                };
+                if matches!(node, HierarchyNode::EntryVia(_)) && to == via {
+                    continue;
+                }
+                let landing = HierarchyNode::landing(to, graph.enters(costing, &via.way, &to.way));
+                if let Some(to) = index.get(&landing) {
+                    transition_edges.push((from, *to, costed.cost));
+                }
+            }
+        }
+
+        let mut edges: HashMap<(usize, usize), ContractedEdge> = HashMap::new();
+        let mut contractor = Contractor::new(nodes.len());
+        for (from, to, cost) in travel_edges.into_iter().chain(transition_edges) {
+            if contractor.set_edge(from, to, cost) {
+                edges.insert(
+                    (from, to),
+                    ContractedEdge {
+                        cost,
+                        kind: ContractedEdgeKind::Original,
+                    },
+                );
+            }
+        }
+
+        let mut contracted = vec![false; nodes.len()];
+        let mut contracted_neighbours = vec![0i64; nodes.len()];
+        let mut upward = vec![Vec::new(); nodes.len()];
+        let mut downward = vec![Vec::new(); nodes.len()];
+        let priority = |node: usize, contractor: &mut Contractor, contracted_neighbours: &[i64]| {
+            // Edge difference, plus the number of neighbours already contracted to spread
+            // contraction evenly across the graph.
+            let shortcuts = contractor.shortcuts(node);
+            let priority = shortcuts.len() as i64
+                - contractor.incoming[node].len() as i64
+                - contractor.outgoing[node].len() as i64
+                + contracted_neighbours[node];
+            (priority, shortcuts)
+        };
+        let mut queue: BinaryHeap<QueueEntry<i64>> = (0..nodes.len())
+            .map(|node| QueueEntry {
+                priority: priority(node, &mut contractor, &contracted_neighbours).0,
+                node,
+            })
+            .collect();
+
+        while let Some(entry) = queue.pop() {
+            let node = entry.node;
+            if contracted[node] {
+                continue;
+            }
+            let (current_priority, shortcuts) =
+                priority(node, &mut contractor, &contracted_neighbours);
+            if queue
+                .peek()
+                .is_some_and(|next| current_priority > next.priority)
+            {
+                queue.push(QueueEntry {
+                    priority: current_priority,
+                    node,
+                });
+                continue;
+            }
+
+            for (from, to, shortcut_cost) in shortcuts {
+                if contractor.set_edge(from, to, shortcut_cost) {
+                    edges.insert(
+                        (from, to),
+                        ContractedEdge {
+                            cost: shortcut_cost,
+                            kind: ContractedEdgeKind::Shortcut { middle: node },
+                        },
+                    );
+                }
+            }
+
+            // Everything still connected to this node will be contracted later, so ranks higher.
+            let (node_incoming, node_outgoing) = contractor.remove(node);
+            for (neighbour, _) in node_incoming.iter().chain(node_outgoing.iter()) {
+                contracted_neighbours[*neighbour] += 1;
+            }
+            upward[node] = node_outgoing;
+            downward[node] = node_incoming;
+            contracted[node] = true;
+        }
+
+        ContractionHierarchy {
+            nodes,
+            index,
+            upward,
+            downward,
+            edges,
+            generation,
+            fingerprint: costing.fingerprint,
+        }
+    }
+
+    /// Searches upward from every seed, in either the forward or the backward direction, returning
+    /// each reached node's cost and the node it was reached from.
+    fn upward_search(
+        &self,
+        seeds: &HashMap<usize, RoutingCost>,
+        backward: bool,
+    ) -> HashMap<usize, (RoutingCost, Option<usize>)> {
+        let mut costs: HashMap<usize, (RoutingCost, Option<usize>)> = seeds
+            .iter()
+            .map(|(node, cost)| (*node, (*cost, None)))
+            .collect();
+        let mut frontier: BinaryHeap<QueueEntry<RoutingCost>> = seeds
+            .iter()
+            .map(|(node, cost)| QueueEntry {
+                priority: *cost,
+                node: *node,
+            })
+            .collect();
+        while let Some(entry) = frontier.pop() {
+            if costs
+                .get(&entry.node)
+                .is_some_and(|(cost, _)| *cost < entry.priority)
+            {
+                continue;
+            }
+            let neighbours = if backward {
+                &self.downward[entry.node]
+            } else {
+                &self.upward[entry.node]
+            };
+            for (neighbour, cost) in neighbours {
+                let new_cost = entry.priority + *cost;
+                if costs
+                    .get(neighbour)
+                    .is_none_or(|(cost, _)| new_cost < *cost)
+                {
+                    costs.insert(*neighbour, (new_cost, Some(entry.node)));
+                    frontier.push(QueueEntry {
+                        priority: new_cost,
+                        node: *neighbour,
+                    });
+                }
+            }
+        }
+        costs
+    }
+
+    /// The cheapest path from any source to any target, as a cost and a list of nodes.
+    fn query(
+        &self,
+        sources: &HashMap<usize, RoutingCost>,
+        targets: &HashMap<usize, RoutingCost>,
+    ) -> Option<(RoutingCost, Vec<usize>)> {
+        let forward = self.upward_search(sources, false);
+        let backward = self.upward_search(targets, true);
+        let (cost, meeting_node) = forward
+            .iter()
+            .filter_map(|(node, (forward_cost, _))| {
+                let (backward_cost, _) = backward.get(node)?;
+                Some((*forward_cost + *backward_cost, *node))
+            })
+            .min_by(|a, b| a.partial_cmp(b).unwrap())?;
+
+        let mut path = vec![meeting_node];
+        while let Some((_, Some(previous))) = forward.get(path.last()?) {
+            path.push(*previous);
+        }
+        path.reverse();
+        while let Some((_, Some(next))) = backward.get(path.last()?) {
+            path.push(*next);
+        }
+        Some((cost, path))
+    }
+
+    /// Expands the edge between two nodes into the original edges it stands for, as the node each
+    /// one leads to and its cost.
+    fn unpack_edge(&self, from: usize, to: usize, hops: &mut Vec<(usize, RoutingCost)>) {
+        let edge = self
+            .edges
+            .get(&(from, to))
+            .expect("Contraction hierarchy path used a missing edge");
+        match edge.kind {
+            ContractedEdgeKind::Original => hops.push((to, edge.cost)),
+            ContractedEdgeKind::Shortcut { middle } => {
+                self.unpack_edge(from, middle, hops);
+                self.unpack_edge(middle, to, hops);
+            }
+        }
+    }
+
+    /// Expands a path between landings into search steps.
+    fn unpack_path(&self, path: &[usize]) -> Vec<SearchEdge> {
+        let mut hops = Vec::new();
+        for window in path.windows(2) {
+            self.unpack_edge(window[0], window[1], &mut hops);
+        }
+        // Original edges alternate between travelling to a via and transitioning to a landing.
+        hops.chunks_exact(2)
+            .map(|hop| SearchEdge {
+                via: self.nodes[hop[0].0].search_node(),
+                to: self.nodes[hop[1].0].search_node(),
+                enters: matches!(self.nodes[hop[1].0], HierarchyNode::Entry(_)),
+                cost: hop[0].1 + hop[1].1,
+            })
+            .collect()
+    }
+}
+
+impl Graph {
+    /// Builds a contraction hierarchy over the graph as it is now and costed by `costing_model`,
+    /// for use by `search_contracted` with any model of the same name that costs alike. Ingesting
+    /// a tile or clearing the graph discards it, and it needs to be prepared again. Only named
+    /// models can have one.
+    pub fn prepare_contraction_hierarchy(
+        &self,
+        costing_model: &dyn CostingModel,
+    ) -> anyhow::Result<()> {
+        let Some(name) = costing_model.name() else {
+            anyhow::bail!("Contraction hierarchies can only be prepared for named costing models");
+        };
+        let generation = self.generation.load(atomic::Ordering::SeqCst);
+        let costing = self.costing(costing_model)?;
+        let contraction_hierarchy = ContractionHierarchy::build(self, &costing, generation);
+        let mut guard = self
+            .contraction_hierarchies
+            .lock()
+            .map_err(|err| anyhow::anyhow!("Failed to lock mutex: {}", err))?;
+        if self.generation.load(atomic::Ordering::SeqCst) != generation {
+            anyhow::bail!("Graph changed while preparing the contraction hierarchy");
+        }
+        guard.insert(name.to_string(), Arc::new(contraction_hierarchy));
+        Ok(())
+    }
+
+    pub fn has_contraction_hierarchy(&self, costing_model: &dyn CostingModel) -> bool {
+        self.costing(costing_model)
+            .is_ok_and(|costing| self.contraction_hierarchy(&costing).is_some())
+    }
+
+    /// The contraction hierarchy prepared for a model of the same name as `costing`'s, if that
+    /// model costs alike.
+    fn contraction_hierarchy(&self, costing: &Costing) -> Option<Arc<ContractionHierarchy>> {
+        self.contraction_hierarchies
+            .lock()
+            .ok()?
+            .get(costing.model.name()?)
+            .filter(|contraction_hierarchy| {
+                contraction_hierarchy.fingerprint == costing.fingerprint
+            })
+            .cloned()
+    }
+
+    /// Finds the same route as `search_djikstra` using the contraction hierarchy prepared for
+    /// `costing_model`. Falls back to `search_bidirectional` if it hasn't been prepared since the
+    /// graph last changed, including when connecting the start and end to it loads new tiles.
+    pub fn search_contracted(
+        &self,
+        costing_model: &dyn CostingModel,
+        start: WayId,
+        distance_along_start_mm: i32,
+        end: WayId,
+        distance_along_end_mm: i32,
+    ) -> Option<SearchResult> {
+        let costing = self.costing(costing_model).ok()?;
+        let contraction_hierarchy = if let Some(contraction_hierarchy) =
+            self.contraction_hierarchy(&costing)
+        {
+            contraction_hierarchy
+        } else {
+            tracing::warn!("No contraction hierarchy prepared, falling back to a regular search");
+            return self.search_bidirectional(
+                costing_model,
+                start,
+                distance_along_start_mm,
+                end,
+                distance_along_end_mm,
+            );
+        };
+
+        let start_node = SearchNode {
+            way: start,
+            distance_along_way_mm: distance_along_start_mm,
+        };
+        let end_node = SearchNode {
+            way: end,
+            distance_along_way_mm: distance_along_end_mm,
+        };
+        let first_state = SearchState {
+            previous: 0,
+            idx: 0,
+            node: start_node,
+            via: start_node,
+            entering: false,
+            cost: RoutingCost::zero(),
+            priority: RoutingCost::zero(),
+        };
+        if start_node == end_node {
+            return self.build_search_result(&[first_state]);
+        }
+
+        // The start and end usually aren't in the hierarchy, so they're connected to it with the
+        // steps the regular search would take from and to them.
+        let mut direct: Option<SearchEdge> = None;
+        let mut first_steps: HashMap<usize, SearchEdge> = HashMap::new();
+        for edge in self.forward_edges(&costing, &start_node, false, Some(&end_node)) {
+            if edge.to == end_node {
+                if direct.is_none_or(|direct| edge.cost < direct.cost) {
+                    direct = Some(edge);
+                }
+            } else if let Some(node) = contraction_hierarchy
+                .index
+                .get(&HierarchyNode::landing(edge.to, edge.enters))
+                && first_steps
+                    .get(node)
+                    .is_none_or(|first_step| edge.cost < first_step.cost)
+            {
+                first_steps.insert(*node, edge);
+            }
+        }
+        let mut last_steps: HashMap<usize, SearchEdge> = HashMap::new();
+        for (from, entering, edge) in [false, true].into_iter().flat_map(|entering| {
+            self.backward_edges(&costing, &end_node, entering, &start_node, &end_node)
+        }) {
+            if let Some(node) = contraction_hierarchy
+                .index
+                .get(&HierarchyNode::landing(from, entering))
+                && last_steps
+                    .get(node)
+                    .is_none_or(|last_step| edge.cost < last_step.cost)
+            {
+                last_steps.insert(*node, edge);
+            }
+        }
+
+        // Reaching the start and end can load tiles, which leaves the hierarchy out of date.
+        if self.generation.load(atomic::Ordering::SeqCst) != contraction_hierarchy.generation {
+            tracing::warn!("Graph changed during the search, falling back to a regular search");
+            return self.search_bidirectional(
+                costing_model,
+                start,
+                distance_along_start_mm,
+                end,
+                distance_along_end_mm,
+            );
+        }
+
+        let sources = first_steps
+            .iter()
+            .map(|(node, edge)| (*node, edge.cost))
+            .collect();
+        let targets = last_steps
+            .iter()
+            .map(|(node, edge)| (*node, edge.cost))
+            .collect();
+        let contracted_route = contraction_hierarchy.query(&sources, &targets);
+
+        let mut steps: Vec<SearchEdge> = Vec::new();
+        match (direct, contracted_route) {
+            (Some(direct), Some((cost, _))) if direct.cost <= cost => steps.push(direct),
+            (_, Some((_, path))) => {
+                steps.push(*first_steps.get(path.first()?)?);
+                steps.extend(contraction_hierarchy.unpack_path(&path));
+                steps.push(*last_steps.get(path.last()?)?);
+            }
+            (Some(direct), None) => steps.push(direct),
+            (None, None) => return None,
+        }
+
+        let mut states = vec![first_state];
+        for step in steps {
+            let previous = states.last()?;
+            let cost = previous.cost + step.cost;
+            states.push(SearchState {
+                previous: previous.idx,
+                idx: states.len(),
+                node: step.to,
+                via: step.via,
+                entering: step.enters,
+                cost,
+                priority: cost,
+            });
+        }
+        self.build_search_result(&states)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use geo::Coord;
+
+    use crate::costing::pedestrian::pedestrian_costing_model;
+
+    use super::super::{Graph, WayId};
+
+    #[test]
+    fn search_contracted_matches_djikstra_basic() {
+        let costing_model = pedestrian_costing_model(1.4);
+        let graph = Graph::new();
+        graph
+            .ingest_tile(
+                2625,
+                5721,
+                14,
+                include_bytes!("../../testdata/tile.pbf").to_vec(),
+                include_bytes!("../../testdata/tile.pbf").to_vec(),
+            )
+            .expect("Failed to ingest tile");
+        graph
+            .prepare_contraction_hierarchy(&costing_model)
+            .expect("Failed to prepare contraction hierarchy");
+        let (from_way_id, from_way_distance) = graph
+            .nearest_way(
+                &costing_model,
+                &Coord {
+                    x: -122.3126740,
+                    y: 47.6153470,
+                },
+            )
+            .unwrap();
+        let (to_way_id, to_way_distance) = graph
+            .nearest_way(
+                &costing_model,
+                &Coord {
+                    x: -122.315503,
+                    y: 47.6163794,
+                },
+            )
+            .unwrap();
+
+        let djikstra = graph
+            .search_djikstra(
+                &costing_model,
+                from_way_id,
+                from_way_distance,
+                to_way_id,
+                to_way_distance,
+            )
+            .expect("Couldn't find a route.");
+        let contracted = graph
+            .search_contracted(
+                &costing_model,
+                from_way_id,
+                from_way_distance,
+                to_way_id,
+                to_way_distance,
+            )
+            .expect("Couldn't find a route.");
+        assert_eq!(contracted.cost, djikstra.cost);
+    }
+
+    #[test]
+    fn search_contracted_matches_djikstra_fremont() {
+        let costing_model = pedestrian_costing_model(1.4);
+        let graph = Graph::new();
+        graph
+            .ingest_tile(
+                2623,
+                5718,
+                14,
+                include_bytes!("../../testdata/tile2.pbf").to_vec(),
+                include_bytes!("../../testdata/tile2.pbf").to_vec(),
+            )
+            .expect("Failed to ingest tile");
+        graph
+            .prepare_contraction_hierarchy(&costing_model)
+            .expect("Failed to prepare contraction hierarchy");
+        let djikstra = graph
+            .search_djikstra(&costing_model, WayId(671949014), 0, WayId(980366562), 0)
+            .expect("Couldn't find a route.");
+        let contracted = graph
+            .search_contracted(&costing_model, WayId(671949014), 0, WayId(980366562), 0)
+            .expect("Couldn't find a route.");
+        assert_eq!(contracted.cost, djikstra.cost);
+    }
+
+    #[test]
+    fn contraction_hierarchy_invalidated_by_changes() {
+        let costing_model = pedestrian_costing_model(1.4);
+        let graph = Graph::new();
+        let ingest_fremont = || {
+            graph
+                .ingest_tile(
+                    2623,
+                    5718,
+                    14,
+                    include_bytes!("../../testdata/tile2.pbf").to_vec(),
+                    include_bytes!("../../testdata/tile2.pbf").to_vec(),
+                )
+                .expect("Failed to ingest tile");
+            graph
+                .prepare_contraction_hierarchy(&costing_model)
+                .expect("Failed to prepare contraction hierarchy");
+            assert!(graph.has_contraction_hierarchy(&costing_model));
+        };
+
+        ingest_fremont();
+        graph
+            .ingest_tile(
+                2625,
+                5721,
+                14,
+                include_bytes!("../../testdata/tile.pbf").to_vec(),
+                include_bytes!("../../testdata/tile.pbf").to_vec(),
+            )
+            .expect("Failed to ingest tile");
+        assert!(!graph.has_contraction_hierarchy(&costing_model));
+
+        graph.clear().expect("Failed to clear graph");
+        ingest_fremont();
+        graph.clear().expect("Failed to clear graph");
+        assert!(!graph.has_contraction_hierarchy(&costing_model));
+    }
+}
@@ -0,0 +1,464 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use crate::costing::{
+    CostingModel, Tags, TransitionGeometry, TransitionToCost, WayCoster, fingerprint,
+    units::TravelSpeed,
+};
+
+use super::{CostedWayTransition, Graph, SearchNode, WayId, WayTransition};
+
+/// The costs a costing model has worked out for the graph so far. Ways and intersections are
+/// costed from their tags the first time a search reaches them, and kept until the graph changes.
+pub(super) struct CostCache {
+    ways: HashMap<WayId, WayCoster>,
+    transitions: HashMap<SearchNode, Vec<(CostedWayTransition, WayTransition)>>,
+    /// The speed of the fastest way costed so far.
+    fastest_way: Option<TravelSpeed>,
+}
+
+impl CostCache {
+    pub(super) fn new() -> CostCache {
+        CostCache {
+            ways: HashMap::new(),
+            transitions: HashMap::new(),
+            fastest_way: None,
+        }
+    }
+
+    pub(super) fn clear(&mut self) {
+        self.ways.clear();
+        self.transitions.clear();
+        self.fastest_way = None;
+    }
+
+    pub(super) fn insert_way(&mut self, way: WayId, way_coster: WayCoster) {
+        if let Some(speed) = way_coster.max_speed()
+            && self.fastest_way.is_none_or(|fastest| speed > fastest)
+        {
+            self.fastest_way = Some(speed);
+        }
+        self.ways.insert(way, way_coster);
+    }
+
+    pub(super) fn insert_transitions(
+        &mut self,
+        node: SearchNode,
+        transitions: Vec<(CostedWayTransition, WayTransition)>,
+    ) {
+        self.transitions.insert(node, transitions);
+    }
+}
+
+/// How many named models' costs are kept. Using another drops the least recently used.
+const MAX_COST_CACHES: usize = 16;
+
+/// The costs named costing models have worked out, by name.
+#[derive(Default)]
+pub(super) struct CostCaches {
+    caches: HashMap<String, NamedCostCache>,
+    /// Counts uses, to tell which cache was used least recently.
+    uses: u64,
+}
+
+struct NamedCostCache {
+    /// The fingerprint of the model the costs were worked out with.
+    fingerprint: u64,
+    last_used: u64,
+    cache: Arc<Mutex<CostCache>>,
+}
+
+impl CostCaches {
+    /// The costs of the model named `name`. They're replaced with empty ones if they were worked
+    /// out by a model of the same name that costs differently.
+    pub(super) fn get(&mut self, name: &str, fingerprint: u64) -> Arc<Mutex<CostCache>> {
+        self.uses += 1;
+        if let Some(named) = self.caches.get_mut(name) {
+            if named.fingerprint == fingerprint {
+                named.last_used = self.uses;
+                return named.cache.clone();
+            }
+            tracing::warn!(
+                "Costing model {} doesn't cost like the last model of that name, discarding its costs",
+                name
+            );
+        }
+        self.insert(name, fingerprint, CostCache::new())
+    }
+
+    /// Keeps `cache` as the costs of the model named `name`.
+    pub(super) fn insert(
+        &mut self,
+        name: &str,
+        fingerprint: u64,
+        cache: CostCache,
+    ) -> Arc<Mutex<CostCache>> {
+        self.uses += 1;
+        if !self.caches.contains_key(name)
+            && self.caches.len() >= MAX_COST_CACHES
+            && let Some(least_recent) = self
+                .caches
+                .iter()
+                .min_by_key(|(_, named)| named.last_used)
+                .map(|(name, _)| name.clone())
+        {
+            self.caches.remove(&least_recent);
+        }
+        let cache = Arc::new(Mutex::new(cache));
+        self.caches.insert(
+            name.to_string(),
+            NamedCostCache {
+                fingerprint,
+                last_used: self.uses,
+                cache: cache.clone(),
+            },
+        );
+        cache
+    }
+
+    /// Empties every cache. Searches already running hold on to their model's cache, so it's
+    /// emptied rather than dropped.
+    pub(super) fn clear(&self) {
+        for named in self.caches.values() {
+            named
+                .cache
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clear();
+        }
+    }
+}
+
+/// A costing model a search was asked to use, along with the costs it has already worked out.
+pub(super) struct Costing<'a> {
+    pub(super) model: &'a dyn CostingModel,
+    /// The model's fingerprint, if it's named.
+    pub(super) fingerprint: Option<u64>,
+    cache: Arc<Mutex<CostCache>>,
+}
+
+impl Costing<'_> {
+    /// Costs are only ever added whole, so a cache a panicking thread held is still sound.
+    pub(super) fn cache(&self) -> MutexGuard<'_, CostCache> {
+        self.cache.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// The fastest speed of any way the model costs, as far as is known: its declared max speed,
+    /// raised to that of any faster way it has costed. `None` if the model doesn't declare one.
+    pub(super) fn max_speed(&self) -> Option<TravelSpeed> {
+        let declared = self.model.max_speed()?;
+        Some(
+            self.cache()
+                .fastest_way
+                .filter(|fastest| *fastest > declared)
+                .unwrap_or(declared),
+        )
+    }
+}
+
+impl Graph {
+    /// `costing_model` with the costs it, or any other model of the same name that costs alike,
+    /// has worked out for the graph so far. Unnamed models start from nothing.
+    pub(super) fn costing<'a>(
+        &self,
+        costing_model: &'a dyn CostingModel,
+    ) -> anyhow::Result<Costing<'a>> {
+        let Some(name) = costing_model.name() else {
+            return Ok(Costing {
+                model: costing_model,
+                fingerprint: None,
+                cache: Arc::new(Mutex::new(CostCache::new())),
+            });
+        };
+        let fingerprint = fingerprint(costing_model);
+        let cache = self
+            .costs
+            .lock()
+            .map_err(|err| anyhow::anyhow!("Failed to lock mutex: {}", err))?
+            .get(name, fingerprint);
+        Ok(Costing {
+            model: costing_model,
+            fingerprint: Some(fingerprint),
+            cache,
+        })
+    }
+
+    fn way_tags(&self, way: &WayId) -> Option<Arc<Tags>> {
+        self.way_tags_read.get_one(way).map(|tags| tags.clone())
+    }
+
+    /// How the costing model costs travel along a way. `None` if the way isn't loaded.
+    pub(super) fn way_coster(&self, costing: &Costing, way: &WayId) -> Option<WayCoster> {
+        if let Some(way_coster) = costing.cache().ways.get(way) {
+            return Some(*way_coster);
+        }
+        let way_coster = costing.model.cost_way(&*self.way_tags(way)?);
+        if let (Some(declared), Some(actual)) = (costing.model.max_speed(), way_coster.max_speed())
+            && actual > declared
+        {
+            tracing::warn!(
+                "Way {:?} is faster than the costing model's declared max speed",
+                way
+            );
+        }
+        costing.cache().insert_way(*way, way_coster);
+        Some(way_coster)
+    }
+
+    /// Whether a transition from `from_way` onto `to_way` leaves the route entering `to_way`, with
+    /// its entry penalty still to pay. Ways without one are never entered, so searches don't track
+    /// the same node twice for nothing.
+    pub(super) fn enters(&self, costing: &Costing, from_way: &WayId, to_way: &WayId) -> bool {
+        from_way != to_way
+            && self
+                .way_coster(costing, to_way)
+                .is_some_and(|way_coster| way_coster.has_entry_penalty())
+    }
+
+    /// The `entering` values a search state on `way` can have: `true` only if the way has an
+    /// entry penalty.
+    pub(super) fn entering_labels(&self, costing: &Costing, way: &WayId) -> &'static [bool] {
+        if self
+            .way_coster(costing, way)
+            .is_some_and(|way_coster| way_coster.has_entry_penalty())
+        {
+            &[false, true]
+        } else {
+            &[false]
+        }
+    }
+
+    /// The transitions the costing model allows at an intersection, with their costs, including
+    /// one back onto the same way if continuing along it is allowed.
+    pub(super) fn costed_transitions(
+        &self,
+        costing: &Costing,
+        node: &SearchNode,
+    ) -> Vec<(CostedWayTransition, WayTransition)> {
+        if let Some(transitions) = costing.cache().transitions.get(node) {
+            return transitions.clone();
+        }
+        let mut transitions: Vec<(WayTransition, Arc<Tags>)> = self
+            .transitions_read
+            .get(node)
+            .iter()
+            .flatten()
+            .cloned()
+            .collect();
+        if transitions.is_empty() {
+            return Vec::new();
+        }
+        // Tiles overlapping at an intersection each hold its transitions.
+        transitions.sort_by_key(|(transition, _)| *transition);
+        transitions.dedup();
+        let costed = self.cost_intersection(costing, node, &transitions);
+        costing.cache().insert_transitions(*node, costed.clone());
+        costed
+    }
+
+    /// The costed transitions that lead to `to`, including `to`'s own transition back onto its
+    /// way.
+    pub(super) fn costed_reverse_transitions(
+        &self,
+        costing: &Costing,
+        to: &SearchNode,
+    ) -> Vec<(CostedWayTransition, WayTransition)> {
+        let mut vias: Vec<SearchNode> = self
+            .reverse_transitions_read
+            .get(to)
+            .iter()
+            .flatten()
+            .map(|transition| SearchNode {
+                way: transition.from_way_id,
+                distance_along_way_mm: transition.distance_along_way_mm,
+            })
+            .collect();
+        vias.push(*to);
+        vias.sort();
+        vias.dedup();
+        vias.iter()
+            .flat_map(|via| self.costed_transitions(costing, via))
+            .filter(|(_, transition)| {
+                transition.to_way_id == to.way
+                    && transition.transition_to_distance_along_way_mm == to.distance_along_way_mm
+            })
+            .collect()
+    }
+
+    fn cost_intersection(
+        &self,
+        costing: &Costing,
+        node: &SearchNode,
+        transitions: &[(WayTransition, Arc<Tags>)],
+    ) -> Vec<(CostedWayTransition, WayTransition)> {
+        let current_way_tags = if let Some(tags) = self.way_tags(&node.way) {
+            tags
+        } else {
+            tracing::warn!("Missing way tags while attempting to cost intersection");
+            return Vec::new();
+        };
+        let mut annotated_transitions = Vec::new();
+        for (way_transition, intersection_tags) in transitions {
+            if let Some(to_way_tags) = self.way_tags(&way_transition.to_way_id) {
+                annotated_transitions.push((*way_transition, to_way_tags, intersection_tags));
+            } else {
+                tracing::warn!(
+                    "Missing way tags for one or more ways, while attempting to cost intersection"
+                );
+            }
+        }
+        let from_legs = self.leg_bearings(&node.way, node.distance_along_way_mm);
+        let mut to_legs: HashMap<WayId, (Option<f64>, Option<f64>)> = HashMap::new();
+        for (way_transition, _, _) in &annotated_transitions {
+            to_legs.entry(way_transition.to_way_id).or_insert_with(|| {
+                self.leg_bearings(
+                    &way_transition.to_way_id,
+                    way_transition.transition_to_distance_along_way_mm,
+                )
+            });
+        }
+        let legs = [from_legs]
+            .iter()
+            .chain(to_legs.values())
+            .map(|(forward, reverse)| forward.is_some() as u32 + reverse.is_some() as u32)
+            .sum::<u32>();
+        let transitions_to_cost: Vec<TransitionToCost> = annotated_transitions
+            .iter()
+            .map(
+                |(way_transition, to_way_tags, intersection_tags)| TransitionToCost {
+                    way_transition: *way_transition,
+                    from_way_tags: &current_way_tags,
+                    to_way_tags,
+                    intersection_tags,
+                    geometry: transition_geometry(
+                        from_legs,
+                        to_legs[&way_transition.to_way_id],
+                        legs,
+                    ),
+                },
+            )
+            .collect();
+        let intersection_costs = costing
+            .model
+            .cost_intersection(&current_way_tags, &transitions_to_cost);
+
+        let way_transition_lookup: HashMap<WayId, WayTransition> = annotated_transitions
+            .iter()
+            .map(|(way_transition, _, _)| (way_transition.to_way_id, *way_transition))
+            .collect();
+        let mut costed: Vec<(CostedWayTransition, WayTransition)> = intersection_costs
+            .transition_costs
+            .iter()
+            .filter_map(|(to_way_id, transition_cost)| {
+                Some((
+                    CostedWayTransition {
+                        to_way_id: *to_way_id,
+                        cost: *transition_cost,
+                    },
+                    *way_transition_lookup.get(to_way_id)?,
+                ))
+            })
+            .collect();
+        // An identity transition represents the cost of interacting with the intersection and
+        // continuing along the same way.
+        if let Some(continue_cost) = intersection_costs.continue_cost {
+            costed.push((
+                CostedWayTransition {
+                    to_way_id: node.way,
+                    cost: continue_cost,
+                },
+                WayTransition::new(
+                    node.way,
+                    node.distance_along_way_mm,
+                    node.way,
+                    node.distance_along_way_mm,
+                ),
+            ));
+        }
+        costed
+    }
+}
+
+/// The geometry of a transition between ways with the given leg bearings, at an intersection with
+/// `legs` legs in all, or `None` if either way's geometry isn't loaded.
+fn transition_geometry(
+    from_legs: (Option<f64>, Option<f64>),
+    to_legs: (Option<f64>, Option<f64>),
+    legs: u32,
+) -> Option<TransitionGeometry> {
+    let loaded = |(forward, reverse): (Option<f64>, Option<f64>)| forward.or(reverse).is_some();
+    (loaded(from_legs) && loaded(to_legs)).then_some(TransitionGeometry {
+        from_legs,
+        to_legs,
+        other_legs: legs.saturating_sub(2),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::costing::{
+        CostingModel, TransitionCostResult,
+        base::{BaseCostingModel, WayCost},
+        pedestrian::pedestrian_costing_model,
+        units::TravelSpeed,
+    };
+
+    use super::super::testing::{CAPITOL_HILL_FROM, capitol_hill};
+    use super::{CostCache, CostCaches, MAX_COST_CACHES};
+
+    #[test]
+    fn costs_kept_by_name_and_fingerprint() {
+        let graph = capitol_hill();
+        let shared = |a: &dyn CostingModel, b: &dyn CostingModel| {
+            Arc::ptr_eq(
+                &graph.costing(a).unwrap().cache,
+                &graph.costing(b).unwrap().cache,
+            )
+        };
+
+        let walking = pedestrian_costing_model(1.4).with_name("walking");
+        assert!(shared(
+            &walking,
+            &pedestrian_costing_model(1.4).with_name("walking")
+        ));
+        // Named alike but costing differently.
+        assert!(!shared(
+            &walking,
+            &pedestrian_costing_model(2.8).with_name("walking")
+        ));
+        let unnamed = BaseCostingModel::new(
+            |_direction, _tags| Some(WayCost::from_speed(TravelSpeed::from_kmh(5.0))),
+            |_tags, _transitions| TransitionCostResult::impassable(),
+        );
+        assert!(!shared(&unnamed, &unnamed));
+        assert!(graph.prepare_contraction_hierarchy(&unnamed).is_err());
+
+        // Emptying a cache forgets the fastest way it had costed.
+        let costing = graph.costing(&walking).unwrap();
+        let (way, _) = graph.nearest_way(&walking, &CAPITOL_HILL_FROM).unwrap();
+        assert!(graph.way_coster(&costing, &way).is_some());
+        assert!(costing.cache().fastest_way.is_some());
+        graph.clear().expect("Failed to clear graph");
+        assert!(costing.cache().fastest_way.is_none());
+    }
+
+    #[test]
+    fn cost_caches_keep_the_most_recently_used() {
+        let mut caches = CostCaches::default();
+        let first = caches.get("model 0", 0);
+        for model in 1..MAX_COST_CACHES {
+            caches.get(&format!("model {}", model), 0);
+        }
+        assert!(Arc::ptr_eq(&caches.get("model 0", 0), &first));
+
+        // Model 1 is now the least recently used, so it makes way.
+        caches.insert("one too many", 0, CostCache::new());
+        assert_eq!(caches.caches.len(), MAX_COST_CACHES);
+        assert!(caches.caches.contains_key("model 0"));
+        assert!(!caches.caches.contains_key("model 1"));
+    }
+}
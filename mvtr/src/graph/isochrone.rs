@@ -0,0 +1,379 @@
+use std::collections::{BinaryHeap, HashMap};
+
+use geo::{ConcaveHull, Distance, Haversine, Length, LineString, MultiLineString};
+use serde_json::json;
+
+use crate::costing::{CostingModel, RoutingCost, units::ElapsedTime};
+
+use super::{Costing, Graph, SearchNode, SearchState, WayId};
+
+/// Passed to `ConcaveHull`. Lower values hug the reached ways more tightly, higher values approach
+/// the convex hull.
+const ISOCHRONE_HULL_CONCAVITY: f64 = 2.0;
+
+/// A stretch of a way reachable within an isochrone's budget, in the direction it's reached.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReachedSegment {
+    way: WayId,
+    from_distance_along_way_mm: i32,
+    to_distance_along_way_mm: i32,
+    remaining_at_start: ElapsedTime,
+    remaining_at_end: ElapsedTime,
+    geometry: LineString,
+}
+
+impl ReachedSegment {
+    pub fn way(&self) -> WayId {
+        self.way
+    }
+
+    pub fn from_distance_along_way_mm(&self) -> i32 {
+        self.from_distance_along_way_mm
+    }
+
+    pub fn to_distance_along_way_mm(&self) -> i32 {
+        self.to_distance_along_way_mm
+    }
+
+    /// The budget left on reaching the start of the segment.
+    pub fn remaining_at_start(&self) -> ElapsedTime {
+        self.remaining_at_start
+    }
+
+    /// The budget left on reaching the end of the segment. Zero if the budget ran out partway
+    /// along the way.
+    pub fn remaining_at_end(&self) -> ElapsedTime {
+        self.remaining_at_end
+    }
+
+    pub fn geometry(&self) -> LineString {
+        self.geometry.clone()
+    }
+
+    /// The part of the segment's geometry reached within `elapsed` of the origin.
+    fn geometry_within(&self, budget: ElapsedTime, elapsed: ElapsedTime) -> Option<LineString> {
+        let elapsed_at_start = budget - self.remaining_at_start;
+        let elapsed_at_end = budget - self.remaining_at_end;
+        if elapsed_at_start > elapsed {
+            return None;
+        }
+        if elapsed_at_end <= elapsed {
+            return Some(self.geometry.clone());
+        }
+        let fraction = (elapsed - elapsed_at_start).millis() as f64
+            / (elapsed_at_end - elapsed_at_start).millis() as f64;
+        let target_meters = Haversine.length(&self.geometry) * fraction;
+
+        let mut travelled_meters = 0.0;
+        let mut coords = vec![*self.geometry.0.first()?];
+        for line in self.geometry.lines() {
+            let line_meters = Haversine.distance(line.start_point(), line.end_point());
+            if travelled_meters + line_meters >= target_meters {
+                let line_fraction = if line_meters > 0.0 {
+                    (target_meters - travelled_meters) / line_meters
+                } else {
+                    0.0
+                };
+                coords.push(line.start + (line.end - line.start) * line_fraction);
+                break;
+            }
+            travelled_meters += line_meters;
+            coords.push(line.end);
+        }
+        Some(LineString::new(coords))
+    }
+}
+
+/// Everything reachable from an origin within a budget of elapsed time equivalent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Isochrone {
+    budget: ElapsedTime,
+    segments: Vec<ReachedSegment>,
+}
+
+impl Isochrone {
+    pub fn budget(&self) -> ElapsedTime {
+        self.budget
+    }
+
+    pub fn segments(&self) -> Vec<ReachedSegment> {
+        self.segments.clone()
+    }
+
+    /// A GeoJSON `FeatureCollection` with a concave hull around everything reached within each
+    /// band, in the order given. Each feature's `time_seconds` property is its band.
+    pub fn polygons_geojson(&self, bands: &[ElapsedTime]) -> serde_json::Value {
+        let features: Vec<serde_json::Value> = bands
+            .iter()
+            .filter_map(|band| {
+                let lines: Vec<LineString> = self
+                    .segments
+                    .iter()
+                    .filter_map(|segment| segment.geometry_within(self.budget, *band))
+                    .collect();
+                if lines.is_empty() {
+                    return None;
+                }
+                let hull = MultiLineString::new(lines).concave_hull(ISOCHRONE_HULL_CONCAVITY);
+                let ring: Vec<[f64; 2]> = hull
+                    .exterior()
+                    .coords()
+                    .map(|coord| [coord.x, coord.y])
+                    .collect();
+                Some(json!({
+                    "type": "Feature",
+                    "properties": {
+                        "time_seconds": band.millis() as f64 / 1000.0,
+                    },
+                    "geometry": {
+                        "type": "Polygon",
+                        "coordinates": [ring],
+                    },
+                }))
+            })
+            .collect();
+        json!({
+            "type": "FeatureCollection",
+            "features": features,
+        })
+    }
+}
+
+impl Graph {
+    /// Expands from a point until `budget` of elapsed time equivalent runs out, returning every
+    /// way segment reached along the way.
+    pub fn search_isochrone(
+        &self,
+        costing_model: &dyn CostingModel,
+        start: WayId,
+        distance_along_start_mm: i32,
+        budget: ElapsedTime,
+    ) -> Isochrone {
+        let costing = if let Ok(costing) = self.costing(costing_model) {
+            costing
+        } else {
+            return Isochrone {
+                budget,
+                segments: Vec::new(),
+            };
+        };
+        let start_node = SearchNode {
+            way: start,
+            distance_along_way_mm: distance_along_start_mm,
+        };
+        let mut frontier = BinaryHeap::new();
+        frontier.push(SearchState {
+            previous: 0,
+            idx: 0,
+            node: start_node,
+            via: start_node,
+            entering: false,
+            cost: RoutingCost::zero(),
+            priority: RoutingCost::zero(),
+        });
+        let mut costs: HashMap<(SearchNode, bool), RoutingCost> = HashMap::new();
+        costs.insert((start_node, false), RoutingCost::zero());
+        let mut segments = Vec::new();
+
+        while let Some(state) = frontier.pop() {
+            if costs
+                .get(&(state.node, state.entering))
+                .is_some_and(|cost| *cost < state.cost)
+            {
+                continue;
+            }
+            segments.extend(self.reached_segments(
+                &costing,
+                &state.node,
+                state.entering,
+                state.cost,
+                budget,
+            ));
+            for edge in self.forward_edges(&costing, &state.node, state.entering, None) {
+                let cost = state.cost + edge.cost;
+                let label = (edge.to, edge.enters);
+                if cost.elapsed_equivalent() > budget
+                    || costs.get(&label).is_some_and(|best| *best <= cost)
+                {
+                    continue;
+                }
+                costs.insert(label, cost);
+                frontier.push(SearchState {
+                    previous: state.idx,
+                    idx: 0,
+                    node: edge.to,
+                    via: edge.via,
+                    entering: edge.enters,
+                    cost,
+                    priority: cost,
+                });
+            }
+        }
+
+        Isochrone { budget, segments }
+    }
+
+    /// The segments reachable from `node`, `entering` its way or not, in each direction along its
+    /// way, up to the nearest intersection or the end of the way, and cut short where the budget
+    /// runs out.
+    fn reached_segments(
+        &self,
+        costing: &Costing,
+        node: &SearchNode,
+        entering: bool,
+        cost: RoutingCost,
+        budget: ElapsedTime,
+    ) -> Vec<ReachedSegment> {
+        let (way_start_mm, way_end_mm) = if let Some(span) = self.way_span_mm(&node.way) {
+            span
+        } else {
+            return Vec::new();
+        };
+        let distance = node.distance_along_way_mm;
+        let nodes = self.node_distances(&node.way);
+        let after = nodes
+            .iter()
+            .find(|node| **node > distance)
+            .copied()
+            .unwrap_or(way_end_mm);
+        let before = nodes
+            .iter()
+            .rev()
+            .find(|node| **node < distance)
+            .copied()
+            .unwrap_or(way_start_mm);
+        let remaining_at_start = budget - cost.elapsed_equivalent();
+
+        let mut segments = Vec::new();
+        for next in [after, before] {
+            if next == distance {
+                continue;
+            }
+            let travel_cost = if let Some(travel_cost) =
+                self.cost_along_way(costing, &node.way, distance, next, entering)
+            {
+                travel_cost.elapsed_equivalent()
+            } else {
+                continue;
+            };
+            let (to_distance, remaining_at_end) = if travel_cost <= remaining_at_start {
+                (next, remaining_at_start - travel_cost)
+            } else {
+                let fraction = remaining_at_start.millis() as f64 / travel_cost.millis() as f64;
+                let to_distance = distance + ((next - distance) as f64 * fraction) as i32;
+                (to_distance, ElapsedTime::zero())
+            };
+            if to_distance == distance {
+                continue;
+            }
+            if let Some(section) = self.way_section(&node.way, distance, to_distance) {
+                segments.push(ReachedSegment {
+                    way: node.way,
+                    from_distance_along_way_mm: distance,
+                    to_distance_along_way_mm: to_distance,
+                    remaining_at_start,
+                    remaining_at_end,
+                    geometry: section.into_iter().collect(),
+                });
+            }
+        }
+        segments
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use geo::Coord;
+
+    use crate::costing::{pedestrian::pedestrian_costing_model, units::ElapsedTime};
+
+    use super::super::Graph;
+
+    #[test]
+    fn isochrone_basic() {
+        let costing_model = pedestrian_costing_model(1.4);
+        let graph = Graph::new();
+        graph
+            .ingest_tile(
+                2625,
+                5721,
+                14,
+                include_bytes!("../../testdata/tile.pbf").to_vec(),
+                include_bytes!("../../testdata/tile.pbf").to_vec(),
+            )
+            .expect("Failed to ingest tile");
+        let (from_way_id, from_way_distance) = graph
+            .nearest_way(
+                &costing_model,
+                &Coord {
+                    x: -122.3126740,
+                    y: 47.6153470,
+                },
+            )
+            .unwrap();
+        let (to_way_id, to_way_distance) = graph
+            .nearest_way(
+                &costing_model,
+                &Coord {
+                    x: -122.315503,
+                    y: 47.6163794,
+                },
+            )
+            .unwrap();
+        let route_cost = graph
+            .search_djikstra(
+                &costing_model,
+                from_way_id,
+                from_way_distance,
+                to_way_id,
+                to_way_distance,
+            )
+            .expect("Couldn't find a route.")
+            .route_cost_seconds();
+
+        let reaches_destination = |budget_seconds: f64| {
+            let isochrone = graph.search_isochrone(
+                &costing_model,
+                from_way_id,
+                from_way_distance,
+                ElapsedTime::from_seconds(budget_seconds),
+            );
+            assert!(isochrone.segments().iter().all(|segment| {
+                segment.remaining_at_end() <= segment.remaining_at_start()
+                    && segment.remaining_at_start() <= isochrone.budget()
+            }));
+            isochrone.segments().iter().any(|segment| {
+                let from = segment.from_distance_along_way_mm();
+                let to = segment.to_distance_along_way_mm();
+                segment.way() == to_way_id
+                    && from.min(to) <= to_way_distance
+                    && to_way_distance <= from.max(to)
+            })
+        };
+        assert!(!reaches_destination(route_cost - 10.0));
+        assert!(reaches_destination(route_cost + 10.0));
+
+        let isochrone = graph.search_isochrone(
+            &costing_model,
+            from_way_id,
+            from_way_distance,
+            ElapsedTime::from_seconds(120.0),
+        );
+        let geojson = isochrone.polygons_geojson(&[
+            ElapsedTime::from_seconds(60.0),
+            ElapsedTime::from_seconds(120.0),
+        ]);
+        let features = geojson["features"].as_array().unwrap();
+        assert_eq!(features.len(), 2);
+        for feature in features {
+            assert_eq!(feature["geometry"]["type"], "Polygon");
+            assert!(
+                feature["geometry"]["coordinates"][0]
+                    .as_array()
+                    .unwrap()
+                    .len()
+                    >= 4
+            );
+        }
+    }
+}
@@ -0,0 +1,395 @@
+use geo::{Bearing, Haversine, InterpolateLine, Length};
+use serde::Serialize;
+
+use crate::costing::{RoutingCost, Tags};
+
+use super::{Graph, SearchState, WayId};
+
+/// How far along a way to look when measuring its bearing at a point, so that small kinks in the
+/// geometry right at an intersection don't decide the turn type.
+const BEARING_SAMPLE_DISTANCE_MM: i32 = 10_000;
+/// How much of a way has to lie beyond an intersection for it to count as a leg there, so that
+/// rounding in distances along ways that end at an intersection doesn't add a leg. Ways shorter
+/// than twice this only need half their length beyond it.
+const MIN_LEG_LENGTH_MM: i32 = 1_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum TurnType {
+    Depart,
+    Continue,
+    SlightRight,
+    Right,
+    SharpRight,
+    UTurn,
+    SharpLeft,
+    Left,
+    SlightLeft,
+    Arrive,
+}
+
+impl TurnType {
+    /// Classifies a change in bearing, in degrees clockwise.
+    fn from_bearing_change(degrees: f64) -> TurnType {
+        let degrees = (degrees + 180.0).rem_euclid(360.0) - 180.0;
+        let right = degrees > 0.0;
+        match degrees.abs() {
+            change if change < 20.0 => TurnType::Continue,
+            change if change < 60.0 && right => TurnType::SlightRight,
+            change if change < 60.0 => TurnType::SlightLeft,
+            change if change < 120.0 && right => TurnType::Right,
+            change if change < 120.0 => TurnType::Left,
+            change if change < 165.0 && right => TurnType::SharpRight,
+            change if change < 165.0 => TurnType::SharpLeft,
+            _ => TurnType::UTurn,
+        }
+    }
+}
+
+/// A single instruction in turn-by-turn directions: how to get onto a street, and the cost of
+/// following it up to the next maneuver.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub struct Maneuver {
+    turn_type: TurnType,
+    street_name: Option<String>,
+    cost: RoutingCost,
+}
+
+impl Maneuver {
+    pub fn turn_type(&self) -> TurnType {
+        self.turn_type
+    }
+
+    pub fn street_name(&self) -> Option<String> {
+        self.street_name.clone()
+    }
+
+    pub fn distance_meters(&self) -> f64 {
+        self.cost.distance().mm() as f64 / 1000.0
+    }
+
+    pub fn duration_seconds(&self) -> f64 {
+        self.cost.elapsed_actual().millis() as f64 / 1000.0
+    }
+}
+
+/// The name to give directions by, from a way's `name` and `ref` tags.
+pub(super) fn street_name(tags: &Tags) -> Option<String> {
+    match (tags.get("name"), tags.get("ref")) {
+        (Some(name), Some(reference)) => Some(format!("{} ({})", name, reference)),
+        (Some(name), None) => Some(name.to_string()),
+        (None, Some(reference)) => Some(reference.to_string()),
+        (None, None) => None,
+    }
+}
+
+/// A stretch of the route along a single way, between transitions onto other ways.
+struct Stretch {
+    way: WayId,
+    /// The bearings the stretch starts and ends with, if it has any length.
+    bearings: Option<(f64, f64)>,
+    cost: RoutingCost,
+}
+
+impl Graph {
+    /// Turns the states of a route, as returned by `unwind_route`, into turn-by-turn directions.
+    pub(super) fn build_maneuvers(&self, states: &[SearchState]) -> Vec<Maneuver> {
+        let mut stretches: Vec<Stretch> = Vec::new();
+        for window in states.windows(2) {
+            let way = window[0].node.way;
+            let cost = window[1].cost - window[0].cost;
+            let bearings = self.travel_bearings(
+                &way,
+                window[0].node.distance_along_way_mm,
+                window[1].via.distance_along_way_mm,
+            );
+            match stretches.last_mut() {
+                Some(stretch) if stretch.way == way => {
+                    stretch.bearings = match (stretch.bearings, bearings) {
+                        (Some((start, _)), Some((_, end))) => Some((start, end)),
+                        (stretch_bearings, bearings) => stretch_bearings.or(bearings),
+                    };
+                    stretch.cost = stretch.cost + cost;
+                }
+                _ => stretches.push(Stretch {
+                    way,
+                    bearings,
+                    cost,
+                }),
+            }
+        }
+
+        let mut maneuvers: Vec<Maneuver> = Vec::new();
+        let mut heading: Option<f64> = None;
+        let mut unassigned_cost = RoutingCost::zero();
+        for stretch in &stretches {
+            let (start_bearing, end_bearing) = if let Some(bearings) = stretch.bearings {
+                bearings
+            } else {
+                // Crossing a way at a single intersection isn't a maneuver of its own.
+                if let Some(maneuver) = maneuvers.last_mut() {
+                    maneuver.cost = maneuver.cost + stretch.cost;
+                } else {
+                    unassigned_cost = unassigned_cost + stretch.cost;
+                }
+                continue;
+            };
+            let street_name = self.street_name(&stretch.way);
+            let turn_type = heading
+                .map(|heading| TurnType::from_bearing_change(start_bearing - heading))
+                .unwrap_or(TurnType::Depart);
+            heading = Some(end_bearing);
+            match maneuvers.last_mut() {
+                Some(maneuver)
+                    if turn_type == TurnType::Continue && maneuver.street_name == street_name =>
+                {
+                    maneuver.cost = maneuver.cost + stretch.cost;
+                }
+                _ => maneuvers.push(Maneuver {
+                    turn_type,
+                    street_name,
+                    cost: unassigned_cost + stretch.cost,
+                }),
+            }
+            unassigned_cost = RoutingCost::zero();
+        }
+
+        if let Some(stretch) = stretches.last() {
+            let street_name = self.street_name(&stretch.way);
+            if maneuvers.is_empty() {
+                maneuvers.push(Maneuver {
+                    turn_type: TurnType::Depart,
+                    street_name: street_name.clone(),
+                    cost: unassigned_cost,
+                });
+            }
+            maneuvers.push(Maneuver {
+                turn_type: TurnType::Arrive,
+                street_name,
+                cost: RoutingCost::zero(),
+            });
+        }
+        maneuvers
+    }
+
+    fn street_name(&self, way: &WayId) -> Option<String> {
+        self.street_names_read
+            .get_one(way)
+            .map(|street_name| street_name.clone())
+    }
+
+    /// The bearings, in degrees clockwise from north, at the start and end of travel along a way
+    /// between two distances.
+    fn travel_bearings(&self, way: &WayId, from_mm: i32, to_mm: i32) -> Option<(f64, f64)> {
+        if from_mm == to_mm {
+            return None;
+        }
+        let sample =
+            (to_mm - from_mm).clamp(-BEARING_SAMPLE_DISTANCE_MM, BEARING_SAMPLE_DISTANCE_MM);
+        let start = Haversine.bearing(
+            self.point_along_way(way, from_mm)?,
+            self.point_along_way(way, from_mm + sample)?,
+        );
+        let end = Haversine.bearing(
+            self.point_along_way(way, to_mm - sample)?,
+            self.point_along_way(way, to_mm)?,
+        );
+        Some((start, end))
+    }
+
+    /// The bearings, in degrees clockwise from north, of travel leaving a point on a way forward
+    /// and in reverse, or `None` for a direction in which the way's loaded geometry ends there.
+    pub(super) fn leg_bearings(
+        &self,
+        way: &WayId,
+        distance_along_way_mm: i32,
+    ) -> (Option<f64>, Option<f64>) {
+        let Some((placement, polyline)) = self.way_geometry(way) else {
+            return (None, None);
+        };
+        let length_mm = (Haversine.length(&polyline) * 1000.0) as i32;
+        let at_mm = ((placement.polyline_meters(distance_along_way_mm) * 1000.0) as i32)
+            .clamp(0, length_mm);
+        let min_leg_length_mm = MIN_LEG_LENGTH_MM.min(length_mm / 2).max(1);
+        let point = |mm: i32| Haversine.point_at_distance_from_start(&polyline, mm as f64 / 1000.0);
+        let bearing_towards = |mm: i32| {
+            if (mm - at_mm).abs() < min_leg_length_mm {
+                return None;
+            }
+            Some(Haversine.bearing(point(at_mm)?, point(mm)?))
+        };
+        (
+            bearing_towards((at_mm + BEARING_SAMPLE_DISTANCE_MM).min(length_mm)),
+            bearing_towards((at_mm - BEARING_SAMPLE_DISTANCE_MM).max(0)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use crate::costing::{
+        Tags, TransitionCostResult, TransitionGeometry, TransitionToCost, Turn,
+        base::{BaseCostingModel, WayCost},
+        pedestrian::pedestrian_costing_model,
+        units::{Direction, TravelSpeed},
+    };
+
+    use super::super::{Graph, WayId, WayTransition, testing::tags};
+    use super::TurnType;
+
+    #[test]
+    fn turn_types_from_bearing_change() {
+        assert_eq!(TurnType::from_bearing_change(5.0), TurnType::Continue);
+        assert_eq!(TurnType::from_bearing_change(-350.0), TurnType::Continue);
+        assert_eq!(TurnType::from_bearing_change(45.0), TurnType::SlightRight);
+        assert_eq!(TurnType::from_bearing_change(270.0), TurnType::Left);
+        assert_eq!(TurnType::from_bearing_change(-140.0), TurnType::SharpLeft);
+        assert_eq!(TurnType::from_bearing_change(180.0), TurnType::UTurn);
+    }
+
+    #[test]
+    fn maneuvers_fremont() {
+        let costing_model = pedestrian_costing_model(1.4);
+        let graph = Graph::new();
+        graph
+            .ingest_tile(
+                2623,
+                5718,
+                14,
+                include_bytes!("../../testdata/tile2.pbf").to_vec(),
+                include_bytes!("../../testdata/tile2.pbf").to_vec(),
+            )
+            .expect("Failed to ingest tile");
+        let result = graph
+            .search_djikstra(&costing_model, WayId(671949014), 0, WayId(980366562), 0)
+            .expect("Couldn't find a route.");
+
+        let maneuvers = result.maneuvers();
+        assert_eq!(maneuvers.first().unwrap().turn_type(), TurnType::Depart);
+        assert_eq!(maneuvers.last().unwrap().turn_type(), TurnType::Arrive);
+        let total_distance: f64 = maneuvers
+            .iter()
+            .map(|maneuver| maneuver.distance_meters())
+            .sum();
+        assert!((total_distance - result.route_distance_meters()).abs() < 0.01);
+        for window in maneuvers.windows(2) {
+            assert!(
+                window[1].turn_type() != TurnType::Continue
+                    || window[0].street_name() != window[1].street_name()
+            );
+        }
+        assert!(maneuvers.iter().any(|maneuver| {
+            maneuver.street_name().as_deref() == Some("North 44th Street")
+                && maneuver.turn_type() == TurnType::Right
+        }));
+    }
+
+    #[test]
+    fn transition_geometry_fremont() {
+        // Every transition the search costs, with its turns and the number of other legs.
+        let costed: Mutex<Vec<(Vec<Turn>, Option<u32>)>> = Mutex::new(Vec::new());
+        let costing_model = BaseCostingModel::new(
+            |_direction, _tags: &Tags| {
+                Some(WayCost::from_speed(TravelSpeed::from_meters_per_second(
+                    1.4,
+                )))
+            },
+            |_tags: &Tags, transitions_to_cost: &[TransitionToCost]| {
+                costed.lock().unwrap().extend(
+                    transitions_to_cost
+                        .iter()
+                        .map(|transition| (transition.turns(), transition.other_legs())),
+                );
+                let transitions: Vec<WayTransition> = transitions_to_cost
+                    .iter()
+                    .map(|transition| transition.way_transition)
+                    .collect();
+                TransitionCostResult::zero(&transitions)
+            },
+        );
+        let graph = Graph::new();
+        graph
+            .ingest_tile(
+                2623,
+                5718,
+                14,
+                include_bytes!("../../testdata/tile2.pbf").to_vec(),
+                include_bytes!("../../testdata/tile2.pbf").to_vec(),
+            )
+            .expect("Failed to ingest tile");
+        graph
+            .search_djikstra(&costing_model, WayId(671949014), 0, WayId(980366562), 0)
+            .expect("Couldn't find a route.");
+
+        let costed = costed.into_inner().unwrap();
+        assert!(!costed.is_empty());
+        assert!(costed.iter().all(|(turns, other_legs)| {
+            !turns.is_empty()
+                && turns
+                    .iter()
+                    .all(|turn| (-180.0..=180.0).contains(&turn.angle()))
+                && other_legs.is_some()
+        }));
+        // A street grid: going straight across four-way intersections, and turning at them.
+        let at_crossroads = |angle: fn(f64) -> bool| {
+            costed.iter().any(|(turns, other_legs)| {
+                *other_legs == Some(2) && turns.iter().any(|turn| angle(turn.angle()))
+            })
+        };
+        assert!(at_crossroads(|angle| angle.abs() < 20.0));
+        assert!(at_crossroads(|angle| (60.0..120.0).contains(&angle)));
+        assert!(at_crossroads(|angle| (-120.0..-60.0).contains(&angle)));
+    }
+
+    #[test]
+    fn transition_geometry_reverse_arrival() {
+        // An east-west street meeting a north-south one part way along both.
+        let street = tags(&[("highway", "residential")]);
+        let transition = TransitionToCost {
+            way_transition: WayTransition::new(WayId::from_id(1), 0, WayId::from_id(2), 0),
+            from_way_tags: &street,
+            to_way_tags: &street,
+            intersection_tags: &street,
+            geometry: Some(TransitionGeometry {
+                from_legs: (Some(90.0), Some(270.0)),
+                to_legs: (Some(0.0), Some(180.0)),
+                other_legs: 2,
+            }),
+        };
+
+        // Heading east, north is to the left; heading west, it's to the right.
+        assert_eq!(transition.entry_bearing(Direction::Forward), Some(90.0));
+        assert_eq!(transition.entry_bearing(Direction::Reverse), Some(270.0));
+        assert_eq!(
+            transition.turn_angle(Direction::Forward, Direction::Forward),
+            Some(-90.0)
+        );
+        assert_eq!(
+            transition.turn_angle(Direction::Reverse, Direction::Forward),
+            Some(90.0)
+        );
+        assert_eq!(
+            transition.turn_angle(Direction::Reverse, Direction::Reverse),
+            Some(-90.0)
+        );
+        assert_eq!(transition.turns().len(), 4);
+
+        // A street that ends at the intersection can only be arrived along forward.
+        let dead_end = TransitionToCost {
+            geometry: Some(TransitionGeometry {
+                from_legs: (None, Some(270.0)),
+                to_legs: (Some(0.0), Some(180.0)),
+                other_legs: 1,
+            }),
+            ..transition
+        };
+        assert_eq!(dead_end.entry_bearing(Direction::Reverse), None);
+        assert!(
+            dead_end
+                .turns()
+                .iter()
+                .all(|turn| turn.arriving() == Direction::Forward)
+        );
+    }
+}
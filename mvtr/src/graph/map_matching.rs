@@ -0,0 +1,382 @@
+use geo::{Distance, Haversine, Point};
+
+use crate::costing::{
+    CostingModel,
+    units::{ElapsedTime, TravelledDistance},
+};
+
+use super::{Graph, SearchNode, WayId};
+
+/// Ways further than this from a GPS fix aren't considered as matches for it.
+const MATCH_CANDIDATE_RADIUS_METERS: f64 = 50.0;
+/// Only this many of the nearest ways are considered as matches for each fix.
+const MATCH_MAX_CANDIDATES: usize = 8;
+/// The standard deviation of GPS error, for the emission probability of each candidate.
+const MATCH_GPS_SIGMA_METERS: f64 = 5.0;
+/// How quickly transitions become less likely as the route between candidates gets longer than
+/// the straight line between fixes.
+const MATCH_TRANSITION_BETA_METERS: f64 = 5.0;
+/// Routes between candidates that would need travelling faster than this between fixes, allowing
+/// for GPS error at both ends, aren't considered.
+const MATCH_MAX_SPEED_METERS_PER_SECOND: f64 = 60.0;
+
+/// A timestamped GPS fix.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TracePoint {
+    coord: geo::Coord,
+    time: ElapsedTime,
+}
+
+impl TracePoint {
+    pub fn new(coord: geo::Coord, time: ElapsedTime) -> TracePoint {
+        TracePoint { coord, time }
+    }
+}
+
+/// Where a single GPS fix was matched to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MatchedPoint {
+    way: WayId,
+    distance_along_way_mm: i32,
+    distance_meters: f64,
+    confidence: f64,
+}
+
+impl MatchedPoint {
+    pub fn way(&self) -> WayId {
+        self.way
+    }
+
+    pub fn distance_along_way_mm(&self) -> i32 {
+        self.distance_along_way_mm
+    }
+
+    /// How far the fix is from where it was matched to.
+    pub fn distance_meters(&self) -> f64 {
+        self.distance_meters
+    }
+
+    /// The share of the probability among this fix's candidates, given the fixes before it, that
+    /// the match holds. Between 0 and 1.
+    pub fn confidence(&self) -> f64 {
+        self.confidence
+    }
+}
+
+/// A GPS trace matched onto the graph.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchedTrace {
+    ways: Vec<WayId>,
+    encoded_polyline: String,
+    points: Vec<Option<MatchedPoint>>,
+}
+
+impl MatchedTrace {
+    /// The ways travelled along, in order.
+    pub fn ways(&self) -> Vec<WayId> {
+        self.ways.clone()
+    }
+
+    /// The route travelled, following the ways. Where the trace couldn't be matched continuously,
+    /// the line jumps straight to where matching picks up again.
+    pub fn encoded_polyline(&self) -> String {
+        self.encoded_polyline.clone()
+    }
+
+    /// The match for each point of the trace, or `None` where no way was close enough.
+    pub fn points(&self) -> Vec<Option<MatchedPoint>> {
+        self.points.clone()
+    }
+}
+
+/// The candidates for one fix and the Viterbi state of each.
+struct Column {
+    point_idx: usize,
+    candidates: Vec<(SearchNode, f64)>,
+    /// The log probability of the likeliest sequence of candidates ending at each candidate.
+    scores: Vec<f64>,
+    /// The candidate in the previous column that sequence came from, or `None` if the sequence
+    /// starts here.
+    previous: Vec<Option<usize>>,
+}
+
+impl Column {
+    fn best(&self) -> usize {
+        (0..self.scores.len())
+            .max_by(|a, b| self.scores[*a].total_cmp(&self.scores[*b]))
+            .unwrap_or(0)
+    }
+
+    fn confidence(&self, candidate: usize) -> f64 {
+        let best = self.scores[self.best()];
+        let total: f64 = self.scores.iter().map(|score| (score - best).exp()).sum();
+        (self.scores[candidate] - best).exp() / total
+    }
+}
+
+impl Graph {
+    /// Matches a GPS trace onto the graph with a hidden Markov model: each fix is matched to one
+    /// of the ways near it, and the likeliest sequence of matches is found with the Viterbi
+    /// algorithm. Matches are likelier the closer they are to their fix, and consecutive matches
+    /// likelier the closer the route between them is in length to the straight line between
+    /// their fixes. `None` if no point of the trace could be matched.
+    pub fn match_trace(
+        &self,
+        costing_model: &dyn CostingModel,
+        trace: &[TracePoint],
+    ) -> Option<MatchedTrace> {
+        let costing = self.costing(costing_model).ok()?;
+        let mut columns: Vec<Column> = Vec::new();
+        for (point_idx, point) in trace.iter().enumerate() {
+            let candidates: Vec<(SearchNode, f64)> = self
+                .ways_within_radius(costing_model, &point.coord, MATCH_CANDIDATE_RADIUS_METERS)
+                .ok()?
+                .into_iter()
+                .take(MATCH_MAX_CANDIDATES)
+                .map(|(way, distance_along_way_mm, distance)| {
+                    (
+                        SearchNode {
+                            way,
+                            distance_along_way_mm,
+                        },
+                        distance,
+                    )
+                })
+                .collect();
+            if candidates.is_empty() {
+                continue;
+            }
+            let emissions: Vec<f64> = candidates
+                .iter()
+                .map(|(_, distance)| -0.5 * (distance / MATCH_GPS_SIGMA_METERS).powi(2))
+                .collect();
+            let mut scores = vec![f64::NEG_INFINITY; candidates.len()];
+            let mut previous = vec![None; candidates.len()];
+            if let Some(last) = columns.last() {
+                let last_point = &trace[last.point_idx];
+                let straight_meters =
+                    Haversine.distance(Point::from(last_point.coord), Point::from(point.coord));
+                let elapsed_seconds = (point.time - last_point.time).millis() as f64 / 1000.0;
+                let max_meters = MATCH_MAX_SPEED_METERS_PER_SECOND * elapsed_seconds
+                    + 2.0 * MATCH_CANDIDATE_RADIUS_METERS;
+                let targets: Vec<SearchNode> = candidates.iter().map(|(node, _)| *node).collect();
+                for (from_idx, (from, _)) in last.candidates.iter().enumerate() {
+                    let route_costs = self.search_one_to_many(
+                        &costing,
+                        from,
+                        &targets,
+                        Some(TravelledDistance((max_meters * 1000.0) as u64)),
+                    );
+                    for (to_idx, cost) in route_costs.iter().enumerate() {
+                        let route_meters = if let Some(cost) = cost {
+                            cost.distance().mm() as f64 / 1000.0
+                        } else {
+                            continue;
+                        };
+                        let transition =
+                            -(route_meters - straight_meters).abs() / MATCH_TRANSITION_BETA_METERS;
+                        let score = last.scores[from_idx] + transition + emissions[to_idx];
+                        if score > scores[to_idx] {
+                            scores[to_idx] = score;
+                            previous[to_idx] = Some(from_idx);
+                        }
+                    }
+                }
+            }
+            // With no route from any previous candidate, the trace is matched afresh from here.
+            if scores.iter().all(|score| *score == f64::NEG_INFINITY) {
+                scores = emissions;
+                previous = vec![None; candidates.len()];
+            }
+            columns.push(Column {
+                point_idx,
+                candidates,
+                scores,
+                previous,
+            });
+        }
+
+        // Walk back along the likeliest sequence, splitting it into the continuously matched
+        // stretches of the trace.
+        let mut chosen = vec![0; columns.len()];
+        let mut chains: Vec<Vec<usize>> = vec![Vec::new()];
+        let mut candidate = columns.last()?.best();
+        for column_idx in (0..columns.len()).rev() {
+            chosen[column_idx] = candidate;
+            chains.last_mut()?.push(column_idx);
+            if let Some(previous) = columns[column_idx].previous[candidate] {
+                candidate = previous;
+            } else if column_idx > 0 {
+                candidate = columns[column_idx - 1].best();
+                chains.push(Vec::new());
+            }
+        }
+
+        let mut ways: Vec<WayId> = Vec::new();
+        let mut route_polyline: Vec<Point> = Vec::new();
+        for chain in chains.iter().rev() {
+            let nodes: Vec<SearchNode> = chain
+                .iter()
+                .rev()
+                .map(|column_idx| columns[*column_idx].candidates[chosen[*column_idx]].0)
+                .collect();
+            let (states, _) = self.search_chained(&costing, &nodes)?;
+            let mut chain_ways = vec![nodes[0].way];
+            for window in states.windows(2) {
+                if window[0].node.distance_along_way_mm != window[1].via.distance_along_way_mm {
+                    chain_ways.push(window[0].node.way);
+                }
+            }
+            chain_ways.push(nodes[nodes.len() - 1].way);
+            for way in chain_ways {
+                if ways.last() != Some(&way) {
+                    ways.push(way);
+                }
+            }
+            let points = if states.len() > 1 {
+                self.route_points(&states)?
+            } else {
+                vec![self.point_along_way(&nodes[0].way, nodes[0].distance_along_way_mm)?]
+            };
+            for point in points {
+                if route_polyline.last() != Some(&point) {
+                    route_polyline.push(point);
+                }
+            }
+        }
+
+        let mut points = vec![None; trace.len()];
+        for (column, candidate) in columns.iter().zip(chosen) {
+            let (node, distance_meters) = column.candidates[candidate];
+            points[column.point_idx] = Some(MatchedPoint {
+                way: node.way,
+                distance_along_way_mm: node.distance_along_way_mm,
+                distance_meters,
+                confidence: column.confidence(candidate),
+            });
+        }
+
+        Some(MatchedTrace {
+            ways,
+            encoded_polyline: polyline::encode_coordinates(
+                route_polyline.iter().map(|point| point.0),
+                5,
+            )
+            .unwrap(),
+            points,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use geo::{Coord, Haversine, InterpolateLine, Length, LineString};
+
+    use crate::costing::{pedestrian::pedestrian_costing_model, units::ElapsedTime};
+
+    use super::super::{Graph, SearchNode};
+    use super::TracePoint;
+
+    #[test]
+    fn match_trace_basic() {
+        let costing_model = pedestrian_costing_model(1.4);
+        let graph = Graph::new();
+        graph
+            .ingest_tile(
+                2625,
+                5721,
+                14,
+                include_bytes!("../../testdata/tile.pbf").to_vec(),
+                include_bytes!("../../testdata/tile.pbf").to_vec(),
+            )
+            .expect("Failed to ingest tile");
+        let (from_way_id, from_way_distance) = graph
+            .nearest_way(
+                &costing_model,
+                &Coord {
+                    x: -122.3126740,
+                    y: 47.6153470,
+                },
+            )
+            .unwrap();
+        let (to_way_id, to_way_distance) = graph
+            .nearest_way(
+                &costing_model,
+                &Coord {
+                    x: -122.315503,
+                    y: 47.6163794,
+                },
+            )
+            .unwrap();
+        let route = graph
+            .search_djikstra(
+                &costing_model,
+                from_way_id,
+                from_way_distance,
+                to_way_id,
+                to_way_distance,
+            )
+            .expect("Couldn't find a route.");
+
+        // A fix every 10 m along the route, a walking pace apart, pushed alternately a few meters
+        // either side of it.
+        let route_line: LineString = polyline::decode_polyline(&route.encoded_polyline(), 5)
+            .expect("Failed to decode polyline");
+        let route_meters = Haversine.length(&route_line);
+        let trace: Vec<TracePoint> = (0..=(route_meters / 10.0) as usize)
+            .map(|idx| {
+                let meters = (idx as f64 * 10.0).min(route_meters);
+                let point = Haversine
+                    .point_at_distance_from_start(&route_line, meters)
+                    .unwrap();
+                let offset = if idx % 2 == 0 { 0.00002 } else { -0.00002 };
+                TracePoint::new(
+                    Coord {
+                        x: point.x() + offset,
+                        y: point.y() + offset,
+                    },
+                    ElapsedTime::from_seconds(meters / 1.4),
+                )
+            })
+            .collect();
+
+        let matched = graph
+            .match_trace(&costing_model, &trace)
+            .expect("Couldn't match trace.");
+        let points = matched.points();
+        assert_eq!(points.len(), trace.len());
+        for point in &points {
+            let point = point.expect("Point wasn't matched.");
+            assert!(point.distance_meters() < 10.0);
+            assert!(point.confidence() > 0.0 && point.confidence() <= 1.0);
+        }
+        let ways = matched.ways();
+        // Junctions are ambiguous, but every way the route goes more than 10 m along is matched.
+        let (states, _) = graph
+            .search_chained(
+                &graph.costing(&costing_model).unwrap(),
+                &[
+                    SearchNode {
+                        way: from_way_id,
+                        distance_along_way_mm: from_way_distance,
+                    },
+                    SearchNode {
+                        way: to_way_id,
+                        distance_along_way_mm: to_way_distance,
+                    },
+                ],
+            )
+            .unwrap();
+        let mut remaining = ways.iter();
+        for window in states.windows(2) {
+            let travelled_mm =
+                (window[1].via.distance_along_way_mm - window[0].node.distance_along_way_mm).abs();
+            if travelled_mm > 10_000 {
+                assert!(remaining.any(|way| *way == window[0].node.way));
+            }
+        }
+        let matched_line: LineString = polyline::decode_polyline(&matched.encoded_polyline(), 5)
+            .expect("Failed to decode polyline");
+        assert!((Haversine.length(&matched_line) - route_meters).abs() < route_meters * 0.1);
+    }
+}
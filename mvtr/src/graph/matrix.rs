@@ -0,0 +1,231 @@
+use std::collections::{BinaryHeap, HashMap};
+
+use serde::Serialize;
+
+use crate::costing::{CostingModel, RoutingCost, units::TravelledDistance};
+
+use super::{Costing, Graph, SearchNode, SearchState, WayId};
+
+/// The routing cost from every source to every target, or `None` where a target is unreachable.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub struct CostMatrix {
+    /// By source, then by target.
+    costs: Vec<Vec<Option<RoutingCost>>>,
+}
+
+impl CostMatrix {
+    pub fn cost(&self, source: usize, target: usize) -> Option<RoutingCost> {
+        *self.costs.get(source)?.get(target)?
+    }
+
+    pub fn route_distance_meters(&self, source: usize, target: usize) -> Option<f64> {
+        Some(self.cost(source, target)?.distance().mm() as f64 / 1000.0)
+    }
+
+    pub fn route_cost_seconds(&self, source: usize, target: usize) -> Option<f64> {
+        Some(self.cost(source, target)?.elapsed_equivalent().millis() as f64 / 1000.0)
+    }
+
+    pub fn route_duration_seconds(&self, source: usize, target: usize) -> Option<f64> {
+        Some(self.cost(source, target)?.elapsed_actual().millis() as f64 / 1000.0)
+    }
+}
+
+impl Graph {
+    /// Finds the cost of the cheapest route from every source to every target, each given as a way
+    /// and a distance along it. One search runs per source and stops once every target is settled.
+    /// Sources are split across up to `threads` threads; 1 searches on the calling thread.
+    pub fn search_matrix(
+        &self,
+        costing_model: &(dyn CostingModel + Sync),
+        sources: &[(WayId, i32)],
+        targets: &[(WayId, i32)],
+        threads: usize,
+    ) -> anyhow::Result<CostMatrix> {
+        let targets: Vec<SearchNode> = targets
+            .iter()
+            .map(|(way, distance_along_way_mm)| SearchNode {
+                way: *way,
+                distance_along_way_mm: *distance_along_way_mm,
+            })
+            .collect();
+        let sources: Vec<SearchNode> = sources
+            .iter()
+            .map(|(way, distance_along_way_mm)| SearchNode {
+                way: *way,
+                distance_along_way_mm: *distance_along_way_mm,
+            })
+            .collect();
+
+        if threads <= 1 || sources.len() <= 1 {
+            let costing = self.costing(costing_model)?;
+            let costs = sources
+                .iter()
+                .map(|source| self.search_one_to_many(&costing, source, &targets, None))
+                .collect();
+            return Ok(CostMatrix { costs });
+        }
+
+        let chunk_size = sources.len().div_ceil(threads);
+        let costs = std::thread::scope(|scope| {
+            let workers: Vec<_> = sources
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    let graph = self.handle();
+                    let targets = &targets;
+                    scope.spawn(move || {
+                        let costing = graph.costing(costing_model)?;
+                        Ok(chunk
+                            .iter()
+                            .map(|source| graph.search_one_to_many(&costing, source, targets, None))
+                            .collect::<Vec<_>>())
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .map(|worker| {
+                    worker
+                        .join()
+                        .map_err(|_| anyhow::anyhow!("Matrix search thread panicked"))?
+                })
+                .collect::<anyhow::Result<Vec<_>>>()
+        })?;
+        Ok(CostMatrix {
+            costs: costs.into_iter().flatten().collect(),
+        })
+    }
+
+    /// A single Dijkstra search from `source`, finishing at each target the same way
+    /// `search_djikstra` would. If `max_distance` is given, routes longer than it aren't expanded,
+    /// so targets only reachable that way are left as `None`.
+    pub(super) fn search_one_to_many(
+        &self,
+        costing: &Costing,
+        source: &SearchNode,
+        targets: &[SearchNode],
+        max_distance: Option<TravelledDistance>,
+    ) -> Vec<Option<RoutingCost>> {
+        let mut targets_by_way: HashMap<WayId, Vec<usize>> = HashMap::new();
+        for (idx, target) in targets.iter().enumerate() {
+            targets_by_way.entry(target.way).or_default().push(idx);
+        }
+        let mut target_costs: Vec<Option<RoutingCost>> = vec![None; targets.len()];
+
+        let mut frontier = BinaryHeap::new();
+        frontier.push(SearchState {
+            previous: 0,
+            idx: 0,
+            node: *source,
+            via: *source,
+            entering: false,
+            cost: RoutingCost::zero(),
+            priority: RoutingCost::zero(),
+        });
+        let mut costs: HashMap<(SearchNode, bool), RoutingCost> = HashMap::new();
+        costs.insert((*source, false), RoutingCost::zero());
+
+        while let Some(state) = frontier.pop() {
+            if costs
+                .get(&(state.node, state.entering))
+                .is_some_and(|cost| *cost < state.cost)
+            {
+                continue;
+            }
+            // Every target cheaper than this state is final, so stop once that's all of them.
+            if target_costs
+                .iter()
+                .all(|cost| cost.is_some_and(|cost| cost <= state.cost))
+            {
+                break;
+            }
+            for idx in targets_by_way.get(&state.node.way).into_iter().flatten() {
+                let target = &targets[*idx];
+                let cost = if *target == state.node {
+                    Some(state.cost)
+                } else {
+                    self.finish_cost(costing, &state.node, state.entering, target)
+                        .map(|finish_cost| state.cost + finish_cost)
+                };
+                if let Some(cost) = cost
+                    && max_distance.is_none_or(|max_distance| cost.distance() <= max_distance)
+                    && target_costs[*idx].is_none_or(|best| cost < best)
+                {
+                    target_costs[*idx] = Some(cost);
+                }
+            }
+            for edge in self.forward_edges(costing, &state.node, state.entering, None) {
+                let cost = state.cost + edge.cost;
+                let label = (edge.to, edge.enters);
+                if max_distance.is_some_and(|max_distance| cost.distance() > max_distance)
+                    || costs.get(&label).is_some_and(|best| *best <= cost)
+                {
+                    continue;
+                }
+                costs.insert(label, cost);
+                frontier.push(SearchState {
+                    previous: state.idx,
+                    idx: 0,
+                    node: edge.to,
+                    via: edge.via,
+                    entering: edge.enters,
+                    cost,
+                    priority: cost,
+                });
+            }
+        }
+        target_costs
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::costing::pedestrian::pedestrian_costing_model;
+
+    use super::super::{Graph, WayId};
+
+    #[test]
+    fn search_matrix_matches_djikstra_fremont() {
+        let costing_model = pedestrian_costing_model(1.4);
+        let graph = Graph::new();
+        graph
+            .ingest_tile(
+                2623,
+                5718,
+                14,
+                include_bytes!("../../testdata/tile2.pbf").to_vec(),
+                include_bytes!("../../testdata/tile2.pbf").to_vec(),
+            )
+            .expect("Failed to ingest tile");
+        let points = [
+            (WayId(671949014), 0),
+            (WayId(980366562), 0),
+            (WayId(671949014), 5_000),
+        ];
+
+        let sequential = graph
+            .search_matrix(&costing_model, &points, &points, 1)
+            .expect("Failed to search matrix");
+        let parallel = graph
+            .search_matrix(&costing_model, &points, &points, 2)
+            .expect("Failed to search matrix");
+        assert_eq!(sequential, parallel);
+        for (source_idx, source) in points.iter().enumerate() {
+            for (target_idx, target) in points.iter().enumerate() {
+                let djikstra =
+                    graph.search_djikstra(&costing_model, source.0, source.1, target.0, target.1);
+                assert_eq!(
+                    sequential.route_duration_seconds(source_idx, target_idx),
+                    djikstra
+                        .as_ref()
+                        .map(|result| result.route_duration_seconds())
+                );
+                assert_eq!(
+                    sequential.route_distance_meters(source_idx, target_idx),
+                    djikstra.map(|result| result.route_distance_meters())
+                );
+            }
+        }
+        assert!(sequential.cost(0, 1).is_some());
+    }
+}
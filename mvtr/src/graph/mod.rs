@@ -0,0 +1,1753 @@
+use std::{
+    collections::{BTreeMap, BinaryHeap, HashMap, HashSet},
+    f64::consts::PI,
+    mem::ManuallyDrop,
+    sync::{
+        Arc, Mutex,
+        atomic::{self, AtomicU64},
+    },
+};
+
+use evmap::ShallowCopy;
+use geo::{ClosestPoint, Distance, Haversine, InterpolateLine, Length, LineLocatePoint, Point};
+use mvt_reader::feature;
+use serde::{Deserialize, Serialize};
+
+mod alternatives;
+mod bidirectional;
+mod contraction;
+mod cost_cache;
+mod isochrone;
+mod maneuvers;
+mod map_matching;
+mod matrix;
+mod snapping;
+mod snapshot;
+mod spatial_index;
+mod stitching;
+#[cfg(test)]
+pub(crate) mod testing;
+mod tile_source;
+mod tiles;
+mod waypoints;
+
+use contraction::ContractionHierarchy;
+use cost_cache::{CostCache, CostCaches, Costing};
+pub use isochrone::{Isochrone, ReachedSegment};
+pub use maneuvers::{Maneuver, TurnType};
+pub use map_matching::{MatchedPoint, MatchedTrace, TracePoint};
+pub use matrix::CostMatrix;
+pub use snapping::{SideOfStreet, Snap, SnapCandidate, SnapOptions};
+pub use snapshot::{SNAPSHOT_VERSION, SnapshotHeader, SnapshotTile};
+use spatial_index::WayIndex;
+use stitching::{WayFragment, WayGeometry, WayPlacement};
+use tile_source::TileLoader;
+pub use tile_source::{AsyncTileSource, FileTileSource, TileBytes, TileSource};
+use tiles::{TileContents, TileId};
+pub use waypoints::Waypoint;
+
+use crate::costing::{
+    CostingModel, RoutingCost, Tags,
+    units::{Direction, ElapsedTime, PartsPerMillion, TravelSpeed, TravelledDistance},
+};
+
+/// Straight-line distance discounted from the A* heuristic, to absorb tile coordinate quantization
+/// and millisecond rounding so the estimate never exceeds the true remaining cost.
+const ASTAR_HEURISTIC_SLACK_METERS: f64 = 2.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct WayId(u64);
+
+impl WayId {
+    pub fn from_id(id: u64) -> WayId {
+        WayId(id)
+    }
+
+    pub fn id(&self) -> u64 {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct TileCoordinates {
+    x: u32,
+    y: u32,
+    z: u32,
+    extent: u32,
+    tile_x: i32,
+    tile_y: i32,
+}
+
+impl TileCoordinates {
+    fn northwest_corner(x: u32, y: u32, z: u32) -> geo::Point {
+        let n = 2f64.powi(z as i32);
+        let lon_deg = (x as f64) / n * 360.0 - 180.0;
+        let lat_rad = f64::atan(f64::sinh(PI * (1.0 - 2.0 * (y as f64) / n)));
+        let lat_deg = lat_rad.to_degrees();
+        geo::Point::new(lon_deg, lat_deg)
+    }
+
+    pub fn tile_envelope(&self) -> geo::Rect {
+        let northwest = Self::northwest_corner(self.x, self.y, self.z);
+        let southeast = Self::northwest_corner(self.x + 1, self.y + 1, self.z);
+        geo::Rect::new(northwest.0, southeast.0)
+    }
+
+    pub fn to_lat_lng(&self) -> geo::Coord {
+        let envelope = self.tile_envelope();
+        let x_frac = self.tile_x as f64 / self.extent as f64;
+        let y_frac = 1.0 - self.tile_y as f64 / self.extent as f64;
+        let x = envelope.min().x + x_frac * envelope.width();
+        let y = envelope.min().y + y_frac * envelope.height();
+        geo::Coord { x, y }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+pub(crate) struct CostedWayTransition {
+    to_way_id: WayId,
+    cost: RoutingCost,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub(crate) struct WayTransition {
+    from_way_id: WayId,
+    distance_along_way_mm: i32,
+    to_way_id: WayId,
+    transition_to_distance_along_way_mm: i32,
+}
+
+impl WayTransition {
+    pub(crate) fn new(
+        from_way_id: WayId,
+        distance_along_way_mm: i32,
+        to_way_id: WayId,
+        transition_to_distance_along_way_mm: i32,
+    ) -> WayTransition {
+        WayTransition {
+            from_way_id,
+            distance_along_way_mm,
+            to_way_id,
+            transition_to_distance_along_way_mm,
+        }
+    }
+
+    pub fn from_way_id(&self) -> WayId {
+        self.from_way_id
+    }
+    pub fn distance_along_way_mm(&self) -> i32 {
+        self.distance_along_way_mm
+    }
+    pub fn to_way_id(&self) -> WayId {
+        self.to_way_id
+    }
+    pub fn transition_to_distance_along_way_mm(&self) -> i32 {
+        self.transition_to_distance_along_way_mm
+    }
+}
+
+impl PartialOrd for WayTransition {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        match self
+            .distance_along_way_mm
+            .partial_cmp(&other.distance_along_way_mm)
+        {
+            Some(core::cmp::Ordering::Equal) => {}
+            ord => return ord,
+        }
+        match self.to_way_id.partial_cmp(&other.to_way_id) {
+            Some(core::cmp::Ordering::Equal) => {}
+            ord => return ord,
+        }
+        self.transition_to_distance_along_way_mm
+            .partial_cmp(&other.transition_to_distance_along_way_mm)
+    }
+}
+
+impl Ord for WayTransition {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.partial_cmp(other).unwrap()
+    }
+}
+
+impl ShallowCopy for WayTransition {
+    unsafe fn shallow_copy(&self) -> ManuallyDrop<Self> {
+        ManuallyDrop::new(*self)
+    }
+}
+
+fn meters_to_mm_fixed(meters: f32) -> i32 {
+    (meters * 1000.0) as i32
+}
+
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
+struct SearchNode {
+    way: WayId,
+    distance_along_way_mm: i32,
+}
+
+impl ShallowCopy for SearchNode {
+    unsafe fn shallow_copy(&self) -> ManuallyDrop<Self> {
+        ManuallyDrop::new(*self)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct SearchState {
+    previous: usize,
+    idx: usize,
+    node: SearchNode,
+    via: SearchNode,
+    /// Whether the state turned onto its way and hasn't travelled along it since, so travelling on
+    /// pays the way's entry penalty for the direction it sets off in. Always `false` on ways
+    /// without an entry penalty.
+    entering: bool,
+    cost: RoutingCost,
+    /// Cost so far plus the estimated cost to the destination. Equal to `cost` for Dijkstra.
+    priority: RoutingCost,
+}
+
+impl PartialOrd for SearchState {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        match self.priority.partial_cmp(&other.priority) {
+            Some(core::cmp::Ordering::Equal) => {}
+            ord => return ord.map(|ord| ord.reverse()),
+        }
+        (self.node, self.entering).partial_cmp(&(other.node, other.entering))
+    }
+}
+
+impl Ord for SearchState {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.partial_cmp(other).unwrap()
+    }
+}
+
+/// The open set of a search, ordered by cost so far plus an estimate of the cost remaining. An
+/// estimate of zero everywhere makes the search a plain Dijkstra.
+struct Frontier<EstimateFn: Fn(&SearchNode) -> ElapsedTime> {
+    heap: BinaryHeap<SearchState>,
+    estimate_remaining: EstimateFn,
+    estimates: HashMap<SearchNode, ElapsedTime>,
+    /// Extra cost equivalent charged for travel along particular ways, as a fraction of the
+    /// travel's own cost. Only the cost equivalent grows, so actual times and distances stay true.
+    way_penalties: HashMap<WayId, PartsPerMillion>,
+}
+
+impl<EstimateFn: Fn(&SearchNode) -> ElapsedTime> Frontier<EstimateFn> {
+    fn new(estimate_remaining: EstimateFn) -> Frontier<EstimateFn> {
+        Frontier {
+            heap: BinaryHeap::new(),
+            estimate_remaining,
+            estimates: HashMap::new(),
+            way_penalties: HashMap::new(),
+        }
+    }
+
+    fn with_way_penalties(
+        mut self,
+        way_penalties: HashMap<WayId, PartsPerMillion>,
+    ) -> Frontier<EstimateFn> {
+        self.way_penalties = way_penalties;
+        self
+    }
+
+    fn travel_cost(&self, way: &WayId, cost: RoutingCost) -> RoutingCost {
+        match self.way_penalties.get(way) {
+            Some(penalty) => cost.with_penalty(cost.elapsed_equivalent() * *penalty),
+            None => cost,
+        }
+    }
+
+    fn push(&mut self, mut state: SearchState) {
+        let estimate = *self
+            .estimates
+            .entry(state.node)
+            .or_insert_with(|| (self.estimate_remaining)(&state.node));
+        state.priority = state.cost.with_penalty(estimate);
+        self.heap.push(state);
+    }
+
+    fn pop(&mut self) -> Option<SearchState> {
+        self.heap.pop()
+    }
+}
+
+/// Where a search state on the destination way is, relative to the destination and to the next
+/// transition it would reach travelling towards it.
+#[derive(Debug, Clone, Copy)]
+struct FinishCandidate {
+    current_distance_along_way: i32,
+    end_distance_along_way: i32,
+    next_transition_distance_along_way: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub struct SearchResult {
+    encoded_polyline: String,
+    cost: RoutingCost,
+    maneuvers: Vec<Maneuver>,
+    legs: Vec<RouteLeg>,
+}
+
+impl SearchResult {
+    pub fn encoded_polyline(&self) -> String {
+        self.encoded_polyline.clone()
+    }
+
+    pub fn maneuvers(&self) -> Vec<Maneuver> {
+        self.maneuvers.clone()
+    }
+
+    /// The route between each pair of consecutive stops. A route without intermediate stops has
+    /// a single leg.
+    pub fn legs(&self) -> Vec<RouteLeg> {
+        self.legs.clone()
+    }
+
+    pub fn route_distance_meters(&self) -> f64 {
+        self.cost.distance().mm() as f64 / 1000.0
+    }
+
+    pub fn route_cost_seconds(&self) -> f64 {
+        self.cost.elapsed_equivalent().millis() as f64 / 1000.0
+    }
+
+    pub fn route_duration_seconds(&self) -> f64 {
+        self.cost.elapsed_actual().millis() as f64 / 1000.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub struct RouteLeg {
+    cost: RoutingCost,
+}
+
+impl RouteLeg {
+    pub fn distance_meters(&self) -> f64 {
+        self.cost.distance().mm() as f64 / 1000.0
+    }
+
+    pub fn cost_seconds(&self) -> f64 {
+        self.cost.elapsed_equivalent().millis() as f64 / 1000.0
+    }
+
+    pub fn duration_seconds(&self) -> f64 {
+        self.cost.elapsed_actual().millis() as f64 / 1000.0
+    }
+}
+
+/// A transition at a node, with the tags of the intersection it's at.
+type TaggedTransition = (WayTransition, Arc<Tags>);
+
+pub struct Graph {
+    nodes_read: evmap::ReadHandle<WayId, SearchNode>,
+    nodes_write: Arc<Mutex<evmap::WriteHandle<WayId, SearchNode>>>,
+    transitions_read: evmap::ReadHandle<SearchNode, TaggedTransition>,
+    transitions_write: Arc<Mutex<evmap::WriteHandle<SearchNode, TaggedTransition>>>,
+    /// The same transitions as `transitions_read`, keyed by the node they lead to.
+    reverse_transitions_read: evmap::ReadHandle<SearchNode, WayTransition>,
+    reverse_transitions_write: Arc<Mutex<evmap::WriteHandle<SearchNode, WayTransition>>>,
+    /// Every node a transition can lead to, keyed by the way it's on. Nodes are included
+    /// themselves, for the transition that continues along their way.
+    landings_read: evmap::ReadHandle<WayId, SearchNode>,
+    landings_write: Arc<Mutex<evmap::WriteHandle<WayId, SearchNode>>>,
+    way_tags_read: evmap::ReadHandle<WayId, Arc<Tags>>,
+    way_tags_write: Arc<Mutex<evmap::WriteHandle<WayId, Arc<Tags>>>>,
+    geometry_read: evmap::ReadHandle<WayId, WayGeometry>,
+    geometry_write: Arc<Mutex<evmap::WriteHandle<WayId, WayGeometry>>>,
+    street_names_read: evmap::ReadHandle<WayId, String>,
+    street_names_write: Arc<Mutex<evmap::WriteHandle<WayId, String>>>,
+    /// The costs worked out so far by the named costing models searches have used most recently.
+    costs: Arc<Mutex<CostCaches>>,
+    /// Incremented whenever the graph changes, so derived data built from an older graph can be
+    /// recognized as stale.
+    generation: Arc<AtomicU64>,
+    /// The prepared contraction hierarchy of each costing model, by the model's name.
+    contraction_hierarchies: Arc<Mutex<HashMap<String, Arc<ContractionHierarchy>>>>,
+    way_index: Arc<Mutex<WayIndex>>,
+    /// What each ingested tile added to the graph.
+    tiles: Arc<Mutex<HashMap<TileId, TileContents>>>,
+    tile_loader: Arc<Mutex<Option<TileLoader>>>,
+}
+
+impl Graph {
+    pub fn new() -> Graph {
+        let (nr, nw) = evmap::new();
+        let (tr, tw) = evmap::new();
+        let (rtr, rtw) = evmap::new();
+        let (lr, lw) = evmap::new();
+        let (wr, ww) = evmap::new();
+        let (gr, gw) = evmap::new();
+        let (sr, sw) = evmap::new();
+        Graph {
+            nodes_read: nr,
+            nodes_write: Arc::new(Mutex::new(nw)),
+            transitions_read: tr,
+            transitions_write: Arc::new(Mutex::new(tw)),
+            reverse_transitions_read: rtr,
+            reverse_transitions_write: Arc::new(Mutex::new(rtw)),
+            landings_read: lr,
+            landings_write: Arc::new(Mutex::new(lw)),
+            way_tags_read: wr,
+            way_tags_write: Arc::new(Mutex::new(ww)),
+            geometry_read: gr,
+            geometry_write: Arc::new(Mutex::new(gw)),
+            street_names_read: sr,
+            street_names_write: Arc::new(Mutex::new(sw)),
+            costs: Arc::new(Mutex::new(CostCaches::default())),
+            generation: Arc::new(AtomicU64::new(0)),
+            contraction_hierarchies: Arc::new(Mutex::new(HashMap::new())),
+            way_index: Arc::new(Mutex::new(WayIndex::default())),
+            tiles: Arc::new(Mutex::new(HashMap::new())),
+            tile_loader: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Another handle onto the same graph. Read handles can't be shared between threads, so each
+    /// thread that searches the graph needs its own.
+    fn handle(&self) -> Graph {
+        Graph {
+            nodes_read: self.nodes_read.clone(),
+            nodes_write: self.nodes_write.clone(),
+            transitions_read: self.transitions_read.clone(),
+            transitions_write: self.transitions_write.clone(),
+            reverse_transitions_read: self.reverse_transitions_read.clone(),
+            reverse_transitions_write: self.reverse_transitions_write.clone(),
+            landings_read: self.landings_read.clone(),
+            landings_write: self.landings_write.clone(),
+            way_tags_read: self.way_tags_read.clone(),
+            way_tags_write: self.way_tags_write.clone(),
+            geometry_read: self.geometry_read.clone(),
+            geometry_write: self.geometry_write.clone(),
+            street_names_read: self.street_names_read.clone(),
+            street_names_write: self.street_names_write.clone(),
+            costs: self.costs.clone(),
+            generation: self.generation.clone(),
+            contraction_hierarchies: self.contraction_hierarchies.clone(),
+            way_index: self.way_index.clone(),
+            tiles: self.tiles.clone(),
+            tile_loader: self.tile_loader.clone(),
+        }
+    }
+
+    pub fn clear(&self) -> Result<(), Box<dyn std::error::Error>> {
+        self.way_tags_write
+            .lock()
+            .map_err(|err| anyhow::anyhow!("Failed to lock mutex: {}", err))?
+            .purge();
+        self.geometry_write
+            .lock()
+            .map_err(|err| anyhow::anyhow!("Failed to lock mutex: {}", err))?
+            .purge();
+        self.street_names_write
+            .lock()
+            .map_err(|err| anyhow::anyhow!("Failed to lock mutex: {}", err))?
+            .purge();
+        self.transitions_write
+            .lock()
+            .map_err(|err| anyhow::anyhow!("Failed to lock mutex: {}", err))?
+            .purge();
+        self.reverse_transitions_write
+            .lock()
+            .map_err(|err| anyhow::anyhow!("Failed to lock mutex: {}", err))?
+            .purge();
+        self.landings_write
+            .lock()
+            .map_err(|err| anyhow::anyhow!("Failed to lock mutex: {}", err))?
+            .purge();
+        self.nodes_write
+            .lock()
+            .map_err(|err| anyhow::anyhow!("Failed to lock mutex: {}", err))?
+            .purge();
+        self.way_index
+            .lock()
+            .map_err(|err| anyhow::anyhow!("Failed to lock mutex: {}", err))?
+            .clear();
+        self.tiles
+            .lock()
+            .map_err(|err| anyhow::anyhow!("Failed to lock mutex: {}", err))?
+            .clear();
+        self.way_tags_write
+            .lock()
+            .map_err(|err| anyhow::anyhow!("Failed to lock mutex: {}", err))?
+            .refresh();
+        self.geometry_write
+            .lock()
+            .map_err(|err| anyhow::anyhow!("Failed to lock mutex: {}", err))?
+            .refresh();
+        self.street_names_write
+            .lock()
+            .map_err(|err| anyhow::anyhow!("Failed to lock mutex: {}", err))?
+            .refresh();
+        self.transitions_write
+            .lock()
+            .map_err(|err| anyhow::anyhow!("Failed to lock mutex: {}", err))?
+            .refresh();
+        self.reverse_transitions_write
+            .lock()
+            .map_err(|err| anyhow::anyhow!("Failed to lock mutex: {}", err))?
+            .refresh();
+        self.landings_write
+            .lock()
+            .map_err(|err| anyhow::anyhow!("Failed to lock mutex: {}", err))?
+            .refresh();
+        self.nodes_write
+            .lock()
+            .map_err(|err| anyhow::anyhow!("Failed to lock mutex: {}", err))?
+            .refresh();
+        self.invalidate()?;
+        Ok(())
+    }
+
+    /// Discards everything derived from the graph's current contents.
+    fn invalidate(&self) -> anyhow::Result<()> {
+        self.generation.fetch_add(1, atomic::Ordering::SeqCst);
+        self.contraction_hierarchies
+            .lock()
+            .map_err(|err| anyhow::anyhow!("Failed to lock mutex: {}", err))?
+            .clear();
+        self.costs
+            .lock()
+            .map_err(|err| anyhow::anyhow!("Failed to lock mutex: {}", err))?
+            .clear();
+        Ok(())
+    }
+
+    /// Adds a tile's ways and intersections to the graph, along with their tags for costing
+    /// models to cost them by when searched.
+    pub fn ingest_tile(
+        &self,
+        x: u32,
+        y: u32,
+        z: u32,
+        mvt_ways: Vec<u8>,
+        mvt_nodes: Vec<u8>,
+    ) -> anyhow::Result<()> {
+        let reader_ways = mvt_reader::Reader::new(mvt_ways)
+            .map_err(|err| anyhow::anyhow!("Could not create MVT reader {}", err))?;
+        let layers_ways = reader_ways
+            .get_layer_names()
+            .map_err(|err| anyhow::anyhow!("Could not get MVT tile's layer list {}", err))?;
+
+        let mut fragments: Vec<(WayId, Vec<TileCoordinates>)> = Vec::new();
+        let mut contents = TileContents::default();
+        if let Some((road_layer_id, _)) = layers_ways
+            .iter()
+            .enumerate()
+            .filter(|(_, layer)| layer.as_str() == "roads")
+            .next()
+        {
+            let features = reader_ways
+                .get_features(road_layer_id)
+                .map_err(|err| anyhow::anyhow!("Could not get MVT tile's road features {}", err))?;
+
+            let extent = reader_ways
+                .get_layer_metadata()
+                .map_err(|err| anyhow::anyhow!("Could not get MVT tile's road metadata {}", err))?
+                [road_layer_id]
+                .extent;
+
+            for feature in &features {
+                let _props_default = HashMap::new();
+                let properties = feature.properties.as_ref().unwrap_or(&_props_default);
+
+                let way_id = WayId(Self::get_u64_property(properties, "way_id")?);
+                let mut tags = HashMap::new();
+                for (key, value) in feature.properties.as_ref().unwrap_or(&HashMap::new()) {
+                    match value {
+                        mvt_reader::feature::Value::String(value) => {
+                            tags.insert(key.clone(), value.clone())
+                        }
+                        _ => continue,
+                    };
+                }
+                let tags = Tags::from_hashmap(tags);
+                if let Some(street_name) = maneuvers::street_name(&tags) {
+                    contents.insert_street_name(way_id, street_name);
+                }
+                contents.insert_way(way_id, Arc::new(tags));
+
+                // A way that leaves the tile's buffer and comes back is clipped into several parts,
+                // each stitched into place like a fragment from another tile.
+                let parts = match &feature.geometry {
+                    geo::Geometry::LineString(line_string) => vec![line_string],
+                    geo::Geometry::MultiLineString(multi_line_string) => {
+                        multi_line_string.0.iter().collect()
+                    }
+                    _ => {
+                        tracing::warn!("Way geometry was not linestring or multilinestring");
+                        continue;
+                    }
+                };
+                if parts.is_empty() {
+                    tracing::warn!("Zero linestrings found");
+                }
+                for part in parts {
+                    let mut polyline = Vec::new();
+                    for coord in &part.0 {
+                        polyline.push(TileCoordinates {
+                            x,
+                            y,
+                            z,
+                            extent,
+                            tile_x: coord.x as i32,
+                            tile_y: coord.y as i32,
+                        });
+                    }
+                    fragments.push((way_id, polyline));
+                }
+            }
+        }
+        let reader_nodes = mvt_reader::Reader::new(mvt_nodes)
+            .map_err(|err| anyhow::anyhow!("Could not create MVT reader {}", err))?;
+        let layers_nodes = reader_nodes
+            .get_layer_names()
+            .map_err(|err| anyhow::anyhow!("Could not get MVT tile's layer list {}", err))?;
+
+        // Where each way's intersections in this tile are, and how far along the way, to place the
+        // tile's fragment of the way along it.
+        let mut anchors: HashMap<WayId, Vec<(geo::Coord, f64)>> = HashMap::new();
+
+        if let Some((intersection_layer_id, _)) = layers_nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, layer)| layer.as_str() == "intersections")
+            .next()
+        {
+            let features = reader_nodes
+                .get_features(intersection_layer_id)
+                .map_err(|err| {
+                    anyhow::anyhow!("Could not get MVT tile's intersection features {}", err)
+                })?;
+
+            let extent = reader_nodes.get_layer_metadata().map_err(|err| {
+                anyhow::anyhow!("Could not get MVT tile's intersection metadata {}", err)
+            })?[intersection_layer_id]
+                .extent;
+
+            for feature in &features {
+                let _props_default = HashMap::new();
+                let properties = feature.properties.as_ref().unwrap_or(&_props_default);
+
+                let mut intersection_tags = HashMap::new();
+                for (key, value) in feature.properties.as_ref().unwrap_or(&HashMap::new()) {
+                    match value {
+                        mvt_reader::feature::Value::String(value) => {
+                            intersection_tags.insert(key.clone(), value.clone())
+                        }
+                        _ => continue,
+                    };
+                }
+                let intersection_tags = Arc::new(Tags::from_hashmap(intersection_tags));
+
+                let from_way_id = WayId(Self::get_u64_property(properties, "way_id")?);
+                let to_way_id = WayId(Self::get_u64_property(properties, "transition_to_way")?);
+                let distance_along_way = Self::get_f32_property(properties, "distance_along_way")?;
+                let transition_to_distance_along_way =
+                    Self::get_f32_property(properties, "transition_to_distance_along_way")?;
+
+                let point = match &feature.geometry {
+                    geo::Geometry::Point(point) => Some(point.0),
+                    geo::Geometry::MultiPoint(multi_point) => {
+                        multi_point.0.first().map(|point| point.0)
+                    }
+                    _ => None,
+                };
+                if let Some(point) = point {
+                    let coord = TileCoordinates {
+                        x,
+                        y,
+                        z,
+                        extent,
+                        tile_x: point.x as i32,
+                        tile_y: point.y as i32,
+                    }
+                    .to_lat_lng();
+                    anchors
+                        .entry(from_way_id)
+                        .or_default()
+                        .push((coord, distance_along_way as f64));
+                }
+
+                let distance_along_way_mm = meters_to_mm_fixed(distance_along_way);
+                let search_node = SearchNode {
+                    way: from_way_id,
+                    distance_along_way_mm,
+                };
+                let way_transition = WayTransition {
+                    from_way_id,
+                    distance_along_way_mm,
+                    transition_to_distance_along_way_mm: meters_to_mm_fixed(
+                        transition_to_distance_along_way,
+                    ),
+                    to_way_id: to_way_id,
+                };
+                contents.insert_node(search_node);
+                contents.insert_transition(way_transition, intersection_tags);
+            }
+        }
+        for (way_id, polyline) in fragments {
+            let anchors = anchors.get(&way_id).map_or(&[][..], Vec::as_slice);
+            contents.insert_geometry(way_id, WayFragment::new(polyline, anchors));
+        }
+        // Replaces whatever the tile added before, so ingesting a tile again doesn't duplicate it.
+        self.replace_tiles(vec![(TileId { x, y, z }, Some(contents))])
+    }
+
+    /// The geometry of a way, stitched together from every loaded tile it passes through.
+    pub fn get_polyline(&self, way: &WayId) -> Option<geo::LineString> {
+        Some(self.geometry_read.get_one(way)?.line_string())
+    }
+
+    /// Where a way's loaded geometry lies along the way, and its polyline.
+    fn way_geometry(&self, way: &WayId) -> Option<(WayPlacement, geo::LineString)> {
+        let geometry = self.geometry_read.get_one(way)?;
+        Some((geometry.placement.clone(), geometry.line_string()))
+    }
+
+    /// The distances along a way its loaded geometry covers.
+    fn way_span_mm(&self, way: &WayId) -> Option<(i32, i32)> {
+        let (placement, polyline) = self.way_geometry(way)?;
+        Some((
+            placement.start_mm(),
+            placement.way_mm(Haversine.length(&polyline)),
+        ))
+    }
+
+    fn point_along_way(&self, way: &WayId, distance_along_way_mm: i32) -> Option<Point> {
+        let (placement, polyline) = self.way_geometry(way)?;
+        Haversine.point_at_distance_from_start(
+            &polyline,
+            placement.polyline_meters(distance_along_way_mm),
+        )
+    }
+
+    pub fn nearest_way(
+        &self,
+        costing_model: &dyn CostingModel,
+        coord: &geo::Coord,
+    ) -> Option<(WayId, i32)> {
+        let (way, distance_along_way_mm, _) =
+            self.nearest_ways(costing_model, coord, 1).ok()?.pop()?;
+        Some((way, distance_along_way_mm))
+    }
+
+    /// The distance in meters from a point to the closest point on a way, and how far along the
+    /// way that is. `None` if the way can't be travelled in either direction.
+    fn project_onto_way(
+        &self,
+        costing: &Costing,
+        way: &WayId,
+        point: &Point,
+    ) -> Option<(f64, i32)> {
+        let coster = self.way_coster(costing, way)?;
+        let allowed_forward = coster
+            .cost_way_segment(TravelledDistance(1), Direction::Forward)
+            .is_some();
+        let allowed_reverse = coster
+            .cost_way_segment(TravelledDistance(1), Direction::Reverse)
+            .is_some();
+        if !allowed_forward && !allowed_reverse {
+            return None;
+        }
+        let (placement, polyline) = self.way_geometry(way)?;
+        let closest_point = match polyline.closest_point(point) {
+            geo::Closest::Intersection(point) => point,
+            geo::Closest::SinglePoint(point) => point,
+            geo::Closest::Indeterminate => panic!(),
+        };
+        let closest_distance = Haversine.distance(*point, closest_point);
+        if closest_distance.is_nan() {
+            tracing::warn!("{:?}, {:?}", point, closest_point);
+        }
+        let fraction = polyline.line_locate_point(&closest_point).unwrap();
+        let distance_meters = fraction * Haversine.length(&polyline);
+        Some((closest_distance, placement.way_mm(distance_meters)))
+    }
+
+    pub fn search_djikstra(
+        &self,
+        costing_model: &dyn CostingModel,
+        start: WayId,
+        distance_along_start_mm: i32,
+        end: WayId,
+        distance_along_end_mm: i32,
+    ) -> Option<SearchResult> {
+        let costing = self.costing(costing_model).ok()?;
+        let states = self.search_djikstra_inner(
+            &costing,
+            start,
+            distance_along_start_mm,
+            end,
+            distance_along_end_mm,
+            Frontier::new(|_| ElapsedTime::zero()),
+        )?;
+        self.build_search_result(&states)
+    }
+
+    /// Like `search_djikstra`, but directed towards the destination using the great-circle distance
+    /// to it at the max speed declared by the costing model. Falls back to Dijkstra if the model
+    /// doesn't declare one.
+    pub fn search_astar(
+        &self,
+        costing_model: &dyn CostingModel,
+        start: WayId,
+        distance_along_start_mm: i32,
+        end: WayId,
+        distance_along_end_mm: i32,
+    ) -> Option<SearchResult> {
+        let costing = self.costing(costing_model).ok()?;
+        let destination = self.point_along_way(&end, distance_along_end_mm)?;
+        let states = if let Some(max_speed) = costing.max_speed() {
+            self.search_djikstra_inner(
+                &costing,
+                start,
+                distance_along_start_mm,
+                end,
+                distance_along_end_mm,
+                Frontier::new(|node| self.estimate_remaining_time(node, &destination, max_speed)),
+            )?
+        } else {
+            tracing::warn!("No max speed available, A* search will not be goal-directed");
+            self.search_djikstra_inner(
+                &costing,
+                start,
+                distance_along_start_mm,
+                end,
+                distance_along_end_mm,
+                Frontier::new(|_| ElapsedTime::zero()),
+            )?
+        };
+        self.build_search_result(&states)
+    }
+
+    fn estimate_remaining_time(
+        &self,
+        node: &SearchNode,
+        destination: &Point,
+        max_speed: TravelSpeed,
+    ) -> ElapsedTime {
+        let position =
+            if let Some(position) = self.point_along_way(&node.way, node.distance_along_way_mm) {
+                position
+            } else {
+                return ElapsedTime::zero();
+            };
+        let distance_meters =
+            (Haversine.distance(position, *destination) - ASTAR_HEURISTIC_SLACK_METERS).max(0.0);
+        (TravelledDistance((distance_meters * 1000.0) as u64) / max_speed)
+            .unwrap_or(ElapsedTime::zero())
+    }
+
+    fn build_search_result(&self, states: &[SearchState]) -> Option<SearchResult> {
+        let cost = states.last()?.cost;
+
+        dbg!(cost);
+
+        let route_polyline = self.route_points(states)?;
+
+        Some(SearchResult {
+            cost,
+            maneuvers: self.build_maneuvers(states),
+            legs: vec![RouteLeg { cost }],
+            encoded_polyline: polyline::encode_coordinates(
+                route_polyline.iter().map(|point| point.0),
+                5,
+            )
+            .unwrap(),
+        })
+    }
+
+    /// The points a route passes through, with consecutive duplicates where it moves between ways
+    /// dropped.
+    fn route_points(&self, states: &[SearchState]) -> Option<Vec<Point>> {
+        let mut route_polyline = Vec::new();
+
+        for window in states.windows(2) {
+            let section = self.way_section(
+                &window[0].node.way,
+                window[0].node.distance_along_way_mm,
+                window[1].via.distance_along_way_mm,
+            )?;
+            let (start_point, end_point) = (section[0], section[section.len() - 1]);
+
+            if route_polyline.last() != Some(&start_point) {
+                route_polyline.push(start_point);
+            }
+
+            route_polyline.extend_from_slice(&section[1..section.len() - 1]);
+
+            if route_polyline.last() != Some(&end_point) {
+                route_polyline.push(end_point);
+            }
+        }
+        Some(route_polyline)
+    }
+
+    /// The points along a way between two distances along it, in the direction of travel, starting
+    /// and ending with points interpolated at those distances.
+    fn way_section(&self, way: &WayId, from_mm: i32, to_mm: i32) -> Option<Vec<Point>> {
+        let (placement, node_linestring) = self.way_geometry(way)?;
+
+        let start_point = Haversine
+            .point_at_distance_from_start(&node_linestring, placement.polyline_meters(from_mm))
+            .expect("Failed to interpolate along way polyline.");
+        let end_point = Haversine
+            .point_at_distance_from_start(&node_linestring, placement.polyline_meters(to_mm))
+            .expect("Failed to interpolate along way polyline.");
+
+        let line_fraction_1 = node_linestring.line_locate_point(&start_point).unwrap();
+        let line_fraction_2 = node_linestring.line_locate_point(&end_point).unwrap();
+        let start_line_fraction = line_fraction_1.min(line_fraction_2);
+        let end_line_fraction = line_fraction_1.max(line_fraction_2);
+
+        let mut middle_points: Vec<Point> = node_linestring
+            .coords()
+            .map(|coord| Point(*coord))
+            .skip_while(|point| {
+                node_linestring.line_locate_point(point).unwrap() < start_line_fraction
+            })
+            .take_while(|point| {
+                node_linestring.line_locate_point(point).unwrap() < end_line_fraction
+            })
+            .collect();
+        if line_fraction_1 > line_fraction_2 {
+            middle_points.reverse();
+        }
+
+        let mut section = vec![start_point];
+        section.extend(middle_points);
+        section.push(end_point);
+        Some(section)
+    }
+
+    fn search_djikstra_inner<EstimateFn: Fn(&SearchNode) -> ElapsedTime>(
+        &self,
+        costing: &Costing,
+        start: WayId,
+        distance_along_start_mm: i32,
+        end: WayId,
+        distance_along_end_mm: i32,
+        frontier: Frontier<EstimateFn>,
+    ) -> Option<Vec<SearchState>> {
+        self.search_djikstra_departing(
+            costing,
+            SearchNode {
+                way: start,
+                distance_along_way_mm: distance_along_start_mm,
+            },
+            None,
+            SearchNode {
+                way: end,
+                distance_along_way_mm: distance_along_end_mm,
+            },
+            frontier,
+        )
+    }
+
+    /// Like `search_djikstra_inner`, but only travels along the start way in `departure`, if
+    /// given, before transitioning off it.
+    fn search_djikstra_departing<EstimateFn: Fn(&SearchNode) -> ElapsedTime>(
+        &self,
+        costing: &Costing,
+        first_node: SearchNode,
+        departure: Option<Direction>,
+        end_node: SearchNode,
+        mut frontier: Frontier<EstimateFn>,
+    ) -> Option<Vec<SearchState>> {
+        let SearchNode {
+            way: end,
+            distance_along_way_mm: distance_along_end_mm,
+        } = end_node;
+        let first_state = SearchState {
+            previous: 0,
+            idx: 0,
+            node: first_node,
+            via: first_node,
+            entering: false,
+            cost: RoutingCost::zero(),
+            priority: RoutingCost::zero(),
+        };
+        frontier.push(first_state);
+        let mut costs: HashMap<(SearchNode, bool), RoutingCost> = HashMap::new();
+        let mut step_log: Vec<SearchState> = vec![first_state];
+
+        while let Some(state) = frontier.pop() {
+            if state.node.way == end && state.node.distance_along_way_mm == distance_along_end_mm {
+                return Some(self.unwind_route(&step_log, state.idx));
+            }
+            self.load_tiles_along(&state.node.way);
+            let all_nodes: HashSet<SearchNode> = self
+                .nodes_read
+                .get(&state.node.way)
+                .iter()
+                .flatten()
+                .cloned()
+                .collect();
+
+            debug_assert!(all_nodes.iter().all(|node| node.way == state.node.way));
+
+            let mut transition_groups = BTreeMap::new();
+            for node in &all_nodes {
+                let transitions = self.costed_transitions(costing, node);
+                debug_assert!(!transition_groups.contains_key(&node));
+                transition_groups.insert(node, transitions);
+            }
+
+            debug_assert!(
+                transition_groups
+                    .keys()
+                    .all(|key| key.way == state.node.way)
+            );
+
+            let identity_transitions_group = transition_groups
+                .iter()
+                .filter(|(node, _)| node == &&&state.node)
+                .map(|(k, v)| (**k, v.clone()))
+                .next();
+            let first_transition_group_after = transition_groups
+                .iter()
+                .filter(|(node, _)| node > &&&state.node)
+                .map(|(k, v)| (**k, v.clone()))
+                .next();
+            let first_transition_group_before = transition_groups
+                .iter()
+                .filter(|(node, _)| node < &&&state.node)
+                .map(|(k, v)| (**k, v.clone()))
+                .last();
+
+            if let Some((via, group)) = identity_transitions_group {
+                self.process_transition_set(
+                    costing,
+                    &group,
+                    &via,
+                    &state,
+                    &mut frontier,
+                    &mut costs,
+                    &mut step_log,
+                );
+            }
+            // The first state is the start, which may only be left in the departure direction.
+            let departure = if state.idx == 0 { departure } else { None };
+            let first_transition_group_after =
+                first_transition_group_after.filter(|_| departure != Some(Direction::Reverse));
+            let first_transition_group_before =
+                first_transition_group_before.filter(|_| departure != Some(Direction::Forward));
+
+            if let Some((via, group)) = first_transition_group_after {
+                if state.node.way == end {
+                    self.check_finish_case(
+                        FinishCandidate {
+                            current_distance_along_way: state.node.distance_along_way_mm,
+                            end_distance_along_way: distance_along_end_mm,
+                            next_transition_distance_along_way: group
+                                .first()
+                                .unwrap()
+                                .1
+                                .distance_along_way_mm,
+                        },
+                        &state,
+                        &mut step_log,
+                        costing,
+                        &mut frontier,
+                    );
+                }
+                self.process_transition_set(
+                    costing,
+                    &group,
+                    &via,
+                    &state,
+                    &mut frontier,
+                    &mut costs,
+                    &mut step_log,
+                );
+            }
+            if let Some((via, group)) = first_transition_group_before {
+                if state.node.way == end {
+                    self.check_finish_case(
+                        FinishCandidate {
+                            current_distance_along_way: state.node.distance_along_way_mm,
+                            end_distance_along_way: distance_along_end_mm,
+                            next_transition_distance_along_way: group
+                                .first()
+                                .unwrap()
+                                .1
+                                .distance_along_way_mm,
+                        },
+                        &state,
+                        &mut step_log,
+                        costing,
+                        &mut frontier,
+                    );
+                }
+                self.process_transition_set(
+                    costing,
+                    &group,
+                    &via,
+                    &state,
+                    &mut frontier,
+                    &mut costs,
+                    &mut step_log,
+                );
+            }
+        }
+        None
+    }
+
+    /// Adds a state at the destination if it lies between `previous`, on the destination way, and
+    /// the next transition along it.
+    fn check_finish_case<EstimateFn: Fn(&SearchNode) -> ElapsedTime>(
+        &self,
+        FinishCandidate {
+            current_distance_along_way,
+            end_distance_along_way,
+            next_transition_distance_along_way,
+        }: FinishCandidate,
+        previous: &SearchState,
+        step_log: &mut Vec<SearchState>,
+        costing: &Costing,
+        frontier: &mut Frontier<EstimateFn>,
+    ) -> Option<()> {
+        if (current_distance_along_way < end_distance_along_way
+            && end_distance_along_way < next_transition_distance_along_way)
+            || (current_distance_along_way > end_distance_along_way
+                && end_distance_along_way > next_transition_distance_along_way)
+        {
+            let end_node = SearchNode {
+                way: previous.node.way,
+                distance_along_way_mm: end_distance_along_way,
+            };
+            let new_state = SearchState {
+                previous: previous.idx,
+                idx: step_log.len(),
+                node: end_node,
+                via: end_node,
+                entering: false,
+                cost: previous.cost
+                    + frontier.travel_cost(
+                        &previous.node.way,
+                        self.cost_along_way(
+                            costing,
+                            &previous.node.way,
+                            current_distance_along_way,
+                            end_distance_along_way,
+                            previous.entering,
+                        )?,
+                    ),
+                priority: RoutingCost::zero(),
+            };
+            frontier.push(new_state);
+            step_log.push(new_state);
+        }
+        Some(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn process_transition_set<EstimateFn: Fn(&SearchNode) -> ElapsedTime>(
+        &self,
+        costing: &Costing,
+        costed_transitions: &[(CostedWayTransition, WayTransition)],
+        via: &SearchNode,
+        state: &SearchState,
+        frontier: &mut Frontier<EstimateFn>,
+        costs: &mut HashMap<(SearchNode, bool), RoutingCost>,
+        step_log: &mut Vec<SearchState>,
+    ) {
+        debug_assert_eq!(state.node.way, via.way);
+        let segment_cost = if let Some(segment_cost) = self.cost_along_way(
+            costing,
+            &state.node.way,
+            state.node.distance_along_way_mm,
+            via.distance_along_way_mm,
+            state.entering,
+        ) {
+            segment_cost
+        } else {
+            // Impassable way segment.
+            return;
+        };
+
+        // Apply the travel cost.
+        let new_cost = state.cost + frontier.travel_cost(&state.node.way, segment_cost);
+
+        for (costed, transition) in costed_transitions {
+            let new_node = SearchNode {
+                way: transition.to_way_id,
+                distance_along_way_mm: transition.transition_to_distance_along_way_mm,
+            };
+            // Landing back where the step started only adds cost, and would let a route that just
+            // turned onto the way get out of its entry penalty.
+            if new_node == state.node {
+                continue;
+            }
+
+            // Apply the transition cost.
+            let new_state = SearchState {
+                previous: state.idx,
+                idx: step_log.len(),
+                node: new_node,
+                via: *via,
+                entering: self.enters(costing, &via.way, &transition.to_way_id),
+                cost: new_cost + costed.cost,
+                priority: RoutingCost::zero(),
+            };
+            let label = (new_node, new_state.entering);
+
+            if let Some(best_cost_this_node) = costs.get_mut(&label) {
+                if new_state.cost < *best_cost_this_node {
+                    frontier.push(new_state);
+                    *best_cost_this_node = new_state.cost;
+                    step_log.push(new_state);
+                }
+            } else {
+                frontier.push(new_state);
+                costs.insert(label, new_state.cost);
+                step_log.push(new_state);
+            }
+        }
+    }
+
+    /// The cost of travelling along `way` between two points on it, or `None` if that direction is
+    /// impassable. If `entering`, travel starts where a route turned onto the way, so the way's
+    /// entry penalty is charged too.
+    fn cost_along_way(
+        &self,
+        costing: &Costing,
+        way: &WayId,
+        from_distance_along_way_mm: i32,
+        to_distance_along_way_mm: i32,
+        entering: bool,
+    ) -> Option<RoutingCost> {
+        let distance: TravelledDistance = TravelledDistance(
+            (from_distance_along_way_mm - to_distance_along_way_mm)
+                .saturating_abs()
+                .try_into()
+                .expect("Distance was negative after an `abs` call."),
+        );
+        let direction = if from_distance_along_way_mm < to_distance_along_way_mm {
+            Direction::Forward
+        } else {
+            Direction::Reverse
+        };
+        let way_coster = self
+            .way_coster(costing, way)
+            .expect("Costing for way not available.");
+        let cost = way_coster.cost_way_segment(distance, direction)?;
+        if entering && distance > TravelledDistance::zero() {
+            Some(cost + way_coster.cost_entry(direction))
+        } else {
+            Some(cost)
+        }
+    }
+
+    fn unwind_route(&self, step_log: &[SearchState], end_step: usize) -> Vec<SearchState> {
+        let mut cycle_detector = HashSet::new();
+        let mut steps_reversed = Vec::new();
+        let mut cursor = end_step;
+        while let Some(step) = step_log.get(cursor) {
+            if cycle_detector.contains(step) {
+                tracing::error!("Cycle detected while unwinding the route");
+                return Vec::new();
+            }
+            cycle_detector.insert(step);
+            steps_reversed.push(*step);
+
+            if step.previous == step.idx {
+                break;
+            }
+            cursor = step.previous
+        }
+        let steps: Vec<SearchState> = steps_reversed.into_iter().rev().collect();
+        for window in steps.windows(2) {
+            assert_eq!(
+                (window[0].node.distance_along_way_mm - window[1].via.distance_along_way_mm).abs()
+                    as u64,
+                window[1].cost.distance().0 - window[0].cost.distance().0
+            );
+        }
+        steps
+    }
+
+    fn get_u64_property(
+        properties: &HashMap<String, feature::Value>,
+        prop_name: &str,
+    ) -> anyhow::Result<u64> {
+        let prop = properties
+            .get(prop_name)
+            .ok_or_else(|| anyhow::anyhow!("Intersection missing {prop_name}"))?;
+        if let feature::Value::UInt(prop) = prop {
+            Ok(*prop)
+        } else {
+            anyhow::bail!("Property not a u64")
+        }
+    }
+
+    fn get_f32_property(
+        properties: &HashMap<String, feature::Value>,
+        prop_name: &str,
+    ) -> anyhow::Result<f32> {
+        let prop = properties
+            .get(prop_name)
+            .ok_or_else(|| anyhow::anyhow!("Intersection missing {prop_name}"))?;
+        if let feature::Value::Float(prop) = prop {
+            Ok(*prop)
+        } else {
+            anyhow::bail!("Property not a f32")
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Instant;
+
+    use geo::Coord;
+
+    use crate::costing::{
+        RoutingCost, Tags, TransitionCostResult, TransitionToCost,
+        base::{BaseCostingModel, WayCost},
+        pedestrian::pedestrian_costing_model,
+        units::{Direction, ElapsedTime, TravelSpeed},
+    };
+
+    use super::{Frontier, Graph, SearchNode, WayId, WayTransition};
+
+    #[test]
+    fn ingest_tile() {
+        let graph = Graph::new();
+        let start = Instant::now();
+        graph
+            .ingest_tile(
+                2625,
+                5721,
+                14,
+                include_bytes!("../../testdata/tile.pbf").to_vec(),
+                include_bytes!("../../testdata/tile.pbf").to_vec(),
+            )
+            .expect("Failed to ingest tile");
+        dbg!(start.elapsed());
+    }
+
+    #[test]
+    fn search_basic() {
+        let costing_model = pedestrian_costing_model(1.4);
+        let graph = Graph::new();
+        graph
+            .ingest_tile(
+                2625,
+                5721,
+                14,
+                include_bytes!("../../testdata/tile.pbf").to_vec(),
+                include_bytes!("../../testdata/tile.pbf").to_vec(),
+            )
+            .expect("Failed to ingest tile");
+        // approx: https://maps.earth/directions/walk/-122.315503,47.6163794/-122.3126740,47.6153470
+        // ----> 325.32080857991474 meters
+        let (from_way_id, from_way_distance) = graph
+            .nearest_way(
+                &costing_model,
+                &Coord {
+                    x: -122.3126740,
+                    y: 47.6153470,
+                },
+            )
+            .unwrap();
+        let (to_way_id, to_way_distance) = graph
+            .nearest_way(
+                &costing_model,
+                &Coord {
+                    x: -122.315503,
+                    y: 47.6163794,
+                },
+            )
+            .unwrap();
+
+        dbg!(from_way_id, from_way_distance, to_way_id, to_way_distance);
+
+        let route = graph
+            .search_djikstra(
+                &costing_model,
+                from_way_id,
+                from_way_distance,
+                to_way_id,
+                to_way_distance,
+            )
+            .expect("Couldn't find a route.");
+        assert_eq!(route.cost.distance().mm(), 325_918);
+        assert_eq!(
+            route.encoded_polyline,
+            "}zraHdepiV?@?@?????BCN??CPAB??A?o@?IAgC???A@?????zF?????????F??@N???L???F????A???@vE???@???B??"
+        );
+    }
+
+    #[test]
+    fn search_fremont() {
+        let costing_model = pedestrian_costing_model(1.4);
+        let graph = Graph::new();
+        graph
+            .ingest_tile(
+                2623,
+                5718,
+                14,
+                include_bytes!("../../testdata/tile2.pbf").to_vec(),
+                include_bytes!("../../testdata/tile2.pbf").to_vec(),
+            )
+            .expect("Failed to ingest tile");
+        let route = graph
+            .search_djikstra(
+                &costing_model,
+                super::WayId(671949014),
+                0,
+                super::WayId(980366562),
+                0,
+            )
+            .expect("Couldn't find a route.");
+        dbg!(&route);
+        assert_eq!(route.cost.distance().mm(), 2_108_876);
+
+        // Costing the same steps again without entry penalties falls short of the route's cost by
+        // the entry penalty of each way it set off along.
+        let costing = graph.costing(&costing_model).unwrap();
+        let states = graph
+            .search_djikstra_inner(
+                &costing,
+                WayId(671949014),
+                0,
+                WayId(980366562),
+                0,
+                Frontier::new(|_| ElapsedTime::zero()),
+            )
+            .expect("Couldn't find a route.");
+        assert_eq!(states.last().unwrap().cost, route.cost);
+        let mut without_entry_penalties = RoutingCost::zero();
+        let mut entry_penalties = RoutingCost::zero();
+        for window in states.windows(2) {
+            let (state, next) = (window[0], window[1]);
+            let (from, to) = (
+                state.node.distance_along_way_mm,
+                next.via.distance_along_way_mm,
+            );
+            without_entry_penalties = without_entry_penalties
+                + graph
+                    .cost_along_way(&costing, &state.node.way, from, to, false)
+                    .unwrap();
+            if let Some((costed, _)) = graph
+                .costed_transitions(&costing, &next.via)
+                .into_iter()
+                .find(|(_, transition)| {
+                    next.node
+                        == SearchNode {
+                            way: transition.to_way_id,
+                            distance_along_way_mm: transition.transition_to_distance_along_way_mm,
+                        }
+                })
+            {
+                without_entry_penalties = without_entry_penalties + costed.cost;
+            }
+            if state.entering && from != to {
+                let direction = if from < to {
+                    Direction::Forward
+                } else {
+                    Direction::Reverse
+                };
+                entry_penalties = entry_penalties
+                    + graph
+                        .way_coster(&costing, &state.node.way)
+                        .unwrap()
+                        .cost_entry(direction);
+            }
+        }
+        assert!(entry_penalties.elapsed_equivalent() > ElapsedTime::zero());
+        assert_eq!(
+            route.cost.elapsed_equivalent().millis()
+                - without_entry_penalties.elapsed_equivalent().millis(),
+            entry_penalties.elapsed_equivalent().millis()
+        );
+        assert_eq!(
+            route.encoded_polyline,
+            "{hzaHfgyiV??A@CA??B@AFU\\ST]\\C@k@j@CB??CC??CB??GJ??KN??C@???DC@??mBdB??????CD??GF?????GAcH????mCA??{C???WD??Q[K[IUKIOE}B?KCKISQQG??oFC???AEgAGa@Oc@Wa@WSYS???oF@?A?K?A????iB?[????G???_C@?????sE?A?qE???}A???}C???mE?A?qD?O@?A?M?????@Q???E????U@??o@???M???E???G?E???E????{C???C?E???I?A?M?A???C?G?E?E?E???IAA?AA?O????I?I????SA??[???G???e@A????AO??EC??EAAA??@A?A?C?eA?e@?I?EACAC??????AG??ACAG??AE??M_@??E???e@???I???Q????W?[?O??I?M?C?AAA?AC????????????K???E???sBA??E???G????????C?aE??A?A?AAAC?C?A???A@C@A@A@???"
+        );
+    }
+
+    #[test]
+    fn search_finishes_at_destination() {
+        let costing_model = pedestrian_costing_model(1.4);
+        let graph = Graph::new();
+        graph
+            .ingest_tile(
+                2623,
+                5718,
+                14,
+                include_bytes!("../../testdata/tile2.pbf").to_vec(),
+                include_bytes!("../../testdata/tile2.pbf").to_vec(),
+            )
+            .expect("Failed to ingest tile");
+        let way = WayId(671949014);
+        let mut nodes: Vec<i32> = graph
+            .nodes_read
+            .get(&way)
+            .iter()
+            .flatten()
+            .map(|node| node.distance_along_way_mm)
+            .collect();
+        nodes.sort();
+        let (first, second) = nodes
+            .windows(2)
+            .map(|pair| (pair[0], pair[1]))
+            .find(|(first, second)| second - first > 4_000)
+            .expect("No long enough stretch of way.");
+
+        // Between the same two transitions, and across one, in both directions. The route ends at
+        // the destination rather than the transition past it.
+        for (from, to) in [
+            (first + 1_000, second - 1_000),
+            (second - 1_000, first + 1_000),
+            (first + 1_000, second + 500),
+            (second + 500, first + 1_000),
+        ] {
+            let costing = graph.costing(&costing_model).unwrap();
+            let states = graph
+                .search_djikstra_inner(
+                    &costing,
+                    way,
+                    from,
+                    way,
+                    to,
+                    Frontier::new(|_| ElapsedTime::zero()),
+                )
+                .expect("Couldn't find a route.");
+            let last = states.last().unwrap();
+            assert_eq!((last.node.way, last.node.distance_along_way_mm), (way, to));
+            assert_eq!(last.cost.distance().mm(), (to - from).unsigned_abs() as u64);
+        }
+    }
+
+    #[test]
+    fn search_charges_entry_penalties_once_per_way() {
+        let graph = Graph::new();
+        graph
+            .ingest_tile(
+                2623,
+                5718,
+                14,
+                include_bytes!("../../testdata/tile2.pbf").to_vec(),
+                include_bytes!("../../testdata/tile2.pbf").to_vec(),
+            )
+            .expect("Failed to ingest tile");
+        let costing_model = BaseCostingModel::new(
+            |_direction, _tags: &Tags| {
+                let mut cost = WayCost::from_speed(TravelSpeed::from_meters_per_second(1.4));
+                cost.add_flat_penalty(ElapsedTime::from_seconds(10.0));
+                Some(cost)
+            },
+            |_tags: &Tags, transitions_to_cost: &[TransitionToCost]| {
+                let transitions: Vec<WayTransition> = transitions_to_cost
+                    .iter()
+                    .map(|transition| transition.way_transition)
+                    .collect();
+                TransitionCostResult::zero(&transitions)
+            },
+        )
+        .with_name("flat penalties");
+        let costing = graph.costing(&costing_model).unwrap();
+        let states = graph
+            .search_djikstra_inner(
+                &costing,
+                WayId(671949014),
+                0,
+                WayId(980366562),
+                0,
+                Frontier::new(|_| ElapsedTime::zero()),
+            )
+            .expect("Couldn't find a route.");
+
+        // Only the penalties make the route cost more than it takes, and they're charged for
+        // every way it turns onto and travels along, but not the one it starts on.
+        let ways_entered = states
+            .windows(2)
+            .filter(|window| window[0].entering && window[0].node != window[1].via)
+            .count() as u64;
+        assert!(ways_entered > 0);
+        let cost = states.last().unwrap().cost;
+        assert_eq!(
+            cost.elapsed_equivalent().millis() - cost.elapsed_actual().millis(),
+            10_000 * ways_entered
+        );
+    }
+
+    #[test]
+    fn search_with_several_models() {
+        let graph = Graph::new();
+        graph
+            .ingest_tile(
+                2623,
+                5718,
+                14,
+                include_bytes!("../../testdata/tile2.pbf").to_vec(),
+                include_bytes!("../../testdata/tile2.pbf").to_vec(),
+            )
+            .expect("Failed to ingest tile");
+        let search = |costing_model| {
+            graph
+                .search_djikstra(
+                    costing_model,
+                    super::WayId(671949014),
+                    0,
+                    super::WayId(980366562),
+                    0,
+                )
+                .expect("Couldn't find a route.")
+        };
+        let walking = pedestrian_costing_model(1.4);
+        let running = pedestrian_costing_model(2.8);
+        let walked = search(&walking);
+        let ran = search(&running);
+        assert!(ran.route_cost_seconds() < walked.route_cost_seconds());
+        assert_eq!(ran.cost.distance(), walked.cost.distance());
+        // Costs worked out for one model don't leak into searches with another.
+        assert_eq!(search(&walking), walked);
+        assert_eq!(search(&running), ran);
+    }
+
+    #[test]
+    fn search_with_several_unnamed_models() {
+        let graph = Graph::new();
+        graph
+            .ingest_tile(
+                2623,
+                5718,
+                14,
+                include_bytes!("../../testdata/tile2.pbf").to_vec(),
+                include_bytes!("../../testdata/tile2.pbf").to_vec(),
+            )
+            .expect("Failed to ingest tile");
+        let zero_transitions = |_tags: &Tags, transitions_to_cost: &[TransitionToCost]| {
+            let transitions: Vec<WayTransition> = transitions_to_cost
+                .iter()
+                .map(|transition_to_cost| transition_to_cost.way_transition)
+                .collect();
+            TransitionCostResult::zero(&transitions)
+        };
+        let anywhere = BaseCostingModel::new(
+            |_direction, _tags: &Tags| {
+                Some(WayCost::from_speed(TravelSpeed::from_meters_per_second(
+                    1.4,
+                )))
+            },
+            zero_transitions,
+        );
+        let off_footways = BaseCostingModel::new(
+            |_direction, tags: &Tags| {
+                let mut way_cost = WayCost::from_speed(TravelSpeed::from_meters_per_second(1.4));
+                if tags.tag_is("highway", "footway") {
+                    way_cost.limit_speed(TravelSpeed::from_meters_per_second(0.1));
+                }
+                Some(way_cost)
+            },
+            zero_transitions,
+        );
+        let search = |costing_model| {
+            graph
+                .search_djikstra(costing_model, WayId(671949014), 0, WayId(980366562), 0)
+                .expect("Couldn't find a route.")
+        };
+        // Neither model is named, so each has costs of its own.
+        let anywhere_route = search(&anywhere);
+        let off_footways_route = search(&off_footways);
+        assert!(
+            anywhere_route.route_distance_meters() < off_footways_route.route_distance_meters()
+        );
+        assert_ne!(
+            anywhere_route.encoded_polyline(),
+            off_footways_route.encoded_polyline()
+        );
+    }
+
+    #[test]
+    fn search_astar_matches_djikstra_basic() {
+        let costing_model = pedestrian_costing_model(1.4);
+        let graph = Graph::new();
+        graph
+            .ingest_tile(
+                2625,
+                5721,
+                14,
+                include_bytes!("../../testdata/tile.pbf").to_vec(),
+                include_bytes!("../../testdata/tile.pbf").to_vec(),
+            )
+            .expect("Failed to ingest tile");
+        let (from_way_id, from_way_distance) = graph
+            .nearest_way(
+                &costing_model,
+                &Coord {
+                    x: -122.3126740,
+                    y: 47.6153470,
+                },
+            )
+            .unwrap();
+        let (to_way_id, to_way_distance) = graph
+            .nearest_way(
+                &costing_model,
+                &Coord {
+                    x: -122.315503,
+                    y: 47.6163794,
+                },
+            )
+            .unwrap();
+
+        let djikstra = graph
+            .search_djikstra(
+                &costing_model,
+                from_way_id,
+                from_way_distance,
+                to_way_id,
+                to_way_distance,
+            )
+            .expect("Couldn't find a route.");
+        let astar = graph
+            .search_astar(
+                &costing_model,
+                from_way_id,
+                from_way_distance,
+                to_way_id,
+                to_way_distance,
+            )
+            .expect("Couldn't find a route.");
+        assert_eq!(astar.cost, djikstra.cost);
+    }
+
+    #[test]
+    fn search_astar_matches_djikstra_fremont() {
+        let costing_model = pedestrian_costing_model(1.4);
+        let graph = Graph::new();
+        graph
+            .ingest_tile(
+                2623,
+                5718,
+                14,
+                include_bytes!("../../testdata/tile2.pbf").to_vec(),
+                include_bytes!("../../testdata/tile2.pbf").to_vec(),
+            )
+            .expect("Failed to ingest tile");
+        let djikstra = graph
+            .search_djikstra(
+                &costing_model,
+                super::WayId(671949014),
+                0,
+                super::WayId(980366562),
+                0,
+            )
+            .expect("Couldn't find a route.");
+        let astar = graph
+            .search_astar(
+                &costing_model,
+                super::WayId(671949014),
+                0,
+                super::WayId(980366562),
+                0,
+            )
+            .expect("Couldn't find a route.");
+        assert_eq!(astar.cost, djikstra.cost);
+    }
+}
@@ -0,0 +1,515 @@
+use geo::{Bearing, Haversine, Point};
+use serde::Serialize;
+
+use crate::costing::{
+    CostingModel,
+    units::{Direction, ElapsedTime, TravelledDistance},
+};
+
+use super::{Costing, Frontier, Graph, SearchNode, SearchResult, WayId};
+
+/// How far along a way to look either side of a snapped point when measuring its bearing.
+const SNAP_BEARING_SAMPLE_DISTANCE_MM: i32 = 5_000;
+/// Points closer than this to a way are on it, rather than to either side.
+const SNAP_ON_WAY_METERS: f64 = 0.5;
+/// Added to the distance of candidates the point isn't on the preferred side of, when ranking.
+const SNAP_WRONG_SIDE_PENALTY_METERS: f64 = 10.0;
+
+/// Which side of a way a point is on, looking in the direction of travel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum SideOfStreet {
+    Left,
+    Right,
+}
+
+impl SideOfStreet {
+    fn opposite(self) -> SideOfStreet {
+        match self {
+            SideOfStreet::Left => SideOfStreet::Right,
+            SideOfStreet::Right => SideOfStreet::Left,
+        }
+    }
+}
+
+/// Constraints on which ways a point may snap to, and preferences between them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SnapOptions {
+    radius_meters: f64,
+    heading: Option<(f64, f64)>,
+    side_of_street: Option<SideOfStreet>,
+}
+
+impl SnapOptions {
+    /// Snaps to ways within `radius_meters` of the point.
+    pub fn new(radius_meters: f64) -> SnapOptions {
+        SnapOptions {
+            radius_meters,
+            heading: None,
+            side_of_street: None,
+        }
+    }
+
+    /// Only snaps to ways that can be travelled within `tolerance_degrees` of `heading_degrees`,
+    /// clockwise from north.
+    pub fn with_heading(mut self, heading_degrees: f64, tolerance_degrees: f64) -> SnapOptions {
+        self.heading = Some((heading_degrees, tolerance_degrees));
+        self
+    }
+
+    /// Ranks ways that can be travelled with the point on `side` ahead of those that can't.
+    pub fn with_side_of_street(mut self, side: SideOfStreet) -> SnapOptions {
+        self.side_of_street = Some(side);
+        self
+    }
+}
+
+/// A way a point could snap to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SnapCandidate {
+    way: WayId,
+    distance_along_way_mm: i32,
+    coord: geo::Coord,
+    distance_meters: f64,
+    bearing: f64,
+    forward_passable: bool,
+    reverse_passable: bool,
+    side_of_street: Option<SideOfStreet>,
+    departure_direction: Option<Direction>,
+}
+
+impl SnapCandidate {
+    pub fn way(&self) -> WayId {
+        self.way
+    }
+
+    pub fn distance_along_way_mm(&self) -> i32 {
+        self.distance_along_way_mm
+    }
+
+    /// The point on the way the candidate snaps to.
+    pub fn coord(&self) -> geo::Coord {
+        self.coord
+    }
+
+    /// How far the point is from the way.
+    pub fn distance_meters(&self) -> f64 {
+        self.distance_meters
+    }
+
+    /// The bearing of the way where the point snaps to it, travelling in `direction`.
+    pub fn bearing(&self, direction: Direction) -> f64 {
+        match direction {
+            Direction::Forward => self.bearing,
+            Direction::Reverse => (self.bearing + 180.0).rem_euclid(360.0),
+        }
+    }
+
+    /// Whether the way can be travelled in `direction`.
+    pub fn passable(&self, direction: Direction) -> bool {
+        match direction {
+            Direction::Forward => self.forward_passable,
+            Direction::Reverse => self.reverse_passable,
+        }
+    }
+
+    /// Which side of the way the point is on, travelling in `direction`. `None` if it's on the
+    /// way itself.
+    pub fn side_of_street(&self, direction: Direction) -> Option<SideOfStreet> {
+        match direction {
+            Direction::Forward => self.side_of_street,
+            Direction::Reverse => self.side_of_street.map(SideOfStreet::opposite),
+        }
+    }
+
+    /// The only direction a route from this candidate may leave in to satisfy the heading and side
+    /// of street it was snapped with. `None` if it may leave in either.
+    pub fn departure_direction(&self) -> Option<Direction> {
+        self.departure_direction
+    }
+
+    /// The directions the way can be travelled in that satisfy the heading constraint, if any.
+    fn allowed_directions(&self, options: &SnapOptions) -> Vec<Direction> {
+        [Direction::Forward, Direction::Reverse]
+            .into_iter()
+            .filter(|direction| self.passable(*direction))
+            .filter(|direction| {
+                options.heading.is_none_or(|(heading, tolerance)| {
+                    let difference =
+                        (self.bearing(*direction) - heading + 180.0).rem_euclid(360.0) - 180.0;
+                    difference.abs() <= tolerance
+                })
+            })
+            .collect()
+    }
+
+    /// Narrows `directions` to those with the point on the preferred side, if there are any, and
+    /// ranks the candidate by its distance, penalised if there aren't.
+    fn rank(&mut self, options: &SnapOptions, directions: Vec<Direction>) -> f64 {
+        let preferred: Vec<Direction> = directions
+            .iter()
+            .copied()
+            .filter(|direction| {
+                options.side_of_street.is_none_or(|preferred| {
+                    self.side_of_street(*direction)
+                        .is_none_or(|side| side == preferred)
+                })
+            })
+            .collect();
+        let (directions, ranking_distance) = if preferred.is_empty() {
+            (
+                directions,
+                self.distance_meters + SNAP_WRONG_SIDE_PENALTY_METERS,
+            )
+        } else {
+            (preferred, self.distance_meters)
+        };
+        self.departure_direction = match directions[..] {
+            [direction] => Some(direction),
+            _ => None,
+        };
+        ranking_distance
+    }
+}
+
+/// Where a point snapped to, and every candidate it could have snapped to, best first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snap {
+    candidates: Vec<SnapCandidate>,
+}
+
+impl Snap {
+    pub fn way(&self) -> WayId {
+        self.candidates[0].way
+    }
+
+    pub fn distance_along_way_mm(&self) -> i32 {
+        self.candidates[0].distance_along_way_mm
+    }
+
+    /// The point on the way snapped to.
+    pub fn coord(&self) -> geo::Coord {
+        self.candidates[0].coord
+    }
+
+    /// How far the point was moved to snap it.
+    pub fn distance_meters(&self) -> f64 {
+        self.candidates[0].distance_meters
+    }
+
+    /// The only direction a route from the snapped point may leave in, if it's restricted.
+    pub fn departure_direction(&self) -> Option<Direction> {
+        self.candidates[0].departure_direction
+    }
+
+    pub fn candidates(&self) -> Vec<SnapCandidate> {
+        self.candidates.clone()
+    }
+}
+
+impl Graph {
+    /// Snaps a point to a way within the radius of `options`, considering only ways that can be
+    /// travelled along its heading, if given. Candidates are ranked by distance, with those the
+    /// point isn't on the preferred side of, if given, ranked as if they were further away. Each
+    /// candidate records the direction a route has to leave it in to honour the heading and side,
+    /// which `search_between_snaps` follows. `None` if no way qualifies.
+    pub fn snap(
+        &self,
+        costing_model: &dyn CostingModel,
+        coord: &geo::Coord,
+        options: &SnapOptions,
+    ) -> Option<Snap> {
+        let costing = self.costing(costing_model).ok()?;
+        let point = Point::new(coord.x, coord.y);
+        let mut candidates: Vec<(f64, SnapCandidate)> = self
+            .ways_within_radius(costing_model, coord, options.radius_meters)
+            .ok()?
+            .into_iter()
+            .filter_map(|(way, distance_along_way_mm, distance_meters)| {
+                let mut candidate = self.snap_candidate(
+                    &costing,
+                    &point,
+                    way,
+                    distance_along_way_mm,
+                    distance_meters,
+                )?;
+                let directions = candidate.allowed_directions(options);
+                if directions.is_empty() {
+                    return None;
+                }
+                Some((candidate.rank(options, directions), candidate))
+            })
+            .collect();
+        if candidates.is_empty() {
+            return None;
+        }
+        candidates.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Some(Snap {
+            candidates: candidates
+                .into_iter()
+                .map(|(_, candidate)| candidate)
+                .collect(),
+        })
+    }
+
+    /// Like `search_djikstra`, from the point `from` snapped to, leaving it in its departure
+    /// direction if it has one, to the point `to` snapped to.
+    pub fn search_between_snaps(
+        &self,
+        costing_model: &dyn CostingModel,
+        from: &Snap,
+        to: &Snap,
+    ) -> Option<SearchResult> {
+        let costing = self.costing(costing_model).ok()?;
+        let states = self.search_djikstra_departing(
+            &costing,
+            SearchNode {
+                way: from.way(),
+                distance_along_way_mm: from.distance_along_way_mm(),
+            },
+            from.departure_direction(),
+            SearchNode {
+                way: to.way(),
+                distance_along_way_mm: to.distance_along_way_mm(),
+            },
+            Frontier::new(|_| ElapsedTime::zero()),
+        )?;
+        self.build_search_result(&states)
+    }
+
+    fn snap_candidate(
+        &self,
+        costing: &Costing,
+        point: &Point,
+        way: WayId,
+        distance_along_way_mm: i32,
+        distance_meters: f64,
+    ) -> Option<SnapCandidate> {
+        let coster = self.way_coster(costing, &way)?;
+        let forward_passable = coster
+            .cost_way_segment(TravelledDistance(1), Direction::Forward)
+            .is_some();
+        let reverse_passable = coster
+            .cost_way_segment(TravelledDistance(1), Direction::Reverse)
+            .is_some();
+        let (way_start_mm, way_end_mm) = self.way_span_mm(&way)?;
+        let snapped = self.point_along_way(&way, distance_along_way_mm)?;
+        let bearing = Haversine.bearing(
+            self.point_along_way(
+                &way,
+                (distance_along_way_mm - SNAP_BEARING_SAMPLE_DISTANCE_MM).max(way_start_mm),
+            )?,
+            self.point_along_way(
+                &way,
+                (distance_along_way_mm + SNAP_BEARING_SAMPLE_DISTANCE_MM).min(way_end_mm),
+            )?,
+        );
+        let side_of_street = if distance_meters < SNAP_ON_WAY_METERS {
+            None
+        } else {
+            let turn =
+                (Haversine.bearing(snapped, *point) - bearing + 180.0).rem_euclid(360.0) - 180.0;
+            Some(if turn > 0.0 {
+                SideOfStreet::Right
+            } else {
+                SideOfStreet::Left
+            })
+        };
+
+        Some(SnapCandidate {
+            way,
+            distance_along_way_mm,
+            coord: snapped.0,
+            distance_meters,
+            bearing,
+            forward_passable,
+            reverse_passable,
+            side_of_street,
+            departure_direction: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use geo::{Bearing, Coord, Haversine};
+
+    use crate::costing::{
+        Tags, TransitionCostResult, TransitionToCost,
+        base::{BaseCostingModel, WayCost},
+        pedestrian::pedestrian_costing_model,
+        units::{Direction, TravelSpeed},
+    };
+    use crate::graph::WayTransition;
+
+    use super::super::Graph;
+    use super::{SnapCandidate, SnapOptions};
+
+    #[test]
+    fn snap_heading_side_and_radius() {
+        // Every way is one-way, so snapping against a way's direction has to skip it.
+        let costing_model = BaseCostingModel::new(
+            |direction, _tags: &Tags| {
+                (direction == Direction::Forward)
+                    .then(|| WayCost::from_speed(TravelSpeed::from_meters_per_second(10.0)))
+            },
+            |_tags: &Tags, transitions_to_cost: &[TransitionToCost]| {
+                let transitions: Vec<WayTransition> = transitions_to_cost
+                    .iter()
+                    .map(|transition_to_cost| transition_to_cost.way_transition)
+                    .collect();
+                TransitionCostResult::zero(&transitions)
+            },
+        );
+        let graph = Graph::new();
+        graph
+            .ingest_tile(
+                2625,
+                5721,
+                14,
+                include_bytes!("../../testdata/tile.pbf").to_vec(),
+                include_bytes!("../../testdata/tile.pbf").to_vec(),
+            )
+            .expect("Failed to ingest tile");
+        // A few meters off a sidewalk.
+        let coord = Coord {
+            x: -122.3126740,
+            y: 47.6153970,
+        };
+
+        let snap = graph
+            .snap(&costing_model, &coord, &SnapOptions::new(30.0))
+            .expect("Couldn't snap.");
+        assert_eq!(
+            (snap.way(), snap.distance_along_way_mm()),
+            graph.nearest_way(&costing_model, &coord).unwrap()
+        );
+        let candidates = snap.candidates();
+        assert!(candidates.len() > 1);
+        assert!(
+            candidates
+                .windows(2)
+                .all(|pair| pair[0].distance_meters() <= pair[1].distance_meters())
+        );
+        assert!(candidates.iter().all(|candidate| {
+            candidate.distance_meters() <= 30.0
+                && candidate.passable(Direction::Forward)
+                && !candidate.passable(Direction::Reverse)
+        }));
+        let nearest = candidates[0];
+
+        let along = graph
+            .snap(
+                &costing_model,
+                &coord,
+                &SnapOptions::new(30.0).with_heading(nearest.bearing(Direction::Forward), 30.0),
+            )
+            .expect("Couldn't snap.");
+        assert_eq!(along.way(), nearest.way());
+        let against = graph.snap(
+            &costing_model,
+            &coord,
+            &SnapOptions::new(30.0).with_heading(nearest.bearing(Direction::Reverse), 30.0),
+        );
+        assert!(against.is_none_or(|snap| {
+            snap.candidates()
+                .iter()
+                .all(|candidate| candidate.way() != nearest.way())
+        }));
+
+        let opposite = nearest
+            .side_of_street(Direction::Forward)
+            .expect("Point is on the way.")
+            .opposite();
+        let preferred = graph
+            .snap(
+                &costing_model,
+                &coord,
+                &SnapOptions::new(30.0).with_side_of_street(opposite),
+            )
+            .expect("Couldn't snap.");
+        let on_opposite_side = |candidate: &SnapCandidate| {
+            candidate.side_of_street(Direction::Forward) == Some(opposite)
+        };
+        if candidates.iter().any(on_opposite_side) {
+            assert!(on_opposite_side(&preferred.candidates()[0]));
+        }
+
+        assert!(
+            graph
+                .snap(
+                    &costing_model,
+                    &coord,
+                    &SnapOptions::new(nearest.distance_meters() / 2.0)
+                )
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn snap_side_of_street_sets_departure() {
+        let costing_model = pedestrian_costing_model(1.4);
+        let graph = Graph::new();
+        graph
+            .ingest_tile(
+                2625,
+                5721,
+                14,
+                include_bytes!("../../testdata/tile.pbf").to_vec(),
+                include_bytes!("../../testdata/tile.pbf").to_vec(),
+            )
+            .expect("Failed to ingest tile");
+        let from = Coord {
+            x: -122.3126740,
+            y: 47.6153970,
+        };
+        let to = graph
+            .snap(
+                &costing_model,
+                &Coord {
+                    x: -122.315503,
+                    y: 47.6163794,
+                },
+                &SnapOptions::new(30.0),
+            )
+            .expect("Couldn't snap.");
+
+        let unconstrained = graph
+            .snap(&costing_model, &from, &SnapOptions::new(30.0))
+            .expect("Couldn't snap.");
+        assert_eq!(unconstrained.departure_direction(), None);
+        let nearest = unconstrained.candidates()[0];
+        assert!(nearest.passable(Direction::Forward) && nearest.passable(Direction::Reverse));
+        let side = nearest
+            .side_of_street(Direction::Forward)
+            .expect("Point is on the way.");
+
+        for (side, expected_departure) in [
+            (side, Direction::Forward),
+            (side.opposite(), Direction::Reverse),
+        ] {
+            let snap = graph
+                .snap(
+                    &costing_model,
+                    &from,
+                    &SnapOptions::new(30.0).with_side_of_street(side),
+                )
+                .expect("Couldn't snap.");
+            assert_eq!(snap.way(), nearest.way());
+            assert_eq!(snap.departure_direction(), Some(expected_departure));
+
+            let route = graph
+                .search_between_snaps(&costing_model, &snap, &to)
+                .expect("No route found.");
+            let points = polyline::decode_polyline(&route.encoded_polyline(), 5)
+                .expect("Invalid polyline.")
+                .into_points();
+            let leaving = Haversine.bearing(points[0], points[1]);
+            let difference =
+                (leaving - nearest.bearing(expected_departure) + 180.0).rem_euclid(360.0) - 180.0;
+            assert!(
+                difference.abs() < 45.0,
+                "Route left at {leaving} degrees, {difference} off the departure bearing."
+            );
+        }
+    }
+}
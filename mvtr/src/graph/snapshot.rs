@@ -0,0 +1,931 @@
+//! A binary snapshot of a costed graph, so a region doesn't have to be decoded from its tiles and
+//! costed again each time it's loaded.
+//!
+//! Everything is little-endian. The header is the magic bytes, the format version, the number of
+//! sections, the name of the costing model the snapshot was costed with, flags saying whether it
+//! had one, the model's fingerprint and a table giving each section's kind, record size, offset and
+//! record count. Each section is an array of fixed-size records starting on an 8-byte boundary, so
+//! they can be read straight out of a mapped file. Records refer to their tile by its index in the
+//! tile section, to strings by offset and length in the string section, to tags by the index of
+//! their first tag in the tag section, and to geometry by the index of their first coordinate in
+//! the coordinate section. Records are written in a fixed order, so the same graph always saves to
+//! the same bytes.
+//!
+//! Loading isn't done in place, though: the graph's maps and spatial index own what they hold, so
+//! every section is decoded into a fresh graph and the index is built over its ways. What loading
+//! saves over ingesting is decoding the tiles and costing them again.
+//!
+//! Ways and intersections keep their tags, so the graph can be searched with any costing model
+//! once loaded. Only the costs of the model it was saved with are included, and only if that model
+//! is named. They're used by models of that name with the same fingerprint.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    io::Write,
+    sync::Arc,
+};
+
+use crate::costing::{
+    CostingModel, RoutingCost, Tags, WayCoster,
+    units::{ElapsedTime, PartsPerMillion, TravelSpeed, TravelledDistance},
+};
+
+use super::{
+    CostCache, CostedWayTransition, Graph, SearchNode, TileCoordinates, WayId, WayTransition,
+    stitching::WayFragment,
+    tiles::{TileContents, TileId},
+};
+
+const SNAPSHOT_MAGIC: &[u8; 8] = b"MVTRSNAP";
+/// Bumped whenever the layout changes. Snapshots of any other version are rejected rather than
+/// misread.
+pub const SNAPSHOT_VERSION: u32 = 6;
+
+const HEADER_SIZE: usize = 40;
+const SECTION_ENTRY_SIZE: usize = 24;
+
+const SECTION_TILES: u32 = 1;
+const SECTION_WAYS: u32 = 2;
+const SECTION_STREET_NAMES: u32 = 3;
+const SECTION_NODES: u32 = 4;
+const SECTION_TRANSITIONS: u32 = 5;
+const SECTION_FRAGMENTS: u32 = 6;
+const SECTION_COORDINATES: u32 = 7;
+const SECTION_STRINGS: u32 = 8;
+const SECTION_TAGS: u32 = 9;
+const SECTION_WAY_COSTS: u32 = 10;
+const SECTION_TRANSITION_COSTS: u32 = 11;
+
+/// Each section's kind and the size of its records.
+const SECTIONS: [(u32, usize); 11] = [
+    (SECTION_TILES, 16),
+    (SECTION_WAYS, 24),
+    (SECTION_STREET_NAMES, 24),
+    (SECTION_NODES, 16),
+    (SECTION_TRANSITIONS, 40),
+    (SECTION_FRAGMENTS, 32),
+    (SECTION_COORDINATES, 8),
+    (SECTION_TAGS, 16),
+    (SECTION_WAY_COSTS, 48),
+    (SECTION_TRANSITION_COSTS, 48),
+    (SECTION_STRINGS, 1),
+];
+
+const HEADER_NAMED_MODEL: u32 = 1;
+
+const WAY_SPEED_FORWARD: u32 = 1;
+const WAY_SPEED_REVERSE: u32 = 1 << 1;
+const WAY_PENALTY_FORWARD: u32 = 1 << 2;
+const WAY_PENALTY_REVERSE: u32 = 1 << 3;
+
+/// A tile a snapshot holds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotTile {
+    x: u32,
+    y: u32,
+    z: u32,
+}
+
+impl SnapshotTile {
+    pub fn x(&self) -> u32 {
+        self.x
+    }
+
+    pub fn y(&self) -> u32 {
+        self.y
+    }
+
+    pub fn z(&self) -> u32 {
+        self.z
+    }
+}
+
+/// What produced a snapshot, readable without loading it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotHeader {
+    version: u32,
+    costing_model: Option<String>,
+    tiles: Vec<SnapshotTile>,
+}
+
+impl SnapshotHeader {
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// The `CostingModel::name` of the model the snapshot was costed with, if it had one.
+    pub fn costing_model(&self) -> Option<&str> {
+        self.costing_model.as_deref()
+    }
+
+    pub fn tiles(&self) -> &[SnapshotTile] {
+        &self.tiles
+    }
+}
+
+/// Appends the fields of fixed-size records to a section.
+#[derive(Default)]
+struct SectionWriter {
+    bytes: Vec<u8>,
+}
+
+impl SectionWriter {
+    fn u32(&mut self, value: u32) -> &mut Self {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    fn i32(&mut self, value: i32) -> &mut Self {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    fn u64(&mut self, value: u64) -> &mut Self {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    fn f64(&mut self, value: f64) -> &mut Self {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+}
+
+/// Appends strings to the string section, writing each distinct string once, as tags mostly repeat
+/// the same few keys and values.
+#[derive(Default)]
+struct StringsWriter {
+    section: SectionWriter,
+    offsets: HashMap<String, (u32, u32)>,
+}
+
+impl StringsWriter {
+    /// The offset and length of `value` in the string section.
+    fn string(&mut self, value: &str) -> (u32, u32) {
+        if let Some(written) = self.offsets.get(value) {
+            return *written;
+        }
+        let written = (self.section.bytes.len() as u32, value.len() as u32);
+        self.section.bytes.extend_from_slice(value.as_bytes());
+        self.offsets.insert(value.to_string(), written);
+        written
+    }
+}
+
+/// A map's entries in key order, so what's written from it doesn't depend on how it hashes.
+fn sorted<K: Ord, V>(map: &HashMap<K, V>) -> Vec<(&K, &V)> {
+    let mut entries: Vec<(&K, &V)> = map.iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    entries
+}
+
+/// Appends tags to the tag section, returning the index of the first and how many there are.
+fn write_tags(
+    sections: &mut HashMap<u32, SectionWriter>,
+    strings: &mut StringsWriter,
+    tags: &Tags,
+) -> (u32, u32) {
+    let section = sections.entry(SECTION_TAGS).or_default();
+    let first = (section.bytes.len() / 16) as u32;
+    let mut entries: Vec<(String, String)> = tags.to_hashmap().into_iter().collect();
+    entries.sort();
+    for (key, value) in &entries {
+        let (key_offset, key_length) = strings.string(key);
+        let (value_offset, value_length) = strings.string(value);
+        section
+            .u32(key_offset)
+            .u32(key_length)
+            .u32(value_offset)
+            .u32(value_length);
+    }
+    (first, entries.len() as u32)
+}
+
+/// Reads the fields of a record in order.
+struct RecordReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> RecordReader<'a> {
+    fn take<const N: usize>(&mut self) -> [u8; N] {
+        let (field, rest) = self.bytes.split_at(N);
+        self.bytes = rest;
+        field.try_into().unwrap()
+    }
+
+    fn u32(&mut self) -> u32 {
+        u32::from_le_bytes(self.take())
+    }
+
+    fn i32(&mut self) -> i32 {
+        i32::from_le_bytes(self.take())
+    }
+
+    fn u64(&mut self) -> u64 {
+        u64::from_le_bytes(self.take())
+    }
+
+    fn f64(&mut self) -> f64 {
+        f64::from_le_bytes(self.take())
+    }
+}
+
+/// A snapshot's sections, checked to lie within it and have the record sizes this version expects.
+struct Snapshot<'a> {
+    /// The offset and length of the costing model's name, if it had one.
+    costing_model: Option<(u32, u32)>,
+    fingerprint: u64,
+    sections: HashMap<u32, &'a [u8]>,
+}
+
+impl<'a> Snapshot<'a> {
+    fn parse(bytes: &'a [u8]) -> anyhow::Result<Snapshot<'a>> {
+        if bytes.len() < HEADER_SIZE || &bytes[..8] != SNAPSHOT_MAGIC {
+            return Err(anyhow::anyhow!("Not a graph snapshot"));
+        }
+        let mut header = RecordReader {
+            bytes: &bytes[8..HEADER_SIZE],
+        };
+        let version = header.u32();
+        if version != SNAPSHOT_VERSION {
+            return Err(anyhow::anyhow!(
+                "Unsupported graph snapshot version {}, expected {}",
+                version,
+                SNAPSHOT_VERSION
+            ));
+        }
+        let section_count = header.u32() as usize;
+        let costing_model = (header.u32(), header.u32());
+        let flags = header.u32();
+        header.u32();
+        let costing_model = (flags & HEADER_NAMED_MODEL != 0).then_some(costing_model);
+        let fingerprint = header.u64();
+
+        let table_end = HEADER_SIZE + section_count * SECTION_ENTRY_SIZE;
+        let table = bytes
+            .get(HEADER_SIZE..table_end)
+            .ok_or_else(|| anyhow::anyhow!("Graph snapshot is truncated"))?;
+        let mut sections = HashMap::new();
+        for entry in table.chunks_exact(SECTION_ENTRY_SIZE) {
+            let mut entry = RecordReader { bytes: entry };
+            let kind = entry.u32();
+            let record_size = entry.u32() as usize;
+            let offset = entry.u64() as usize;
+            let count = entry.u64() as usize;
+            let expected_size = SECTIONS
+                .iter()
+                .find(|(section, _)| *section == kind)
+                .map(|(_, size)| *size);
+            if expected_size != Some(record_size) {
+                return Err(anyhow::anyhow!(
+                    "Graph snapshot section {} has unexpected records of {} bytes",
+                    kind,
+                    record_size
+                ));
+            }
+            let section = count
+                .checked_mul(record_size)
+                .and_then(|length| offset.checked_add(length))
+                .and_then(|end| bytes.get(offset..end))
+                .ok_or_else(|| anyhow::anyhow!("Graph snapshot is truncated"))?;
+            sections.insert(kind, section);
+        }
+        Ok(Snapshot {
+            costing_model,
+            fingerprint,
+            sections,
+        })
+    }
+
+    fn records(&self, kind: u32) -> anyhow::Result<impl Iterator<Item = RecordReader<'a>>> {
+        let (_, record_size) = SECTIONS
+            .iter()
+            .find(|(section, _)| *section == kind)
+            .unwrap();
+        let section = self
+            .sections
+            .get(&kind)
+            .ok_or_else(|| anyhow::anyhow!("Graph snapshot is missing section {}", kind))?;
+        Ok(section
+            .chunks_exact(*record_size)
+            .map(|bytes| RecordReader { bytes }))
+    }
+
+    fn string(&self, offset: u32, length: u32) -> anyhow::Result<String> {
+        let strings = self.sections.get(&SECTION_STRINGS).copied().unwrap_or(&[]);
+        let end = offset
+            .checked_add(length)
+            .ok_or_else(|| anyhow::anyhow!("Graph snapshot string is out of bounds"))?;
+        let bytes = strings
+            .get(offset as usize..end as usize)
+            .ok_or_else(|| anyhow::anyhow!("Graph snapshot string is out of bounds"))?;
+        Ok(std::str::from_utf8(bytes)
+            .map_err(|err| anyhow::anyhow!("Graph snapshot string is not UTF-8 {}", err))?
+            .to_string())
+    }
+
+    fn costing_model(&self) -> anyhow::Result<Option<String>> {
+        self.costing_model
+            .map(|(offset, length)| self.string(offset, length))
+            .transpose()
+    }
+
+    fn tiles(&self) -> anyhow::Result<Vec<SnapshotTile>> {
+        Ok(self
+            .records(SECTION_TILES)?
+            .map(|mut record| SnapshotTile {
+                x: record.u32(),
+                y: record.u32(),
+                z: record.u32(),
+            })
+            .collect())
+    }
+
+    /// Every tag in the snapshot, in order, for records to pick theirs out of by index.
+    fn tags(&self) -> anyhow::Result<Vec<(String, String)>> {
+        self.records(SECTION_TAGS)?
+            .map(|mut record| {
+                let key = self.string(record.u32(), record.u32())?;
+                let value = self.string(record.u32(), record.u32())?;
+                Ok((key, value))
+            })
+            .collect()
+    }
+}
+
+/// The tags a record refers to, from those `Snapshot::tags` read.
+fn tags(all_tags: &[(String, String)], first: u32, count: u32) -> anyhow::Result<Arc<Tags>> {
+    let end = first
+        .checked_add(count)
+        .ok_or_else(|| anyhow::anyhow!("Graph snapshot tags are out of bounds"))?;
+    let tags = all_tags
+        .get(first as usize..end as usize)
+        .ok_or_else(|| anyhow::anyhow!("Graph snapshot tags are out of bounds"))?;
+    Ok(Arc::new(Tags::from_hashmap(tags.iter().cloned().collect())))
+}
+
+fn tile_contents(tiles: &mut [TileContents], index: u32) -> anyhow::Result<&mut TileContents> {
+    tiles
+        .get_mut(index as usize)
+        .ok_or_else(|| anyhow::anyhow!("Graph snapshot refers to a missing tile"))
+}
+
+fn optional_speed(flags: u32, flag: u32, um_per_ms: u32) -> Option<TravelSpeed> {
+    (flags & flag != 0).then_some(TravelSpeed { um_per_ms })
+}
+
+fn optional_penalty(flags: u32, flag: u32, ppm: u32) -> Option<PartsPerMillion> {
+    (flags & flag != 0).then_some(PartsPerMillion(ppm))
+}
+
+impl Graph {
+    /// Writes every loaded tile to a snapshot `Graph::load` can read back, along with its costs
+    /// under `costing_model`.
+    pub fn save(
+        &self,
+        costing_model: &dyn CostingModel,
+        writer: &mut impl Write,
+    ) -> anyhow::Result<()> {
+        let costing = self.costing(costing_model)?;
+        let tiles = self
+            .tiles
+            .lock()
+            .map_err(|err| anyhow::anyhow!("Failed to lock mutex: {}", err))?;
+
+        let mut sections: HashMap<u32, SectionWriter> = HashMap::new();
+        let mut strings = StringsWriter::default();
+        let mut coordinate_count = 0u32;
+        let mut ways = BTreeSet::new();
+        let mut nodes = BTreeSet::new();
+        for (index, (tile, contents)) in sorted(&tiles).into_iter().enumerate() {
+            let index = index as u32;
+            sections
+                .entry(SECTION_TILES)
+                .or_default()
+                .u32(tile.x)
+                .u32(tile.y)
+                .u32(tile.z)
+                .u32(0);
+            for (way, way_tags) in sorted(&contents.ways) {
+                ways.insert(*way);
+                for tags in way_tags {
+                    let (first_tag, tag_count) = write_tags(&mut sections, &mut strings, tags);
+                    sections
+                        .entry(SECTION_WAYS)
+                        .or_default()
+                        .u32(index)
+                        .u32(first_tag)
+                        .u64(way.0)
+                        .u32(tag_count)
+                        .u32(0);
+                }
+            }
+            for (way, street_names) in sorted(&contents.street_names) {
+                for street_name in street_names {
+                    let (offset, length) = strings.string(street_name);
+                    sections
+                        .entry(SECTION_STREET_NAMES)
+                        .or_default()
+                        .u32(index)
+                        .u32(0)
+                        .u64(way.0)
+                        .u32(offset)
+                        .u32(length);
+                }
+            }
+            for node in sorted(&contents.nodes)
+                .into_iter()
+                .flat_map(|(_, nodes)| nodes)
+            {
+                sections
+                    .entry(SECTION_NODES)
+                    .or_default()
+                    .u32(index)
+                    .i32(node.distance_along_way_mm)
+                    .u64(node.way.0);
+            }
+            for (node, transitions) in sorted(&contents.transitions) {
+                nodes.insert(*node);
+                for (way_transition, intersection_tags) in transitions {
+                    let (first_tag, tag_count) =
+                        write_tags(&mut sections, &mut strings, intersection_tags);
+                    sections
+                        .entry(SECTION_TRANSITIONS)
+                        .or_default()
+                        .u32(index)
+                        .i32(way_transition.distance_along_way_mm)
+                        .u64(way_transition.from_way_id.0)
+                        .u64(way_transition.to_way_id.0)
+                        .i32(way_transition.transition_to_distance_along_way_mm)
+                        .u32(first_tag)
+                        .u32(tag_count)
+                        .u32(0);
+                }
+            }
+            for (way, fragments) in sorted(&contents.geometry) {
+                for fragment in fragments {
+                    let extent = fragment.polyline.first().map_or(0, |coords| coords.extent);
+                    sections
+                        .entry(SECTION_FRAGMENTS)
+                        .or_default()
+                        .u32(index)
+                        .u32(extent)
+                        .u64(way.0)
+                        .f64(fragment.start_meters.unwrap_or(f64::NAN))
+                        .u32(coordinate_count)
+                        .u32(fragment.polyline.len() as u32);
+                    for coords in &fragment.polyline {
+                        sections
+                            .entry(SECTION_COORDINATES)
+                            .or_default()
+                            .i32(coords.tile_x)
+                            .i32(coords.tile_y);
+                    }
+                    coordinate_count += fragment.polyline.len() as u32;
+                }
+            }
+        }
+        drop(tiles);
+
+        // An unnamed model's costs can't be told apart from any other's once loaded.
+        if costing.fingerprint.is_none() {
+            ways.clear();
+            nodes.clear();
+        }
+        for way in ways {
+            let Some(way_coster) = self.way_coster(&costing, &way) else {
+                continue;
+            };
+            let flags = [
+                (way_coster.speed_forward.is_some(), WAY_SPEED_FORWARD),
+                (way_coster.speed_reverse.is_some(), WAY_SPEED_REVERSE),
+                (
+                    way_coster.penalty_ppm_forward.is_some(),
+                    WAY_PENALTY_FORWARD,
+                ),
+                (
+                    way_coster.penalty_ppm_reverse.is_some(),
+                    WAY_PENALTY_REVERSE,
+                ),
+            ]
+            .into_iter()
+            .filter(|(present, _)| *present)
+            .fold(0, |flags, (_, flag)| flags | flag);
+            sections
+                .entry(SECTION_WAY_COSTS)
+                .or_default()
+                .u32(flags)
+                .u32(0)
+                .u64(way.0)
+                .u32(way_coster.speed_forward.map_or(0, |speed| speed.um_per_ms))
+                .u32(way_coster.speed_reverse.map_or(0, |speed| speed.um_per_ms))
+                .u32(way_coster.penalty_ppm_forward.map_or(0, |ppm| ppm.0))
+                .u32(way_coster.penalty_ppm_reverse.map_or(0, |ppm| ppm.0))
+                .u64(way_coster.entry_penalty_forward.millis())
+                .u64(way_coster.entry_penalty_reverse.millis());
+        }
+        for node in nodes {
+            let mut transitions = self.costed_transitions(&costing, &node);
+            transitions.sort_by_key(|(_, way_transition)| *way_transition);
+            for (costed_way_transition, way_transition) in transitions {
+                let cost = costed_way_transition.cost;
+                sections
+                    .entry(SECTION_TRANSITION_COSTS)
+                    .or_default()
+                    .i32(way_transition.distance_along_way_mm)
+                    .i32(way_transition.transition_to_distance_along_way_mm)
+                    .u64(way_transition.from_way_id.0)
+                    .u64(way_transition.to_way_id.0)
+                    .u64(cost.cost_millis.millis())
+                    .u64(cost.actual_millis.millis())
+                    .u64(cost.distance_mm.mm());
+            }
+        }
+        let (model_offset, model_length) = strings.string(costing_model.name().unwrap_or_default());
+        let flags = if costing.fingerprint.is_some() {
+            HEADER_NAMED_MODEL
+        } else {
+            0
+        };
+        sections.insert(SECTION_STRINGS, strings.section);
+
+        let mut offset = HEADER_SIZE + SECTIONS.len() * SECTION_ENTRY_SIZE;
+        let mut header = SectionWriter::default();
+        header.bytes.extend_from_slice(SNAPSHOT_MAGIC);
+        header
+            .u32(SNAPSHOT_VERSION)
+            .u32(SECTIONS.len() as u32)
+            .u32(model_offset)
+            .u32(model_length)
+            .u32(flags)
+            .u32(0)
+            .u64(costing.fingerprint.unwrap_or(0));
+        let mut body = Vec::new();
+        for (kind, record_size) in SECTIONS {
+            let section = sections.remove(&kind).unwrap_or_default();
+            let padding = offset.next_multiple_of(8) - offset;
+            body.resize(body.len() + padding, 0);
+            offset += padding;
+            header
+                .u32(kind)
+                .u32(record_size as u32)
+                .u64(offset as u64)
+                .u64((section.bytes.len() / record_size) as u64);
+            offset += section.bytes.len();
+            body.extend_from_slice(&section.bytes);
+        }
+        writer.write_all(&header.bytes)?;
+        writer.write_all(&body)?;
+        Ok(())
+    }
+
+    /// Reads which tiles a snapshot holds and how they were costed, without loading it.
+    pub fn read_snapshot_header(bytes: &[u8]) -> anyhow::Result<SnapshotHeader> {
+        let snapshot = Snapshot::parse(bytes)?;
+        Ok(SnapshotHeader {
+            version: SNAPSHOT_VERSION,
+            costing_model: snapshot.costing_model()?,
+            tiles: snapshot.tiles()?,
+        })
+    }
+
+    /// Loads a graph from a snapshot written by `Graph::save`, decoding all of it up front. Searches
+    /// with a costing model of the name the snapshot was saved with start from its costs, if the
+    /// model costs alike.
+    pub fn load(bytes: &[u8]) -> anyhow::Result<Graph> {
+        let snapshot = Snapshot::parse(bytes)?;
+        let all_tags = snapshot.tags()?;
+        let tile_ids: Vec<TileId> = snapshot
+            .tiles()?
+            .iter()
+            .map(|tile| TileId {
+                x: tile.x,
+                y: tile.y,
+                z: tile.z,
+            })
+            .collect();
+        let mut tiles: Vec<TileContents> =
+            tile_ids.iter().map(|_| TileContents::default()).collect();
+
+        for mut record in snapshot.records(SECTION_WAYS)? {
+            let index = record.u32();
+            let first_tag = record.u32();
+            let way = WayId(record.u64());
+            let tags = tags(&all_tags, first_tag, record.u32())?;
+            tile_contents(&mut tiles, index)?.insert_way(way, tags);
+        }
+        for mut record in snapshot.records(SECTION_STREET_NAMES)? {
+            let index = record.u32();
+            record.u32();
+            let way = WayId(record.u64());
+            let street_name = snapshot.string(record.u32(), record.u32())?;
+            tile_contents(&mut tiles, index)?.insert_street_name(way, street_name);
+        }
+        for mut record in snapshot.records(SECTION_NODES)? {
+            let index = record.u32();
+            let distance_along_way_mm = record.i32();
+            let way = WayId(record.u64());
+            tile_contents(&mut tiles, index)?.insert_node(SearchNode {
+                way,
+                distance_along_way_mm,
+            });
+        }
+        for mut record in snapshot.records(SECTION_TRANSITIONS)? {
+            let index = record.u32();
+            let distance_along_way_mm = record.i32();
+            let from_way_id = WayId(record.u64());
+            let to_way_id = WayId(record.u64());
+            let transition_to_distance_along_way_mm = record.i32();
+            let first_tag = record.u32();
+            let intersection_tags = tags(&all_tags, first_tag, record.u32())?;
+            tile_contents(&mut tiles, index)?.insert_transition(
+                WayTransition {
+                    from_way_id,
+                    distance_along_way_mm,
+                    to_way_id,
+                    transition_to_distance_along_way_mm,
+                },
+                intersection_tags,
+            );
+        }
+        let coordinates: Vec<(i32, i32)> = snapshot
+            .records(SECTION_COORDINATES)?
+            .map(|mut record| (record.i32(), record.i32()))
+            .collect();
+        for mut record in snapshot.records(SECTION_FRAGMENTS)? {
+            let index = record.u32();
+            let extent = record.u32();
+            let way = WayId(record.u64());
+            let start_meters = record.f64();
+            let first = record.u32();
+            let count = record.u32();
+            let TileId { x, y, z } = *tile_ids
+                .get(index as usize)
+                .ok_or_else(|| anyhow::anyhow!("Graph snapshot refers to a missing tile"))?;
+            let end = first
+                .checked_add(count)
+                .ok_or_else(|| anyhow::anyhow!("Graph snapshot geometry is out of bounds"))?;
+            let polyline = coordinates
+                .get(first as usize..end as usize)
+                .ok_or_else(|| anyhow::anyhow!("Graph snapshot geometry is out of bounds"))?
+                .iter()
+                .map(|(tile_x, tile_y)| TileCoordinates {
+                    x,
+                    y,
+                    z,
+                    extent,
+                    tile_x: *tile_x,
+                    tile_y: *tile_y,
+                })
+                .collect();
+            tile_contents(&mut tiles, index)?.insert_geometry(
+                way,
+                WayFragment {
+                    start_meters: (!start_meters.is_nan()).then_some(start_meters),
+                    polyline,
+                },
+            );
+        }
+
+        let mut cost_cache = CostCache::new();
+        for mut record in snapshot.records(SECTION_WAY_COSTS)? {
+            let flags = record.u32();
+            record.u32();
+            let way = WayId(record.u64());
+            cost_cache.insert_way(
+                way,
+                WayCoster {
+                    speed_forward: optional_speed(flags, WAY_SPEED_FORWARD, record.u32()),
+                    speed_reverse: optional_speed(flags, WAY_SPEED_REVERSE, record.u32()),
+                    penalty_ppm_forward: optional_penalty(flags, WAY_PENALTY_FORWARD, record.u32()),
+                    penalty_ppm_reverse: optional_penalty(flags, WAY_PENALTY_REVERSE, record.u32()),
+                    entry_penalty_forward: ElapsedTime::from_millis(record.u64()),
+                    entry_penalty_reverse: ElapsedTime::from_millis(record.u64()),
+                },
+            );
+        }
+        let mut transition_costs: HashMap<SearchNode, Vec<(CostedWayTransition, WayTransition)>> =
+            HashMap::new();
+        for mut record in snapshot.records(SECTION_TRANSITION_COSTS)? {
+            let distance_along_way_mm = record.i32();
+            let transition_to_distance_along_way_mm = record.i32();
+            let from_way_id = WayId(record.u64());
+            let to_way_id = WayId(record.u64());
+            let cost = RoutingCost {
+                cost_millis: ElapsedTime::from_millis(record.u64()),
+                actual_millis: ElapsedTime::from_millis(record.u64()),
+                distance_mm: TravelledDistance(record.u64()),
+            };
+            transition_costs
+                .entry(SearchNode {
+                    way: from_way_id,
+                    distance_along_way_mm,
+                })
+                .or_default()
+                .push((
+                    CostedWayTransition { to_way_id, cost },
+                    WayTransition {
+                        from_way_id,
+                        distance_along_way_mm,
+                        to_way_id,
+                        transition_to_distance_along_way_mm,
+                    },
+                ));
+        }
+        for (node, transitions) in transition_costs {
+            cost_cache.insert_transitions(node, transitions);
+        }
+
+        let graph = Graph::new();
+        graph.replace_tiles(
+            tile_ids
+                .into_iter()
+                .zip(tiles.into_iter().map(Some))
+                .collect(),
+        )?;
+        // After the tiles, as loading them clears every cache.
+        if let Some(costing_model) = snapshot.costing_model()? {
+            graph
+                .costs
+                .lock()
+                .map_err(|err| anyhow::anyhow!("Failed to lock mutex: {}", err))?
+                .insert(&costing_model, snapshot.fingerprint, cost_cache);
+        }
+        Ok(graph)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use geo::Coord;
+
+    use crate::costing::{
+        CostingModel, Tags, TransitionCostResult, TransitionToCost, WayCoster,
+        pedestrian::pedestrian_costing_model, units::TravelSpeed,
+    };
+
+    use super::super::{Graph, WayId};
+    use super::SNAPSHOT_VERSION;
+
+    /// Counts the intersections a model is asked to cost.
+    struct CountingModel<Model> {
+        model: Model,
+        intersections: AtomicUsize,
+    }
+
+    impl<Model: CostingModel> CostingModel for CountingModel<Model> {
+        fn cost_intersection(
+            &self,
+            current_way_tags: &Tags,
+            transitions_to_cost: &[TransitionToCost],
+        ) -> TransitionCostResult {
+            self.intersections.fetch_add(1, Ordering::Relaxed);
+            self.model
+                .cost_intersection(current_way_tags, transitions_to_cost)
+        }
+
+        fn cost_way(&self, tags: &Tags) -> WayCoster {
+            self.model.cost_way(tags)
+        }
+
+        fn max_speed(&self) -> Option<TravelSpeed> {
+            self.model.max_speed()
+        }
+
+        fn name(&self) -> Option<&str> {
+            self.model.name()
+        }
+    }
+
+    #[test]
+    fn save_and_load_snapshot() {
+        let costing_model = pedestrian_costing_model(1.4);
+        let tile_data = [
+            (
+                2625,
+                5721,
+                include_bytes!("../../testdata/tile.pbf").to_vec(),
+            ),
+            (
+                2623,
+                5718,
+                include_bytes!("../../testdata/tile2.pbf").to_vec(),
+            ),
+        ];
+        let ingest = |tiles: &[(u32, u32, Vec<u8>)]| {
+            let graph = Graph::new();
+            for (x, y, tile) in tiles {
+                graph
+                    .ingest_tile(*x, *y, 14, tile.clone(), tile.clone())
+                    .expect("Failed to ingest tile");
+            }
+            graph
+        };
+        let graph = ingest(&tile_data);
+        let mut snapshot = Vec::new();
+        graph
+            .save(&costing_model, &mut snapshot)
+            .expect("Failed to save graph");
+
+        // The same tiles save to the same bytes whatever order they were ingested in.
+        let mut reversed = tile_data.clone();
+        reversed.reverse();
+        let mut reversed_snapshot = Vec::new();
+        ingest(&reversed)
+            .save(&costing_model, &mut reversed_snapshot)
+            .expect("Failed to save graph");
+        assert!(reversed_snapshot == snapshot);
+
+        let header = Graph::read_snapshot_header(&snapshot).expect("Failed to read header");
+        assert_eq!(header.version(), SNAPSHOT_VERSION);
+        assert_eq!(header.costing_model(), Some("pedestrian 1.4 m/s"));
+        let mut tiles: Vec<(u32, u32, u32)> = header
+            .tiles()
+            .iter()
+            .map(|tile| (tile.x(), tile.y(), tile.z()))
+            .collect();
+        tiles.sort();
+        assert_eq!(tiles, vec![(2623, 5718, 14), (2625, 5721, 14)]);
+
+        let loaded = Graph::load(&snapshot).expect("Failed to load graph");
+        let fremont_route = |graph: &Graph| {
+            graph.search_djikstra(&costing_model, WayId(671949014), 0, WayId(980366562), 0)
+        };
+        let from = Coord {
+            x: -122.3126740,
+            y: 47.6153470,
+        };
+        let to = Coord {
+            x: -122.315503,
+            y: 47.6163794,
+        };
+        let capitol_hill_route = |graph: &Graph| {
+            let (from_way, from_distance) = graph.nearest_way(&costing_model, &from).unwrap();
+            let (to_way, to_distance) = graph.nearest_way(&costing_model, &to).unwrap();
+            graph.search_astar(&costing_model, from_way, from_distance, to_way, to_distance)
+        };
+        let route = fremont_route(&graph).expect("Couldn't find a route.");
+        assert_eq!(fremont_route(&loaded), Some(route.clone()));
+        assert!(capitol_hill_route(&graph).is_some());
+        assert_eq!(capitol_hill_route(&loaded), capitol_hill_route(&graph));
+        let (way, _) = graph.nearest_way(&costing_model, &from).unwrap();
+        assert_eq!(loaded.get_polyline(&way), graph.get_polyline(&way));
+
+        // Searching with the model the snapshot was saved with uses the saved costs, where a
+        // freshly ingested graph has to cost each intersection it reaches.
+        let counting_model = |model| CountingModel {
+            model,
+            intersections: AtomicUsize::new(0),
+        };
+        let loaded_model = counting_model(pedestrian_costing_model(1.4));
+        let reloaded = Graph::load(&snapshot).expect("Failed to load graph");
+        assert_eq!(
+            reloaded.search_djikstra(&loaded_model, WayId(671949014), 0, WayId(980366562), 0),
+            Some(route.clone())
+        );
+        assert_eq!(loaded_model.intersections.load(Ordering::Relaxed), 0);
+        let ingested_model = counting_model(pedestrian_costing_model(1.4));
+        assert_eq!(
+            ingest(&tile_data).search_djikstra(
+                &ingested_model,
+                WayId(671949014),
+                0,
+                WayId(980366562),
+                0
+            ),
+            Some(route.clone())
+        );
+        assert!(ingested_model.intersections.load(Ordering::Relaxed) > 0);
+
+        // The loaded graph keeps track of its tiles like one they were ingested into.
+        loaded
+            .remove_tile(2625, 5721, 14)
+            .expect("Failed to remove tile");
+        assert_eq!(fremont_route(&loaded), Some(route));
+        assert_ne!(
+            loaded.nearest_way(&costing_model, &from),
+            graph.nearest_way(&costing_model, &from)
+        );
+
+        // The tags are kept, so the loaded graph can be searched with other models too.
+        let faster_model = pedestrian_costing_model(2.8);
+        assert_eq!(
+            loaded.search_djikstra(&faster_model, WayId(671949014), 0, WayId(980366562), 0),
+            graph.search_djikstra(&faster_model, WayId(671949014), 0, WayId(980366562), 0)
+        );
+        // A model reusing the saved model's name but costing differently doesn't get its costs.
+        let misnamed_model = pedestrian_costing_model(2.8).with_name("pedestrian 1.4 m/s");
+        assert_eq!(
+            reloaded.search_djikstra(&misnamed_model, WayId(671949014), 0, WayId(980366562), 0),
+            graph.search_djikstra(&faster_model, WayId(671949014), 0, WayId(980366562), 0)
+        );
+
+        assert!(Graph::load(&snapshot[..snapshot.len() - 1]).is_err());
+        let mut other_version = snapshot.clone();
+        other_version[8] = other_version[8].wrapping_add(1);
+        assert!(Graph::load(&other_version).is_err());
+        assert!(Graph::read_snapshot_header(&other_version).is_err());
+    }
+}
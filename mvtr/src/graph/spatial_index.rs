@@ -0,0 +1,309 @@
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap, HashSet},
+    f64::consts::FRAC_PI_2,
+};
+
+use geo::{Haversine, Line, LineString, Point};
+use rstar::{RTree, primitives::GeomWithData};
+
+use crate::costing::CostingModel;
+
+use super::{Costing, Graph, WayId};
+
+type IndexedSegment = GeomWithData<Line, WayId>;
+
+/// An R-tree of the segments of every way's geometry, in degrees, so ways near a point can be
+/// found without measuring the distance to every way in the graph.
+#[derive(Default)]
+pub(super) struct WayIndex {
+    tree: RTree<IndexedSegment>,
+    /// The segments of each way, to find them again when the way is removed.
+    segments: HashMap<WayId, Vec<IndexedSegment>>,
+}
+
+impl WayIndex {
+    /// Indexes a way's geometry, replacing whatever was indexed for it before.
+    pub(super) fn insert_way(&mut self, way: WayId, polyline: &LineString) {
+        self.remove_way(&way);
+        let segments = way_segments(way, polyline);
+        for segment in &segments {
+            self.tree.insert(*segment);
+        }
+        self.segments.insert(way, segments);
+    }
+
+    /// Like `insert_way` for many ways at once. An empty index, such as a new graph's, is built in
+    /// one go, which is much faster than inserting segments one at a time.
+    pub(super) fn insert_ways(&mut self, ways: Vec<(WayId, LineString)>) {
+        if !self.segments.is_empty() {
+            for (way, polyline) in ways {
+                self.insert_way(way, &polyline);
+            }
+            return;
+        }
+        self.segments = ways
+            .iter()
+            .map(|(way, polyline)| (*way, way_segments(*way, polyline)))
+            .collect();
+        self.tree = RTree::bulk_load(self.segments.values().flatten().copied().collect());
+    }
+
+    pub(super) fn remove_way(&mut self, way: &WayId) {
+        for segment in self.segments.remove(way).into_iter().flatten() {
+            self.tree.remove(&segment);
+        }
+    }
+
+    pub(super) fn clear(&mut self) {
+        *self = WayIndex::default();
+    }
+}
+
+/// The segments of a way's geometry, or a single point if it has only one coordinate.
+fn way_segments(way: WayId, polyline: &LineString) -> Vec<IndexedSegment> {
+    let mut segments: Vec<IndexedSegment> = polyline
+        .lines()
+        .map(|line| GeomWithData::new(line, way))
+        .collect();
+    if segments.is_empty()
+        && let Some(coord) = polyline.0.first()
+    {
+        segments.push(GeomWithData::new(Line::new(*coord, *coord), way));
+    }
+    segments
+}
+
+/// A way found near a point, ordered nearest first and then by id.
+struct Nearby {
+    way: WayId,
+    distance_along_way_mm: i32,
+    distance_meters: f64,
+}
+
+impl PartialEq for Nearby {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Nearby {}
+
+impl PartialOrd for Nearby {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Nearby {
+    // Reversed, so the nearest way is at the top of a `BinaryHeap`.
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .distance_meters
+            .total_cmp(&self.distance_meters)
+            .then_with(|| other.way.cmp(&self.way))
+    }
+}
+
+/// The least great-circle distance, in meters, there can be between `point` and any point
+/// `planar_degrees` away from it in degrees of longitude and latitude.
+fn haversine_lower_bound(point: &Point, planar_degrees: f64) -> f64 {
+    let max_latitude = (point.y().abs() + planar_degrees).min(90.0).to_radians();
+    let half_angle = (planar_degrees.to_radians() / 2.0).min(FRAC_PI_2);
+    2.0 * Haversine.radius() * max_latitude.cos() * half_angle.sin()
+}
+
+impl Graph {
+    /// Up to `k` traversable ways nearest to a point, nearest first, each with the distance along
+    /// the way closest to the point and how far away that is in meters.
+    pub fn nearest_ways(
+        &self,
+        costing_model: &dyn CostingModel,
+        coord: &geo::Coord,
+        k: usize,
+    ) -> anyhow::Result<Vec<(WayId, i32, f64)>> {
+        let costing = self.costing(costing_model)?;
+        let mut ways = Vec::new();
+        self.ways_by_distance(
+            &costing,
+            coord,
+            |way, distance_along_way_mm, distance_meters| {
+                if ways.len() >= k {
+                    return false;
+                }
+                ways.push((way, distance_along_way_mm, distance_meters));
+                true
+            },
+        )?;
+        Ok(ways)
+    }
+
+    /// Every traversable way passing within `radius_meters` of a point, nearest first, each with
+    /// the distance along the way closest to the point and how far away that is in meters.
+    pub fn ways_within_radius(
+        &self,
+        costing_model: &dyn CostingModel,
+        coord: &geo::Coord,
+        radius_meters: f64,
+    ) -> anyhow::Result<Vec<(WayId, i32, f64)>> {
+        let costing = self.costing(costing_model)?;
+        let mut ways = Vec::new();
+        self.ways_by_distance(
+            &costing,
+            coord,
+            |way, distance_along_way_mm, distance_meters| {
+                if distance_meters > radius_meters {
+                    return false;
+                }
+                ways.push((way, distance_along_way_mm, distance_meters));
+                true
+            },
+        )?;
+        Ok(ways)
+    }
+
+    /// Visits traversable ways in order of distance from a point, as `project_onto_way` measures
+    /// it, until `visit` returns false. Ways are measured as the index reaches them by planar
+    /// distance, and each is visited once no way reached later could be any nearer.
+    fn ways_by_distance(
+        &self,
+        costing: &Costing,
+        coord: &geo::Coord,
+        mut visit: impl FnMut(WayId, i32, f64) -> bool,
+    ) -> anyhow::Result<()> {
+        let point = Point::new(coord.x, coord.y);
+        let index = self
+            .way_index
+            .lock()
+            .map_err(|err| anyhow::anyhow!("Failed to lock mutex: {}", err))?;
+        let mut measured: HashSet<WayId> = HashSet::new();
+        let mut pending: BinaryHeap<Nearby> = BinaryHeap::new();
+        for (segment, distance_2) in index.tree.nearest_neighbor_iter_with_distance_2(&point) {
+            let bound = haversine_lower_bound(&point, distance_2.sqrt());
+            while let Some(nearby) = pending.peek()
+                && nearby.distance_meters < bound
+            {
+                let nearby = pending.pop().unwrap();
+                if !visit(
+                    nearby.way,
+                    nearby.distance_along_way_mm,
+                    nearby.distance_meters,
+                ) {
+                    return Ok(());
+                }
+            }
+            let way = segment.data;
+            if measured.insert(way)
+                && let Some((distance_meters, distance_along_way_mm)) =
+                    self.project_onto_way(costing, &way, &point)
+            {
+                pending.push(Nearby {
+                    way,
+                    distance_along_way_mm,
+                    distance_meters,
+                });
+            }
+        }
+        while let Some(nearby) = pending.pop() {
+            if !visit(
+                nearby.way,
+                nearby.distance_along_way_mm,
+                nearby.distance_meters,
+            ) {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use geo::{BoundingRect, Coord, Point};
+
+    use crate::costing::pedestrian::pedestrian_costing_model;
+
+    use super::super::{Costing, Graph, WayId};
+
+    /// The nearest way to a point by measuring the distance to every way in the graph.
+    fn nearest_way_scan(
+        graph: &Graph,
+        costing: &Costing,
+        coord: &Coord,
+    ) -> Option<(WayId, i32, f64)> {
+        let point = Point::new(coord.x, coord.y);
+        let mut best: Option<(WayId, i32, f64)> = None;
+        for (way, _) in graph.geometry_read.read().unwrap().iter() {
+            if let Some((distance_meters, distance_along_way_mm)) =
+                graph.project_onto_way(costing, way, &point)
+                && best.is_none_or(|(_, _, best_meters)| distance_meters < best_meters)
+            {
+                best = Some((*way, distance_along_way_mm, distance_meters));
+            }
+        }
+        best
+    }
+
+    #[test]
+    fn spatial_index_matches_scan() {
+        let costing_model = pedestrian_costing_model(1.4);
+        let graph = Graph::new();
+        graph
+            .ingest_tile(
+                2625,
+                5721,
+                14,
+                include_bytes!("../../testdata/tile.pbf").to_vec(),
+                include_bytes!("../../testdata/tile.pbf").to_vec(),
+            )
+            .expect("Failed to ingest tile");
+        let bounds = graph
+            .get_polyline(
+                &graph
+                    .nearest_way(
+                        &costing_model,
+                        &Coord {
+                            x: -122.3126740,
+                            y: 47.6153470,
+                        },
+                    )
+                    .unwrap()
+                    .0,
+            )
+            .unwrap()
+            .bounding_rect()
+            .unwrap();
+        let center = bounds.center();
+        let costing = graph.costing(&costing_model).unwrap();
+
+        // A grid reaching well past the tile's edges.
+        for x in -10..=10 {
+            for y in -10..=10 {
+                let coord = Coord {
+                    x: center.x + x as f64 * 0.0007,
+                    y: center.y + y as f64 * 0.0005,
+                };
+                let (way, distance_along_way_mm, distance_meters) =
+                    nearest_way_scan(&graph, &costing, &coord).unwrap();
+                let nearest = graph.nearest_ways(&costing_model, &coord, 3).unwrap();
+                assert_eq!(nearest.len(), 3);
+                assert_eq!(nearest[0].2, distance_meters);
+                if nearest[1].2 != distance_meters {
+                    assert_eq!(nearest[0].0, way);
+                    assert_eq!(nearest[0].1, distance_along_way_mm);
+                }
+                assert!(nearest.windows(2).all(|pair| pair[0].2 <= pair[1].2));
+
+                let within = graph
+                    .ways_within_radius(&costing_model, &coord, nearest[2].2)
+                    .unwrap();
+                assert!(within.len() >= 3);
+                assert_eq!(within[..3], nearest[..]);
+                assert!(within.iter().all(|(_, _, meters)| *meters <= nearest[2].2));
+            }
+        }
+
+        graph.clear().expect("Failed to clear graph");
+        assert_eq!(graph.nearest_way(&costing_model, &center), None);
+    }
+}
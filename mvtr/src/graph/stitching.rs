@@ -0,0 +1,421 @@
+use std::mem::ManuallyDrop;
+
+use evmap::ShallowCopy;
+use geo::{
+    Closest, ClosestPoint, Distance, Haversine, Length, Line, LineLocatePoint, LineString, Point,
+};
+
+use super::TileCoordinates;
+
+/// How far an intersection, or the end of what's been stitched so far, may be from a fragment and
+/// still count as lying on it.
+const STITCH_TOLERANCE_METERS: f64 = 1.0;
+
+/// The piece of a way's geometry a single tile holds, clipped to the tile and its buffer.
+#[derive(Debug, Clone)]
+pub(super) struct WayFragment {
+    /// How far along the whole way the fragment starts, as the intersections in its tile place it.
+    /// `None` if none of them are on it.
+    pub(super) start_meters: Option<f64>,
+    pub(super) polyline: Vec<TileCoordinates>,
+}
+
+impl WayFragment {
+    /// Places a fragment along its way from `anchors`, the locations of the way's intersections in
+    /// the same tile and their `distance_along_way` in meters.
+    pub(super) fn new(
+        polyline: Vec<TileCoordinates>,
+        anchors: &[(geo::Coord, f64)],
+    ) -> WayFragment {
+        let line_string = to_line_string(&polyline);
+        let mut starts: Vec<f64> = anchors
+            .iter()
+            .filter_map(|(coord, distance_along_way)| {
+                let (along, off) = locate(&line_string, &Point(*coord))?;
+                (off <= STITCH_TOLERANCE_METERS).then(|| (distance_along_way - along).max(0.0))
+            })
+            .collect();
+        starts.sort_by(f64::total_cmp);
+        // The intersections are measured along the unclipped way, so they mostly agree; the median
+        // keeps one that lands on the wrong lap of a looping way from skewing the result.
+        let start_meters = (!starts.is_empty()).then(|| starts[(starts.len() - 1) / 2]);
+        WayFragment {
+            start_meters,
+            polyline,
+        }
+    }
+}
+
+/// A way's geometry, stitched together from the fragments of every loaded tile it passes through.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(super) struct WayGeometry {
+    pub(super) placement: WayPlacement,
+    pub(super) polyline: Vec<TileCoordinates>,
+}
+
+impl WayGeometry {
+    pub(super) fn line_string(&self) -> LineString {
+        to_line_string(&self.polyline)
+    }
+}
+
+impl ShallowCopy for WayGeometry {
+    unsafe fn shallow_copy(&self) -> ManuallyDrop<Self> {
+        ManuallyDrop::new(WayGeometry {
+            placement: WayPlacement {
+                anchors: ManuallyDrop::into_inner(unsafe { self.placement.anchors.shallow_copy() }),
+            },
+            polyline: ManuallyDrop::into_inner(unsafe { self.polyline.shallow_copy() }),
+        })
+    }
+}
+
+/// Where a way's stitched polyline lies along the whole way. Past a gap bridged with a straight
+/// line, the polyline carries on from where the next fragment was placed, however long the bridge
+/// is.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(super) struct WayPlacement {
+    /// Distances along the polyline and the matching distances along the way, in millimeters and
+    /// in order, with distances between them interpolated. The first is the start of the polyline,
+    /// which is more than zero along the way while the tiles holding the way's start aren't
+    /// loaded.
+    anchors: Vec<(i32, i32)>,
+}
+
+impl WayPlacement {
+    /// The placement of a polyline with no gaps, starting `start_mm` along its way.
+    fn starting_at(start_mm: i32) -> WayPlacement {
+        WayPlacement {
+            anchors: vec![(0, start_mm)],
+        }
+    }
+
+    pub(super) fn start_mm(&self) -> i32 {
+        self.anchors[0].1
+    }
+
+    /// How far along the way the point `polyline_meters` along the polyline is.
+    pub(super) fn way_mm(&self, polyline_meters: f64) -> i32 {
+        interpolate(
+            &self.anchors,
+            polyline_meters * 1000.0,
+            |(polyline, way)| (*polyline, *way),
+        ) as i32
+    }
+
+    /// How far along the polyline, in meters, the point `way_mm` along the way is, or its start if
+    /// the point is before it.
+    pub(super) fn polyline_meters(&self, way_mm: i32) -> f64 {
+        interpolate(&self.anchors, way_mm as f64, |(polyline, way)| {
+            (*way, *polyline)
+        })
+        .max(0.0)
+            / 1000.0
+    }
+}
+
+/// Maps `x` between the two distances of each anchor, as picked by `pair`, interpolating between
+/// the anchors either side of it and carrying on evenly beyond the first and last.
+fn interpolate(anchors: &[(i32, i32)], x: f64, pair: impl Fn(&(i32, i32)) -> (i32, i32)) -> f64 {
+    let next = anchors.partition_point(|anchor| pair(anchor).0 as f64 <= x);
+    let (from, to) = pair(&anchors[next.saturating_sub(1)]);
+    let (from, to) = (from as f64, to as f64);
+    match anchors.get(next).map(&pair) {
+        Some((next_from, next_to)) if next > 0 && next_from as f64 > from => {
+            to + (x - from) * (next_to as f64 - to) / (next_from as f64 - from)
+        }
+        _ => to + (x - from),
+    }
+}
+
+fn to_line_string(polyline: &[TileCoordinates]) -> LineString {
+    polyline.iter().map(|coords| coords.to_lat_lng()).collect()
+}
+
+/// How far along a line the point on it closest to `point` is, and how far from `point` that is,
+/// both in meters.
+fn locate(line_string: &LineString, point: &Point) -> Option<(f64, f64)> {
+    let closest = match line_string.closest_point(point) {
+        Closest::Intersection(closest) | Closest::SinglePoint(closest) => closest,
+        Closest::Indeterminate => return None,
+    };
+    let fraction = line_string.line_locate_point(&closest).unwrap_or(0.0);
+    Some((
+        fraction * Haversine.length(line_string),
+        Haversine.distance(closest, *point),
+    ))
+}
+
+/// The index of the segment of a line closest to `point`, and how far from `point` it is in meters.
+fn closest_segment(line_string: &LineString, point: &Point) -> Option<(usize, f64)> {
+    line_string
+        .lines()
+        .enumerate()
+        .filter_map(
+            |(index, line): (usize, Line)| match line.closest_point(point) {
+                Closest::Intersection(closest) | Closest::SinglePoint(closest) => {
+                    Some((index, Haversine.distance(closest, *point)))
+                }
+                Closest::Indeterminate => None,
+            },
+        )
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+}
+
+/// Merges the fragments of a way into one geometry, ordered by how far along the way each starts.
+/// Fragments no intersection places are put after whichever placed fragment they carry on from,
+/// and where tiles between fragments aren't loaded the gap is bridged with a straight line, with
+/// the fragment after it kept where it was placed along the way.
+pub(super) fn stitch<'a>(
+    fragments: impl IntoIterator<Item = &'a WayFragment>,
+) -> Option<WayGeometry> {
+    let fragments: Vec<(&WayFragment, LineString, f64)> = fragments
+        .into_iter()
+        .filter(|fragment| !fragment.polyline.is_empty())
+        .map(|fragment| {
+            let line_string = to_line_string(&fragment.polyline);
+            let length = Haversine.length(&line_string);
+            (fragment, line_string, length)
+        })
+        .collect();
+
+    let mut starts: Vec<Option<f64>> = fragments
+        .iter()
+        .map(|(fragment, _, _)| fragment.start_meters)
+        .collect();
+    if starts.iter().all(Option::is_none) {
+        // Nothing says where the way starts, so the longest fragment is taken to.
+        let (longest, _) = fragments
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.2.total_cmp(&b.1.2))?;
+        starts[longest] = Some(0.0);
+    }
+    loop {
+        let mut placed_any = false;
+        for index in 0..fragments.len() {
+            if starts[index].is_some() {
+                continue;
+            }
+            let (_, line_string, length) = &fragments[index];
+            let first = Point(line_string.0[0]);
+            let last = Point(line_string.0[line_string.0.len() - 1]);
+            let start = fragments
+                .iter()
+                .zip(&starts)
+                .find_map(|((_, other, _), start)| {
+                    let start = (*start)?;
+                    if let Some((along, off)) = locate(other, &first)
+                        && off <= STITCH_TOLERANCE_METERS
+                    {
+                        return Some(start + along);
+                    }
+                    let (along, off) = locate(other, &last)?;
+                    (off <= STITCH_TOLERANCE_METERS).then(|| (start + along - length).max(0.0))
+                });
+            placed_any |= start.is_some();
+            starts[index] = start;
+        }
+        if !placed_any {
+            break;
+        }
+    }
+
+    let mut placed: Vec<(f64, usize)> = starts
+        .iter()
+        .enumerate()
+        .filter_map(|(index, start)| Some(((*start)?, index)))
+        .collect();
+    if placed.len() < fragments.len() {
+        tracing::warn!(
+            "Dropping {} way fragments that couldn't be placed along the way",
+            fragments.len() - placed.len()
+        );
+    }
+    // Longer fragments first where they start at the same place, so shorter ones are skipped.
+    placed.sort_by(|a, b| {
+        a.0.total_cmp(&b.0)
+            .then_with(|| fragments[b.1].2.total_cmp(&fragments[a.1].2))
+    });
+
+    let (start_meters, first) = *placed.first()?;
+    let mut polyline = fragments[first].0.polyline.clone();
+    let mut placement = WayPlacement::starting_at((start_meters * 1000.0) as i32);
+    let mut end_meters = start_meters + fragments[first].2;
+    for (start, index) in placed.into_iter().skip(1) {
+        let (fragment, line_string, length) = &fragments[index];
+        if start + length <= end_meters + STITCH_TOLERANCE_METERS {
+            continue;
+        }
+        let end = Point(polyline[polyline.len() - 1].to_lat_lng());
+        let skip = match closest_segment(line_string, &end) {
+            // Tiles overlap by their buffer, so carry on from where the fragment passes the end
+            // of what's been stitched so far.
+            Some((segment, off)) if off <= STITCH_TOLERANCE_METERS => segment + 1,
+            _ => {
+                // The bridge is straight, so it's shorter than the way it stands in for.
+                let gap_start = Haversine.length(&to_line_string(&polyline));
+                let bridge = Haversine.distance(end, Point(line_string.0[0]));
+                let gap_start_mm = placement.way_mm(gap_start);
+                placement.anchors.extend([
+                    ((gap_start * 1000.0) as i32, gap_start_mm),
+                    (
+                        ((gap_start + bridge) * 1000.0) as i32,
+                        gap_start_mm.max((start * 1000.0) as i32),
+                    ),
+                ]);
+                0
+            }
+        };
+        polyline.extend(
+            fragment.polyline[skip..]
+                .iter()
+                .filter(|coords| coords.to_lat_lng() != end.0),
+        );
+        end_meters = start + length;
+    }
+    Some(WayGeometry {
+        placement,
+        polyline,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use geo::{Distance, Haversine, Point};
+
+    use super::super::{Graph, TileCoordinates};
+    use super::{WayFragment, WayGeometry, stitch};
+
+    fn ingest_capitol_hill() -> Graph {
+        let graph = Graph::new();
+        graph
+            .ingest_tile(
+                2625,
+                5721,
+                14,
+                include_bytes!("../../testdata/tile.pbf").to_vec(),
+                include_bytes!("../../testdata/tile.pbf").to_vec(),
+            )
+            .expect("Failed to ingest tile");
+        graph
+    }
+
+    #[test]
+    fn geometry_matches_intersection_distances() {
+        let graph = ingest_capitol_hill();
+        let mut errors: Vec<f64> = Vec::new();
+        for (_, transitions) in graph.transitions_read.read().unwrap().iter() {
+            for (transition, _) in transitions {
+                if transition.from_way_id == transition.to_way_id {
+                    continue;
+                }
+                let (Some(from), Some(to)) = (
+                    graph
+                        .point_along_way(&transition.from_way_id, transition.distance_along_way_mm),
+                    graph.point_along_way(
+                        &transition.to_way_id,
+                        transition.transition_to_distance_along_way_mm,
+                    ),
+                ) else {
+                    continue;
+                };
+                errors.push(Haversine.distance(from, to));
+            }
+        }
+        // Distances along ways are measured on the unclipped geometry in the database, which the
+        // tiles only approximate.
+        assert!(!errors.is_empty());
+        assert!(errors.iter().all(|error| *error < 10.0));
+        assert!(errors.iter().filter(|error| **error > 1.0).count() * 50 < errors.len());
+    }
+
+    /// The longest way in the tile that starts in it, to split into fragments.
+    fn long_way() -> WayGeometry {
+        ingest_capitol_hill()
+            .geometry_read
+            .read()
+            .unwrap()
+            .iter()
+            .filter_map(|(_, geometries)| geometries.get_one().cloned())
+            .filter(|geometry| geometry.placement.start_mm() == 0 && geometry.polyline.len() >= 6)
+            .max_by_key(|geometry| geometry.polyline.len())
+            .expect("No way long enough to split.")
+    }
+
+    /// How far along a polyline its vertex at `index` is, in meters.
+    fn vertex_meters(polyline: &[TileCoordinates], index: usize) -> f64 {
+        polyline[..=index]
+            .windows(2)
+            .map(|pair| {
+                Haversine.distance(Point(pair[0].to_lat_lng()), Point(pair[1].to_lat_lng()))
+            })
+            .sum()
+    }
+
+    #[test]
+    fn stitch_overlapping_fragments() {
+        let geometry = long_way();
+        let polyline = &geometry.polyline;
+        let vertex_meters = |index: usize| vertex_meters(polyline, index);
+
+        // Overlapping by a segment, as fragments of adjacent tiles do by their buffers.
+        let head = WayFragment {
+            start_meters: Some(0.0),
+            polyline: polyline[..4].to_vec(),
+        };
+        let inside_head = WayFragment {
+            start_meters: Some(vertex_meters(1)),
+            polyline: polyline[1..3].to_vec(),
+        };
+        let tail = WayFragment {
+            start_meters: None,
+            polyline: polyline[2..].to_vec(),
+        };
+        assert_eq!(stitch([&tail, &inside_head, &head]), Some(geometry.clone()));
+
+        // Without the head, the tail starts as far along the way as its intersections place it.
+        let placed_tail = WayFragment {
+            start_meters: Some(vertex_meters(2)),
+            ..tail.clone()
+        };
+        let stitched = stitch([&placed_tail]).unwrap();
+        assert_eq!(stitched.polyline, polyline[2..]);
+        assert_eq!(
+            stitched.placement.start_mm(),
+            (vertex_meters(2) * 1000.0) as i32
+        );
+    }
+
+    #[test]
+    fn stitch_fragments_across_a_gap() {
+        let geometry = long_way();
+        let polyline = &geometry.polyline;
+
+        // With the tiles between them missing, the tail is bridged to from the head but stays
+        // where it was placed, which here is further along than the bridge reaches.
+        let head = WayFragment {
+            start_meters: Some(0.0),
+            polyline: polyline[..3].to_vec(),
+        };
+        let tail = WayFragment {
+            start_meters: Some(vertex_meters(polyline, 4) + 100.0),
+            polyline: polyline[4..].to_vec(),
+        };
+        let stitched = stitch([&tail, &head]).unwrap();
+        assert_eq!(stitched.polyline, [&polyline[..3], &polyline[4..]].concat());
+        for (index, expected) in [
+            (1, vertex_meters(polyline, 1)),
+            (3, vertex_meters(polyline, 4) + 100.0),
+            (
+                stitched.polyline.len() - 1,
+                vertex_meters(polyline, polyline.len() - 1) + 100.0,
+            ),
+        ] {
+            let along = vertex_meters(&stitched.polyline, index);
+            let way_mm = stitched.placement.way_mm(along);
+            assert!((way_mm - (expected * 1000.0) as i32).abs() <= 1);
+            assert!((stitched.placement.polyline_meters(way_mm) - along).abs() < 0.01);
+        }
+    }
+}
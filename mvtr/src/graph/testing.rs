@@ -0,0 +1,132 @@
+//! Fixtures the costing models' tests share: tags built from pairs or read from the Capitol Hill
+//! test tile, and routes across it along with the tags of every way they travel.
+
+use std::{collections::HashMap, sync::Arc};
+
+use geo::Coord;
+
+use crate::costing::{CostingModel, Tags, units::ElapsedTime};
+
+use super::{Frontier, Graph, SearchResult, WayId};
+
+/// Where routes across Capitol Hill start, on a sidewalk.
+pub(crate) const CAPITOL_HILL_FROM: Coord = Coord {
+    x: -122.3126740,
+    y: 47.6153470,
+};
+/// A sidewalk a few blocks north-west of `CAPITOL_HILL_FROM`.
+pub(crate) const CAPITOL_HILL_TO: Coord = Coord {
+    x: -122.315503,
+    y: 47.6163794,
+};
+/// A residential street a few blocks west of `CAPITOL_HILL_FROM`, for models that can't use
+/// sidewalks.
+pub(crate) const CAPITOL_HILL_TO_BY_ROAD: Coord = Coord {
+    x: -122.3160,
+    y: 47.6140,
+};
+/// Either side of a flight of steps: walking routes between them take the steps, and routes that
+/// can't have to go a couple of hundred meters around.
+pub(crate) const CAPITOL_HILL_EAST_OF_STEPS: Coord = Coord {
+    x: -122.3198748,
+    y: 47.6167422,
+};
+pub(crate) const CAPITOL_HILL_WEST_OF_STEPS: Coord = Coord {
+    x: -122.3206266,
+    y: 47.6166696,
+};
+
+pub(crate) fn tags(pairs: &[(&str, &str)]) -> Tags {
+    Tags::from_hashmap(
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect::<HashMap<String, String>>(),
+    )
+}
+
+/// A graph of the Capitol Hill test tile.
+pub(crate) fn capitol_hill() -> Graph {
+    let graph = Graph::new();
+    graph
+        .ingest_tile(
+            2625,
+            5721,
+            14,
+            include_bytes!("../../testdata/tile.pbf").to_vec(),
+            include_bytes!("../../testdata/tile.pbf").to_vec(),
+        )
+        .expect("Failed to ingest tile");
+    graph
+}
+
+/// The tags `way` has in `graph`, as ingested from its tile.
+pub(crate) fn way_tags(graph: &Graph, way: u64) -> Arc<Tags> {
+    graph
+        .way_tags_read
+        .get_one(&WayId(way))
+        .map(|tags| tags.clone())
+        .expect("Way isn't loaded.")
+}
+
+/// A route, and the tags of each way it travels along, in order.
+pub(crate) struct TestRoute {
+    pub(crate) result: SearchResult,
+    pub(crate) way_tags: Vec<Arc<Tags>>,
+}
+
+impl TestRoute {
+    /// Whether any way the route travels along has `key` set to one of `values`.
+    pub(crate) fn uses(&self, key: &str, values: &[&str]) -> bool {
+        self.way_tags.iter().any(|tags| tags.tag_in(key, values))
+    }
+}
+
+/// The route Dijkstra finds between the ways nearest `from` and `to`, checked to be the one A*
+/// finds too.
+pub(crate) fn route(
+    graph: &Graph,
+    costing_model: &dyn CostingModel,
+    from: &Coord,
+    to: &Coord,
+) -> TestRoute {
+    let (from_way, from_distance) = graph
+        .nearest_way(costing_model, from)
+        .expect("Couldn't snap the start.");
+    let (to_way, to_distance) = graph
+        .nearest_way(costing_model, to)
+        .expect("Couldn't snap the destination.");
+    let costing = graph
+        .costing(costing_model)
+        .expect("Couldn't cost the graph.");
+    let states = graph
+        .search_djikstra_inner(
+            &costing,
+            from_way,
+            from_distance,
+            to_way,
+            to_distance,
+            Frontier::new(|_| ElapsedTime::zero()),
+        )
+        .expect("Couldn't find a route.");
+    let result = graph
+        .build_search_result(&states)
+        .expect("Couldn't build the route.");
+    assert_eq!(
+        graph.search_astar(costing_model, from_way, from_distance, to_way, to_distance),
+        Some(result.clone())
+    );
+    let mut ways: Vec<WayId> = states.windows(2).map(|window| window[0].node.way).collect();
+    ways.dedup();
+    let way_tags = ways
+        .iter()
+        .map(|way| {
+            graph
+                .way_tags_read
+                .get_one(way)
+                .map(|tags| tags.clone())
+                .expect("Route travels along a way that isn't loaded.")
+        })
+        .collect();
+    TestRoute { result, way_tags }
+}
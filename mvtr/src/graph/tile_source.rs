@@ -0,0 +1,550 @@
+use std::{
+    collections::{HashMap, HashSet},
+    f64::consts::PI,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use super::{Graph, WayId, tiles::TileId};
+
+/// Ways within this fraction of a tile of its edge are treated as reaching the neighbouring tile
+/// too, in case they continue across the edge.
+const TILE_EDGE_MARGIN: f64 = 1.0 / 256.0;
+
+/// The bytes of a tile's roads and intersections layers, as passed to `Graph::ingest_tile`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TileBytes {
+    roads: Vec<u8>,
+    intersections: Vec<u8>,
+}
+
+impl TileBytes {
+    pub fn new(roads: Vec<u8>, intersections: Vec<u8>) -> TileBytes {
+        TileBytes {
+            roads,
+            intersections,
+        }
+    }
+}
+
+/// Supplies tiles to a graph as searches reach them.
+pub trait TileSource {
+    /// The tile at `(x, y, z)`, or `None` if the source doesn't have it.
+    fn fetch_tile(&self, x: u32, y: u32, z: u32) -> anyhow::Result<Option<TileBytes>>;
+}
+
+/// A `TileSource` that has to wait for its tiles, such as one fetching them over the network.
+/// Used through `Graph::search_with_async_tile_source`.
+pub trait AsyncTileSource {
+    /// The tile at `(x, y, z)`, or `None` if the source doesn't have it.
+    fn fetch_tile(
+        &self,
+        x: u32,
+        y: u32,
+        z: u32,
+    ) -> impl Future<Output = anyhow::Result<Option<TileBytes>>>;
+}
+
+impl TileSource for HashMap<(u32, u32, u32), TileBytes> {
+    fn fetch_tile(&self, x: u32, y: u32, z: u32) -> anyhow::Result<Option<TileBytes>> {
+        Ok(self.get(&(x, y, z)).cloned())
+    }
+}
+
+/// Reads tiles from files, with paths made by replacing `{x}`, `{y}` and `{z}` in a template for
+/// each layer. A tile is missing if its roads file is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileTileSource {
+    roads_template: String,
+    intersections_template: String,
+}
+
+impl FileTileSource {
+    pub fn new(roads_template: &str, intersections_template: &str) -> FileTileSource {
+        FileTileSource {
+            roads_template: roads_template.to_string(),
+            intersections_template: intersections_template.to_string(),
+        }
+    }
+
+    fn path(template: &str, x: u32, y: u32, z: u32) -> PathBuf {
+        PathBuf::from(
+            template
+                .replace("{x}", &x.to_string())
+                .replace("{y}", &y.to_string())
+                .replace("{z}", &z.to_string()),
+        )
+    }
+}
+
+impl TileSource for FileTileSource {
+    fn fetch_tile(&self, x: u32, y: u32, z: u32) -> anyhow::Result<Option<TileBytes>> {
+        let roads_path = Self::path(&self.roads_template, x, y, z);
+        if !roads_path.exists() {
+            return Ok(None);
+        }
+        let roads = std::fs::read(&roads_path).map_err(|err| {
+            anyhow::anyhow!("Could not read tile {}: {}", roads_path.display(), err)
+        })?;
+        let intersections_path = Self::path(&self.intersections_template, x, y, z);
+        let intersections = std::fs::read(&intersections_path).map_err(|err| {
+            anyhow::anyhow!(
+                "Could not read tile {}: {}",
+                intersections_path.display(),
+                err
+            )
+        })?;
+        Ok(Some(TileBytes::new(roads, intersections)))
+    }
+}
+
+type LoadTileFn = Arc<dyn Fn(&Graph, TileId) -> anyhow::Result<()> + Send + Sync>;
+
+/// What a graph does with tiles a search reaches that haven't been ingested.
+pub(super) struct TileLoader {
+    zoom: u32,
+    /// Called without the loader locked, so other searches carry on while a tile is fetched and
+    /// ingested, and may be called for different tiles at once.
+    load: LoadTileFn,
+    /// Tiles already handed to `load`, or about to be, whether or not there turned out to be
+    /// anything there. Searches reaching a tile that's still being loaded carry on without it.
+    requested: HashSet<TileId>,
+    /// Ways whose tiles have all been requested, since the last tile was loaded.
+    checked_ways: HashSet<WayId>,
+}
+
+impl TileLoader {
+    fn new(
+        zoom: u32,
+        load: impl Fn(&Graph, TileId) -> anyhow::Result<()> + Send + Sync + 'static,
+    ) -> TileLoader {
+        TileLoader {
+            zoom,
+            load: Arc::new(load),
+            requested: HashSet::new(),
+            checked_ways: HashSet::new(),
+        }
+    }
+
+    /// Marks tiles as requested, returning the ones that need handing to `load`.
+    fn request(&mut self, graph: &Graph, tiles: impl IntoIterator<Item = TileId>) -> PendingTiles {
+        let loaded = graph.tiles.lock().ok();
+        let tiles = tiles
+            .into_iter()
+            .filter(|tile| {
+                self.requested.insert(*tile)
+                    && !loaded
+                        .as_ref()
+                        .is_some_and(|loaded| loaded.contains_key(tile))
+            })
+            .collect();
+        PendingTiles {
+            load: self.load.clone(),
+            tiles,
+        }
+    }
+}
+
+/// Tiles a `TileLoader` has marked as requested but not loaded yet.
+struct PendingTiles {
+    load: LoadTileFn,
+    tiles: Vec<TileId>,
+}
+
+/// Where a coordinate is in the grid of tiles at `zoom`, in fractions of a tile.
+fn tile_position(coord: &geo::Coord, zoom: u32) -> (f64, f64) {
+    let n = 2f64.powi(zoom as i32);
+    let x = (coord.x + 180.0) / 360.0 * n;
+    let y = (1.0 - coord.y.to_radians().tan().asinh() / PI) / 2.0 * n;
+    (x, y)
+}
+
+/// The tiles at `zoom` a coordinate is in, or close enough to the edge of.
+fn tiles_near(coord: &geo::Coord, zoom: u32) -> Vec<TileId> {
+    let (x, y) = tile_position(coord, zoom);
+    let max = 2u32.pow(zoom) - 1;
+    let range = |position: f64| {
+        let low = ((position - TILE_EDGE_MARGIN).floor().max(0.0) as u32).min(max);
+        let high = ((position + TILE_EDGE_MARGIN).floor().max(0.0) as u32).min(max);
+        low..=high
+    };
+    range(x)
+        .flat_map(|x| range(y).map(move |y| TileId { x, y, z: zoom }))
+        .collect()
+}
+
+impl Graph {
+    /// Loads tiles from `source` as searches reach them. Tiles are requested at `zoom`.
+    pub fn set_tile_source<S>(&self, zoom: u32, source: S) -> anyhow::Result<()>
+    where
+        S: TileSource + Send + Sync + 'static,
+    {
+        self.replace_tile_loader(Some(TileLoader::new(zoom, move |graph, tile| {
+            if let Some(bytes) = source.fetch_tile(tile.x, tile.y, tile.z)? {
+                graph.ingest_tile(tile.x, tile.y, tile.z, bytes.roads, bytes.intersections)?;
+            }
+            Ok(())
+        })))?;
+        Ok(())
+    }
+
+    /// Stops loading tiles as searches reach them.
+    pub fn clear_tile_source(&self) -> anyhow::Result<()> {
+        self.replace_tile_loader(None)?;
+        Ok(())
+    }
+
+    /// Loads the tile containing a point from the tile source, if it isn't loaded already, so
+    /// that a search can be started from it.
+    pub fn load_tile_at(&self, coord: &geo::Coord) -> anyhow::Result<()> {
+        let pending = self
+            .tile_loader
+            .lock()
+            .map_err(|err| anyhow::anyhow!("Failed to lock mutex: {}", err))?
+            .as_mut()
+            .map(|loader| loader.request(self, tiles_near(coord, loader.zoom)));
+        if let Some(pending) = pending {
+            self.load_pending(pending);
+        }
+        Ok(())
+    }
+
+    /// Runs `search` with tiles loaded from an asynchronous source as it reaches them. Searches
+    /// can't wait for tiles partway through, so `search` is run over again with every tile it
+    /// asked for loaded, until it runs without reaching any new ones.
+    pub async fn search_with_async_tile_source<S, R>(
+        &self,
+        zoom: u32,
+        source: &S,
+        search: impl Fn(&Graph) -> R,
+    ) -> anyhow::Result<R>
+    where
+        S: AsyncTileSource,
+    {
+        let wanted: Arc<Mutex<Vec<TileId>>> = Arc::new(Mutex::new(Vec::new()));
+        let previous = self.replace_tile_loader(Some(TileLoader::new(zoom, {
+            let wanted = wanted.clone();
+            move |_, tile| {
+                wanted
+                    .lock()
+                    .map_err(|err| anyhow::anyhow!("Failed to lock mutex: {}", err))?
+                    .push(tile);
+                Ok(())
+            }
+        })))?;
+
+        let result = async {
+            loop {
+                let result = search(self);
+                let tiles = std::mem::take(
+                    &mut *wanted
+                        .lock()
+                        .map_err(|err| anyhow::anyhow!("Failed to lock mutex: {}", err))?,
+                );
+                if tiles.is_empty() {
+                    return Ok(result);
+                }
+                for tile in tiles {
+                    if let Some(bytes) = source.fetch_tile(tile.x, tile.y, tile.z).await? {
+                        self.ingest_tile(tile.x, tile.y, tile.z, bytes.roads, bytes.intersections)?;
+                    }
+                }
+            }
+        }
+        .await;
+
+        self.replace_tile_loader(previous)?;
+        result
+    }
+
+    fn replace_tile_loader(
+        &self,
+        loader: Option<TileLoader>,
+    ) -> anyhow::Result<Option<TileLoader>> {
+        Ok(std::mem::replace(
+            &mut *self
+                .tile_loader
+                .lock()
+                .map_err(|err| anyhow::anyhow!("Failed to lock mutex: {}", err))?,
+            loader,
+        ))
+    }
+
+    /// Loads every tile a way's geometry reaches that hasn't been loaded or requested yet, if
+    /// the graph has a tile source. Searches call this on each way they reach.
+    pub(super) fn load_tiles_along(&self, way: &WayId) {
+        let pending = {
+            let mut loader = if let Ok(loader) = self.tile_loader.lock() {
+                loader
+            } else {
+                return;
+            };
+            let loader = if let Some(loader) = loader.as_mut() {
+                loader
+            } else {
+                return;
+            };
+            if !loader.checked_ways.insert(*way) {
+                return;
+            }
+            let tiles: HashSet<TileId> = self
+                .geometry_read
+                .get(way)
+                .iter()
+                .flatten()
+                .flat_map(|geometry| geometry.polyline.iter())
+                .flat_map(|coordinates| tiles_near(&coordinates.to_lat_lng(), loader.zoom))
+                .collect();
+            loader.request(self, tiles)
+        };
+        self.load_pending(pending);
+    }
+
+    /// Hands requested tiles to the loader's `load`, with the loader unlocked.
+    fn load_pending(&self, pending: PendingTiles) {
+        if pending.tiles.is_empty() {
+            return;
+        }
+        for tile in pending.tiles {
+            if let Err(err) = (pending.load)(self, tile) {
+                tracing::warn!("Failed to load tile {:?}: {}", tile, err);
+            }
+        }
+        if let Ok(mut loader) = self.tile_loader.lock()
+            && let Some(loader) = loader.as_mut()
+        {
+            loader.checked_ways.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashMap,
+        pin::pin,
+        sync::{Arc, Mutex},
+        task::{Context, Poll, Waker},
+    };
+
+    use crate::costing::{pedestrian::pedestrian_costing_model, units::ElapsedTime};
+
+    use super::super::{Graph, WayId};
+    use super::{AsyncTileSource, TileBytes, TileLoader, TileSource, tiles_near};
+
+    const FREMONT: (u32, u32, u32) = (2623, 5718, 14);
+
+    /// An in-memory source holding just the Fremont tile, that records what it's asked for.
+    #[derive(Clone, Default)]
+    struct RecordingSource {
+        requests: Arc<Mutex<Vec<(u32, u32, u32)>>>,
+    }
+
+    impl TileSource for RecordingSource {
+        fn fetch_tile(&self, x: u32, y: u32, z: u32) -> anyhow::Result<Option<TileBytes>> {
+            self.requests.lock().unwrap().push((x, y, z));
+            let tiles = HashMap::from([(
+                FREMONT,
+                TileBytes::new(
+                    include_bytes!("../../testdata/tile2.pbf").to_vec(),
+                    include_bytes!("../../testdata/tile2.pbf").to_vec(),
+                ),
+            )]);
+            tiles.fetch_tile(x, y, z)
+        }
+    }
+
+    impl AsyncTileSource for RecordingSource {
+        async fn fetch_tile(&self, x: u32, y: u32, z: u32) -> anyhow::Result<Option<TileBytes>> {
+            TileSource::fetch_tile(self, x, y, z)
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = pin!(future);
+        let mut context = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+                return output;
+            }
+        }
+    }
+
+    fn fremont_graph() -> Graph {
+        let graph = Graph::new();
+        graph
+            .ingest_tile(
+                FREMONT.0,
+                FREMONT.1,
+                FREMONT.2,
+                include_bytes!("../../testdata/tile2.pbf").to_vec(),
+                include_bytes!("../../testdata/tile2.pbf").to_vec(),
+            )
+            .expect("Failed to ingest tile");
+        graph
+    }
+
+    fn fremont_route(graph: &Graph) -> Option<f64> {
+        let costing_model = pedestrian_costing_model(1.4);
+        graph
+            .search_djikstra(&costing_model, WayId(671949014), 0, WayId(980366562), 0)
+            .map(|result| result.route_cost_seconds())
+    }
+
+    #[test]
+    fn tile_source_loads_tiles_as_reached() {
+        let ingested = Graph::new();
+        ingested
+            .ingest_tile(
+                FREMONT.0,
+                FREMONT.1,
+                FREMONT.2,
+                include_bytes!("../../testdata/tile2.pbf").to_vec(),
+                include_bytes!("../../testdata/tile2.pbf").to_vec(),
+            )
+            .expect("Failed to ingest tile");
+        let expected = fremont_route(&ingested).expect("Couldn't find a route.");
+        let start = ingested.get_polyline(&WayId(671949014)).unwrap().0[0];
+
+        let source = RecordingSource::default();
+        let graph = Graph::new();
+        graph
+            .set_tile_source(14, source.clone())
+            .expect("Failed to set tile source");
+        assert_eq!(fremont_route(&graph), None);
+        graph.load_tile_at(&start).expect("Failed to load tile");
+        assert_eq!(fremont_route(&graph), Some(expected));
+
+        // Expanding across the whole tile reaches its edges, so the tiles around it are asked
+        // for, but nothing is asked for twice.
+        graph.search_isochrone(
+            &pedestrian_costing_model(1.4),
+            WayId(671949014),
+            0,
+            ElapsedTime::from_seconds(7200.0),
+        );
+        let requests = source.requests.lock().unwrap().clone();
+        assert!(requests.contains(&FREMONT));
+        assert!(requests.iter().any(|tile| *tile != FREMONT));
+        for tile in &requests {
+            assert_eq!(requests.iter().filter(|other| *other == tile).count(), 1);
+            assert!(tile.0.abs_diff(FREMONT.0) <= 1 && tile.1.abs_diff(FREMONT.1) <= 1);
+        }
+
+        let async_graph = Graph::new();
+        let result = block_on(async_graph.search_with_async_tile_source(
+            14,
+            &RecordingSource::default(),
+            |graph| {
+                graph.load_tile_at(&start).expect("Failed to load tile");
+                fremont_route(graph)
+            },
+        ))
+        .expect("Failed to search");
+        assert_eq!(result, Some(expected));
+    }
+
+    #[test]
+    fn tile_loading_leaves_the_loader_unlocked() {
+        let expected = fremont_route(&fremont_graph()).expect("Couldn't find a route.");
+        let start = fremont_graph().get_polyline(&WayId(671949014)).unwrap().0[0];
+
+        // Searching while a tile is loaded needs the loader, as would any other search running
+        // at the same time.
+        let routes = Arc::new(Mutex::new(Vec::new()));
+        let graph = Graph::new();
+        graph
+            .replace_tile_loader(Some(TileLoader::new(14, {
+                let routes = routes.clone();
+                move |graph, tile| {
+                    if (tile.x, tile.y, tile.z) == FREMONT {
+                        graph.ingest_tile(
+                            tile.x,
+                            tile.y,
+                            tile.z,
+                            include_bytes!("../../testdata/tile2.pbf").to_vec(),
+                            include_bytes!("../../testdata/tile2.pbf").to_vec(),
+                        )?;
+                        routes.lock().unwrap().push(fremont_route(graph));
+                    }
+                    Ok(())
+                }
+            })))
+            .expect("Failed to set tile loader");
+        graph.load_tile_at(&start).expect("Failed to load tile");
+        assert_eq!(*routes.lock().unwrap(), vec![Some(expected)]);
+    }
+
+    #[test]
+    fn contracted_search_falls_back_when_tiles_load() {
+        let costing_model = pedestrian_costing_model(1.4);
+        let end = WayId(980366562);
+
+        // A way reaching the next tile over, so starting from it loads that tile.
+        let ingested = fremont_graph();
+        let mut ways: Vec<WayId> = ingested
+            .geometry_read
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(way, _)| *way)
+            .collect();
+        ways.sort();
+        let (start, neighbour) = ways
+            .into_iter()
+            .filter(|way| {
+                ingested
+                    .search_djikstra(&costing_model, *way, 0, end, 0)
+                    .is_some()
+            })
+            .find_map(|way| {
+                let neighbour = ingested
+                    .get_polyline(&way)?
+                    .0
+                    .iter()
+                    .flat_map(|coord| tiles_near(coord, 14))
+                    .map(|tile| (tile.x, tile.y, tile.z))
+                    .find(|tile| *tile != FREMONT)?;
+                Some((way, neighbour))
+            })
+            .expect("No way reaches the next tile");
+
+        // The hierarchy is prepared before the Fremont tile's intersections are known, and they
+        // arrive once the search reaches the next tile over.
+        let graph = Graph::new();
+        graph
+            .ingest_tile(
+                FREMONT.0,
+                FREMONT.1,
+                FREMONT.2,
+                include_bytes!("../../testdata/tile2.pbf").to_vec(),
+                Vec::new(),
+            )
+            .expect("Failed to ingest tile");
+        graph
+            .prepare_contraction_hierarchy(&costing_model)
+            .expect("Failed to prepare contraction hierarchy");
+        graph
+            .replace_tile_loader(Some(TileLoader::new(14, move |graph, tile| {
+                if (tile.x, tile.y, tile.z) == neighbour {
+                    graph.ingest_tile(
+                        FREMONT.0,
+                        FREMONT.1,
+                        FREMONT.2,
+                        include_bytes!("../../testdata/tile2.pbf").to_vec(),
+                        include_bytes!("../../testdata/tile2.pbf").to_vec(),
+                    )?;
+                }
+                Ok(())
+            })))
+            .expect("Failed to set tile loader");
+        let contracted = graph
+            .search_contracted(&costing_model, start, 0, end, 0)
+            .expect("Couldn't find a route.");
+        assert!(!graph.has_contraction_hierarchy(&costing_model));
+        let djikstra = graph
+            .search_djikstra(&costing_model, start, 0, end, 0)
+            .expect("Couldn't find a route.");
+        assert_eq!(contracted.cost, djikstra.cost);
+    }
+}
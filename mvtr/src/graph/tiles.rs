@@ -0,0 +1,325 @@
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hash,
+    sync::{Arc, Mutex},
+};
+
+use evmap::ShallowCopy;
+
+use crate::costing::Tags;
+
+use super::{
+    Graph, SearchNode, WayId, WayTransition,
+    stitching::{self, WayFragment, WayGeometry},
+};
+
+/// A tile's coordinates, as passed to `ingest_tile`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(super) struct TileId {
+    pub(super) x: u32,
+    pub(super) y: u32,
+    pub(super) z: u32,
+}
+
+/// Everything a single tile added to each of the graph's maps, by key, so the tile can be taken
+/// out again without disturbing what other tiles added under the same keys.
+#[derive(Default)]
+pub(super) struct TileContents {
+    pub(super) ways: HashMap<WayId, Vec<Arc<Tags>>>,
+    pub(super) geometry: HashMap<WayId, Vec<WayFragment>>,
+    pub(super) street_names: HashMap<WayId, Vec<String>>,
+    pub(super) nodes: HashMap<WayId, Vec<SearchNode>>,
+    /// Every way transition at each intersection, with the intersection's tags.
+    pub(super) transitions: HashMap<SearchNode, Vec<(WayTransition, Arc<Tags>)>>,
+    reverse_transitions: HashMap<SearchNode, Vec<WayTransition>>,
+    landings: HashMap<WayId, Vec<SearchNode>>,
+}
+
+impl TileContents {
+    pub(super) fn insert_way(&mut self, way: WayId, tags: Arc<Tags>) {
+        self.ways.entry(way).or_default().push(tags);
+    }
+
+    pub(super) fn insert_geometry(&mut self, way: WayId, fragment: WayFragment) {
+        self.geometry.entry(way).or_default().push(fragment);
+    }
+
+    pub(super) fn insert_street_name(&mut self, way: WayId, street_name: String) {
+        self.street_names.entry(way).or_default().push(street_name);
+    }
+
+    pub(super) fn insert_node(&mut self, node: SearchNode) {
+        self.nodes.entry(node.way).or_default().push(node);
+        // Continuing along the way through an intersection lands on the intersection itself.
+        self.landings.entry(node.way).or_default().push(node);
+    }
+
+    pub(super) fn insert_transition(
+        &mut self,
+        way_transition: WayTransition,
+        intersection_tags: Arc<Tags>,
+    ) {
+        let search_node = SearchNode {
+            way: way_transition.from_way_id,
+            distance_along_way_mm: way_transition.distance_along_way_mm,
+        };
+        let landing = SearchNode {
+            way: way_transition.to_way_id,
+            distance_along_way_mm: way_transition.transition_to_distance_along_way_mm,
+        };
+        self.transitions
+            .entry(search_node)
+            .or_default()
+            .push((way_transition, intersection_tags));
+        self.reverse_transitions
+            .entry(landing)
+            .or_default()
+            .push(way_transition);
+        self.landings.entry(landing.way).or_default().push(landing);
+    }
+}
+
+/// Rewrites every key of one of the graph's maps that the `changed` tiles' old or current contents
+/// touch, with the values every loaded tile has for it, and makes the result visible to readers.
+fn sync_map<K, V>(
+    write: &Mutex<evmap::WriteHandle<K, V>>,
+    tiles: &HashMap<TileId, TileContents>,
+    changed: &[TileId],
+    old: &[TileContents],
+    field: impl Fn(&TileContents) -> &HashMap<K, Vec<V>>,
+) -> anyhow::Result<HashSet<K>>
+where
+    K: Eq + Hash + Clone,
+    V: Eq + Hash + Clone + ShallowCopy,
+{
+    let keys = changed_keys(tiles, changed, old, &field);
+    // One pass over every tile, rather than one per changed tile, so loading many tiles at once
+    // doesn't take time quadratic in their number.
+    let mut values: HashMap<&K, Vec<&V>> = HashMap::new();
+    for contents in tiles.values() {
+        for (key, key_values) in field(contents) {
+            if keys.contains(key) {
+                values.entry(key).or_default().extend(key_values);
+            }
+        }
+    }
+    let mut write = write
+        .lock()
+        .map_err(|err| anyhow::anyhow!("Failed to lock mutex: {}", err))?;
+    for key in &keys {
+        write.empty(key.clone());
+        for value in values.remove(key).into_iter().flatten() {
+            write.insert(key.clone(), value.clone());
+        }
+    }
+    write.refresh();
+    Ok(keys)
+}
+
+fn changed_keys<K: Eq + Hash + Clone, V>(
+    tiles: &HashMap<TileId, TileContents>,
+    changed: &[TileId],
+    old: &[TileContents],
+    field: impl Fn(&TileContents) -> &HashMap<K, Vec<V>>,
+) -> HashSet<K> {
+    old.iter()
+        .chain(changed.iter().filter_map(|tile| tiles.get(tile)))
+        .flat_map(|contents| field(contents).keys().cloned())
+        .collect()
+}
+
+/// Like `sync_map`, but stitches the fragments every loaded tile has of each way the tiles touch
+/// into a single geometry.
+fn sync_geometry(
+    write: &Mutex<evmap::WriteHandle<WayId, WayGeometry>>,
+    tiles: &HashMap<TileId, TileContents>,
+    changed: &[TileId],
+    old: &[TileContents],
+) -> anyhow::Result<HashSet<WayId>> {
+    let ways = changed_keys(tiles, changed, old, |contents| &contents.geometry);
+    let mut fragments: HashMap<WayId, Vec<&WayFragment>> = HashMap::new();
+    for contents in tiles.values() {
+        for (way, way_fragments) in &contents.geometry {
+            if ways.contains(way) {
+                fragments.entry(*way).or_default().extend(way_fragments);
+            }
+        }
+    }
+    let mut write = write
+        .lock()
+        .map_err(|err| anyhow::anyhow!("Failed to lock mutex: {}", err))?;
+    for way in &ways {
+        write.empty(*way);
+        if let Some(geometry) = stitching::stitch(fragments.remove(way).into_iter().flatten()) {
+            write.insert(*way, geometry);
+        }
+    }
+    write.refresh();
+    Ok(ways)
+}
+
+impl Graph {
+    /// Removes everything a tile added to the graph. Ways and nodes other loaded tiles also have
+    /// are kept with whatever those tiles added for them.
+    pub fn remove_tile(&self, x: u32, y: u32, z: u32) -> anyhow::Result<()> {
+        self.replace_tiles(vec![(TileId { x, y, z }, None)])
+    }
+
+    /// Swaps out what each tile added to the graph for the contents given for it, or removes it if
+    /// `None`.
+    pub(super) fn replace_tiles(
+        &self,
+        changes: Vec<(TileId, Option<TileContents>)>,
+    ) -> anyhow::Result<()> {
+        let mut tiles = self
+            .tiles
+            .lock()
+            .map_err(|err| anyhow::anyhow!("Failed to lock mutex: {}", err))?;
+        let mut changed = Vec::new();
+        let mut old = Vec::new();
+        for (tile, contents) in changes {
+            changed.push(tile);
+            old.extend(match contents {
+                Some(contents) => tiles.insert(tile, contents),
+                None => tiles.remove(&tile),
+            });
+        }
+        let (changed, old) = (&changed[..], &old[..]);
+
+        // We want way tags to be available before the routing graph is because that way we can unwrap() costing access.
+        sync_map(&self.way_tags_write, &tiles, changed, old, |contents| {
+            &contents.ways
+        })?;
+        let geometry_ways = sync_geometry(&self.geometry_write, &tiles, changed, old)?;
+        sync_map(&self.street_names_write, &tiles, changed, old, |contents| {
+            &contents.street_names
+        })?;
+        sync_map(&self.transitions_write, &tiles, changed, old, |contents| {
+            &contents.transitions
+        })?;
+        sync_map(
+            &self.reverse_transitions_write,
+            &tiles,
+            changed,
+            old,
+            |contents| &contents.reverse_transitions,
+        )?;
+        sync_map(&self.landings_write, &tiles, changed, old, |contents| {
+            &contents.landings
+        })?;
+        sync_map(&self.nodes_write, &tiles, changed, old, |contents| {
+            &contents.nodes
+        })?;
+
+        {
+            let mut way_index = self
+                .way_index
+                .lock()
+                .map_err(|err| anyhow::anyhow!("Failed to lock mutex: {}", err))?;
+            let mut inserted = Vec::new();
+            for way in geometry_ways {
+                if let Some(polyline) = self.get_polyline(&way) {
+                    inserted.push((way, polyline));
+                } else {
+                    way_index.remove_way(&way);
+                }
+            }
+            way_index.insert_ways(inserted);
+        }
+        self.invalidate()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::costing::pedestrian::pedestrian_costing_model;
+
+    use super::super::{Graph, WayId};
+
+    /// How many keys and values each of the graph's maps holds.
+    fn map_sizes(graph: &Graph) -> Vec<(usize, usize)> {
+        fn size<K, V>(read: &evmap::ReadHandle<K, V>) -> (usize, usize)
+        where
+            K: Eq + std::hash::Hash,
+            V: Eq + std::hash::Hash,
+        {
+            let read = read.read().unwrap();
+            (
+                read.len(),
+                read.iter().map(|(_, values)| values.len()).sum(),
+            )
+        }
+        vec![
+            size(&graph.way_tags_read),
+            size(&graph.geometry_read),
+            size(&graph.street_names_read),
+            size(&graph.nodes_read),
+            size(&graph.transitions_read),
+            size(&graph.reverse_transitions_read),
+            size(&graph.landings_read),
+        ]
+    }
+
+    #[test]
+    fn reingest_and_remove_tiles() {
+        let costing_model = pedestrian_costing_model(1.4);
+        let graph = Graph::new();
+        let ingest_capitol_hill = || {
+            graph
+                .ingest_tile(
+                    2625,
+                    5721,
+                    14,
+                    include_bytes!("../../testdata/tile.pbf").to_vec(),
+                    include_bytes!("../../testdata/tile.pbf").to_vec(),
+                )
+                .expect("Failed to ingest tile");
+        };
+        let ingest_fremont = || {
+            graph
+                .ingest_tile(
+                    2623,
+                    5718,
+                    14,
+                    include_bytes!("../../testdata/tile2.pbf").to_vec(),
+                    include_bytes!("../../testdata/tile2.pbf").to_vec(),
+                )
+                .expect("Failed to ingest tile");
+        };
+        let fremont_route = |graph: &Graph| {
+            graph.search_djikstra(&costing_model, WayId(671949014), 0, WayId(980366562), 0)
+        };
+
+        ingest_fremont();
+        let fremont_sizes = map_sizes(&graph);
+        let route = fremont_route(&graph).expect("Couldn't find a route.");
+
+        ingest_fremont();
+        assert_eq!(map_sizes(&graph), fremont_sizes);
+        assert_eq!(fremont_route(&graph), Some(route.clone()));
+
+        ingest_capitol_hill();
+        assert_ne!(map_sizes(&graph), fremont_sizes);
+        graph
+            .remove_tile(2625, 5721, 14)
+            .expect("Failed to remove tile");
+        assert_eq!(map_sizes(&graph), fremont_sizes);
+        assert_eq!(fremont_route(&graph), Some(route));
+
+        graph
+            .remove_tile(2623, 5718, 14)
+            .expect("Failed to remove tile");
+        assert!(map_sizes(&graph).iter().all(|size| *size == (0, 0)));
+        assert_eq!(
+            graph.nearest_way(
+                &costing_model,
+                &geo::Coord {
+                    x: -122.35,
+                    y: 47.66
+                },
+            ),
+            None
+        );
+        assert_eq!(fremont_route(&graph), None);
+    }
+}
@@ -0,0 +1,228 @@
+use crate::costing::{CostingModel, RoutingCost, units::ElapsedTime};
+
+use super::{Costing, Frontier, Graph, RouteLeg, SearchNode, SearchResult, SearchState};
+
+/// A point a multi-waypoint route must visit, in order.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Waypoint {
+    coord: geo::Coord,
+    pass_through: bool,
+}
+
+impl Waypoint {
+    /// A waypoint where the route stops, ending one leg and starting the next.
+    pub fn stop(coord: geo::Coord) -> Waypoint {
+        Waypoint {
+            coord,
+            pass_through: false,
+        }
+    }
+
+    /// A waypoint the route passes through without stopping, to shape it.
+    pub fn pass_through(coord: geo::Coord) -> Waypoint {
+        Waypoint {
+            coord,
+            pass_through: true,
+        }
+    }
+}
+
+impl Graph {
+    /// Searches for a route visiting each waypoint in order, snapping each to its nearest way. The
+    /// first and last waypoints are always stops.
+    pub fn route_via(
+        &self,
+        costing_model: &dyn CostingModel,
+        waypoints: &[Waypoint],
+    ) -> Option<SearchResult> {
+        if waypoints.len() < 2 {
+            return None;
+        }
+        let costing = self.costing(costing_model).ok()?;
+        let snapped: Vec<SearchNode> = waypoints
+            .iter()
+            .map(|waypoint| {
+                let (way, distance_along_way_mm) =
+                    self.nearest_way(costing_model, &waypoint.coord)?;
+                Some(SearchNode {
+                    way,
+                    distance_along_way_mm,
+                })
+            })
+            .collect::<Option<_>>()?;
+
+        let (states, leg_ends) = self.search_chained(&costing, &snapped)?;
+        let mut stops = vec![0];
+        for (idx, leg_end) in leg_ends.iter().enumerate() {
+            let is_last = idx + 2 == snapped.len();
+            if is_last || !waypoints[idx + 1].pass_through {
+                stops.push(*leg_end);
+            }
+        }
+
+        let mut result = self.build_search_result(&states)?;
+        result.maneuvers = stops
+            .windows(2)
+            .flat_map(|leg| self.build_maneuvers(&states[leg[0]..=leg[1]]))
+            .collect();
+        result.legs = stops
+            .windows(2)
+            .map(|leg| RouteLeg {
+                cost: states[leg[1]].cost - states[leg[0]].cost,
+            })
+            .collect();
+        Some(result)
+    }
+
+    /// Searches from each node to the next in turn, chaining the states of every search into a
+    /// single route. Also returns the index of the state each search finished at.
+    pub(super) fn search_chained(
+        &self,
+        costing: &Costing,
+        nodes: &[SearchNode],
+    ) -> Option<(Vec<SearchState>, Vec<usize>)> {
+        // Each search's states are chained onto the last, with costs running on from where it
+        // ended. Its first state is where the previous search finished, so it's dropped.
+        let mut states: Vec<SearchState> = Vec::new();
+        let mut search_ends = Vec::new();
+        for window in nodes.windows(2) {
+            let search_states = self.search_djikstra_inner(
+                costing,
+                window[0].way,
+                window[0].distance_along_way_mm,
+                window[1].way,
+                window[1].distance_along_way_mm,
+                Frontier::new(|_| ElapsedTime::zero()),
+            )?;
+            let offset = states
+                .last()
+                .map(|state| state.cost)
+                .unwrap_or(RoutingCost::zero());
+            let skip = if states.is_empty() { 0 } else { 1 };
+            for mut state in search_states.into_iter().skip(skip) {
+                state.previous = states.len().saturating_sub(1);
+                state.idx = states.len();
+                state.cost = offset + state.cost;
+                states.push(state);
+            }
+            search_ends.push(states.len() - 1);
+        }
+        Some((states, search_ends))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use geo::Coord;
+
+    use crate::costing::pedestrian::pedestrian_costing_model;
+
+    use super::super::{Graph, TurnType};
+    use super::Waypoint;
+
+    const START: Coord = Coord {
+        x: -122.3126740,
+        y: 47.6153470,
+    };
+    const MIDDLE: Coord = Coord {
+        x: -122.3128900,
+        y: 47.6160000,
+    };
+    const END: Coord = Coord {
+        x: -122.315503,
+        y: 47.6163794,
+    };
+
+    fn basic_graph() -> Graph {
+        let graph = Graph::new();
+        graph
+            .ingest_tile(
+                2625,
+                5721,
+                14,
+                include_bytes!("../../testdata/tile.pbf").to_vec(),
+                include_bytes!("../../testdata/tile.pbf").to_vec(),
+            )
+            .expect("Failed to ingest tile");
+        graph
+    }
+
+    fn search(graph: &Graph, from: &Coord, to: &Coord) -> f64 {
+        let costing_model = pedestrian_costing_model(1.4);
+        let (from_way_id, from_way_distance) = graph.nearest_way(&costing_model, from).unwrap();
+        let (to_way_id, to_way_distance) = graph.nearest_way(&costing_model, to).unwrap();
+        graph
+            .search_djikstra(
+                &costing_model,
+                from_way_id,
+                from_way_distance,
+                to_way_id,
+                to_way_distance,
+            )
+            .expect("Couldn't find a route.")
+            .route_cost_seconds()
+    }
+
+    #[test]
+    fn route_via_stop() {
+        let costing_model = pedestrian_costing_model(1.4);
+        let graph = basic_graph();
+        let route = graph
+            .route_via(
+                &costing_model,
+                &[
+                    Waypoint::stop(START),
+                    Waypoint::stop(MIDDLE),
+                    Waypoint::stop(END),
+                ],
+            )
+            .expect("Couldn't find a route.");
+
+        let legs = route.legs();
+        assert_eq!(legs.len(), 2);
+        assert_eq!(legs[0].cost_seconds(), search(&graph, &START, &MIDDLE));
+        assert_eq!(legs[1].cost_seconds(), search(&graph, &MIDDLE, &END));
+        assert!(
+            (route.route_cost_seconds() - legs[0].cost_seconds() - legs[1].cost_seconds()).abs()
+                < 0.001
+        );
+        let arrivals = route
+            .maneuvers()
+            .iter()
+            .filter(|maneuver| maneuver.turn_type() == TurnType::Arrive)
+            .count();
+        assert_eq!(arrivals, 2);
+    }
+
+    #[test]
+    fn route_via_pass_through() {
+        let costing_model = pedestrian_costing_model(1.4);
+        let graph = basic_graph();
+        let route = graph
+            .route_via(
+                &costing_model,
+                &[
+                    Waypoint::stop(START),
+                    Waypoint::pass_through(MIDDLE),
+                    Waypoint::stop(END),
+                ],
+            )
+            .expect("Couldn't find a route.");
+
+        assert_eq!(route.legs().len(), 1);
+        assert!(
+            (route.route_cost_seconds()
+                - search(&graph, &START, &MIDDLE)
+                - search(&graph, &MIDDLE, &END))
+            .abs()
+                < 0.001
+        );
+        let maneuvers = route.maneuvers();
+        assert_eq!(maneuvers.first().unwrap().turn_type(), TurnType::Depart);
+        assert!(
+            maneuvers[1..]
+                .iter()
+                .all(|maneuver| maneuver.turn_type() != TurnType::Depart)
+        );
+    }
+}
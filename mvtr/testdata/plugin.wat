@@ -0,0 +1,150 @@
+;; A walking costing plugin for `PluginCostingModel`, written against ABI version 1 as a plugin
+;; author would. Motorways are impassable, everything else is walked at 1.4 m/s, ways other than
+;; footways are a little less pleasant, and steps can't be turned onto.
+(module
+  (memory (export "memory") 1)
+  (global $heap (mut i32) (i32.const 1024))
+
+  (data (i32.const 0) "highway")
+  (data (i32.const 16) "motorway")
+  (data (i32.const 32) "footway")
+  (data (i32.const 48) "steps")
+  (data (i32.const 64) "crossing")
+
+  (func (export "abi_version") (result i32)
+    (i32.const 1))
+
+  ;; A bump allocator. Every call gets a fresh instance, so nothing is ever freed.
+  (func $alloc (export "alloc") (param $size i32) (result i32)
+    (local $ptr i32)
+    (local.set $ptr (global.get $heap))
+    (global.set $heap
+      (i32.and (i32.add (i32.add (local.get $ptr) (local.get $size)) (i32.const 7))
+               (i32.const -8)))
+    (if (i32.gt_u (global.get $heap) (i32.mul (memory.size) (i32.const 65536)))
+      (then
+        (if (i32.eq
+              (memory.grow
+                (i32.add (i32.shr_u (i32.sub (global.get $heap)
+                                             (i32.mul (memory.size) (i32.const 65536)))
+                                    (i32.const 16))
+                         (i32.const 1)))
+              (i32.const -1))
+          (then unreachable))))
+    (local.get $ptr))
+
+  (func $bytes_eq (param $a i32) (param $a_len i32) (param $b i32) (param $b_len i32)
+                  (result i32)
+    (local $i i32)
+    (if (i32.ne (local.get $a_len) (local.get $b_len))
+      (then (return (i32.const 0))))
+    (block $done
+      (loop $next
+        (br_if $done (i32.ge_u (local.get $i) (local.get $a_len)))
+        (if (i32.ne (i32.load8_u (i32.add (local.get $a) (local.get $i)))
+                    (i32.load8_u (i32.add (local.get $b) (local.get $i))))
+          (then (return (i32.const 0))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next)))
+    (i32.const 1))
+
+  ;; Where the tags block at `$ptr` ends.
+  (func $skip_tags (param $ptr i32) (result i32)
+    (local $count i32)
+    (local.set $count (i32.load (local.get $ptr)))
+    (local.set $ptr (i32.add (local.get $ptr) (i32.const 4)))
+    (block $done
+      (loop $next
+        (br_if $done (i32.eqz (local.get $count)))
+        ;; The key, then the value.
+        (local.set $ptr (i32.add (i32.add (local.get $ptr) (i32.const 4))
+                                 (i32.load (local.get $ptr))))
+        (local.set $ptr (i32.add (i32.add (local.get $ptr) (i32.const 4))
+                                 (i32.load (local.get $ptr))))
+        (local.set $count (i32.sub (local.get $count) (i32.const 1)))
+        (br $next)))
+    (local.get $ptr))
+
+  ;; Whether the tags block at `$ptr` has `$key` set to `$value`.
+  (func $tag_is (param $ptr i32) (param $key i32) (param $key_len i32)
+                (param $value i32) (param $value_len i32) (result i32)
+    (local $count i32)
+    (local $tag_key i32)
+    (local $tag_key_len i32)
+    (local $tag_value i32)
+    (local $tag_value_len i32)
+    (local.set $count (i32.load (local.get $ptr)))
+    (local.set $ptr (i32.add (local.get $ptr) (i32.const 4)))
+    (block $done
+      (loop $next
+        (br_if $done (i32.eqz (local.get $count)))
+        (local.set $tag_key_len (i32.load (local.get $ptr)))
+        (local.set $tag_key (i32.add (local.get $ptr) (i32.const 4)))
+        (local.set $ptr (i32.add (local.get $tag_key) (local.get $tag_key_len)))
+        (local.set $tag_value_len (i32.load (local.get $ptr)))
+        (local.set $tag_value (i32.add (local.get $ptr) (i32.const 4)))
+        (local.set $ptr (i32.add (local.get $tag_value) (local.get $tag_value_len)))
+        (if (call $bytes_eq (local.get $tag_key) (local.get $tag_key_len)
+                            (local.get $key) (local.get $key_len))
+          (then
+            (return (call $bytes_eq (local.get $tag_value) (local.get $tag_value_len)
+                                    (local.get $value) (local.get $value_len)))))
+        (local.set $count (i32.sub (local.get $count) (i32.const 1)))
+        (br $next)))
+    (i32.const 0))
+
+  (func (export "cost_way") (param $ptr i32) (param $len i32) (result i32)
+    (local $out i32)
+    (local $speed f64)
+    (local $penalty f64)
+    (local.set $out (call $alloc (i32.const 32)))
+    (local.set $speed (f64.const 1.4))
+    (if (call $tag_is (local.get $ptr) (i32.const 0) (i32.const 7) (i32.const 16) (i32.const 8))
+      (then (local.set $speed (f64.const 0))))
+    (local.set $penalty (f64.const 0.05))
+    (if (call $tag_is (local.get $ptr) (i32.const 0) (i32.const 7) (i32.const 32) (i32.const 7))
+      (then (local.set $penalty (f64.const 0))))
+    (f64.store (local.get $out) (local.get $speed))
+    (f64.store offset=8 (local.get $out) (local.get $speed))
+    (f64.store offset=16 (local.get $out) (local.get $penalty))
+    (f64.store offset=24 (local.get $out) (local.get $penalty))
+    (local.get $out))
+
+  (func (export "cost_intersection") (param $ptr i32) (param $len i32) (result i32)
+    (local $count i32)
+    (local $costed i32)
+    (local $out i32)
+    (local $to_way_id i64)
+    (local $to_way_tags i32)
+    (local $penalty f64)
+    ;; The current way's tags don't matter here.
+    (local.set $ptr (call $skip_tags (local.get $ptr)))
+    (local.set $count (i32.load (local.get $ptr)))
+    (local.set $ptr (i32.add (local.get $ptr) (i32.const 4)))
+    (local.set $out (call $alloc (i32.add (i32.const 12)
+                                          (i32.mul (local.get $count) (i32.const 16)))))
+    (f64.store (local.get $out) (f64.const 0))
+    (block $done
+      (loop $next
+        (br_if $done (i32.eqz (local.get $count)))
+        (local.set $to_way_id (i64.load offset=8 (local.get $ptr)))
+        (local.set $to_way_tags (call $skip_tags (i32.add (local.get $ptr) (i32.const 16))))
+        (local.set $ptr (call $skip_tags (call $skip_tags (local.get $to_way_tags))))
+        (if (i32.eqz (call $tag_is (local.get $to_way_tags)
+                                   (i32.const 0) (i32.const 7) (i32.const 48) (i32.const 5)))
+          (then
+            (local.set $penalty (f64.const 0))
+            (if (call $tag_is (local.get $to_way_tags)
+                              (i32.const 32) (i32.const 7) (i32.const 64) (i32.const 8))
+              (then (local.set $penalty (f64.const 10))))
+            (i64.store offset=12
+              (i32.add (local.get $out) (i32.mul (local.get $costed) (i32.const 16)))
+              (local.get $to_way_id))
+            (f64.store offset=20
+              (i32.add (local.get $out) (i32.mul (local.get $costed) (i32.const 16)))
+              (local.get $penalty))
+            (local.set $costed (i32.add (local.get $costed) (i32.const 1)))))
+        (local.set $count (i32.sub (local.get $count) (i32.const 1)))
+        (br $next)))
+    (i32.store offset=8 (local.get $out) (local.get $costed))
+    (local.get $out)))